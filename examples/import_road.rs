@@ -0,0 +1,60 @@
+//! Converts external curve data to a road network JSON file.
+//!
+//! Usage:
+//!
+//! ```text
+//! cargo run --example import_road -- track.gpx [--banked] > assets/road_network.json
+//! ```
+//!
+//! Supported inputs: `.csv`, `.json` (control points), `.svg` (first path, flat) and `.gpx`.
+
+use std::{env, fs::read_to_string, process::exit};
+
+use osd::road_import::*;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let path = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(path) => path,
+        _ => {
+            eprintln!("usage: import_road <file.csv|file.json|file.svg|file.gpx> [--banked]");
+            exit(1);
+        }
+    };
+
+    let up_mode = if args.iter().any(|arg| arg == "--banked") {
+        UpMode::Banked { factor: 20.0, max_angle: 35f32.to_radians() }
+    } else {
+        UpMode::Level
+    };
+
+    let text = read_to_string(path).unwrap();
+    let extension = path.rsplit('.').next().unwrap_or("").to_lowercase();
+
+    let result = match extension.as_str() {
+        "csv" => import_csv(&text, up_mode),
+        "json" => import_json(&text, up_mode),
+        "gpx" => import_gpx(&text, 2.0, up_mode),
+        "svg" => match svg_first_path_data(&text) {
+            Some(d) => import_svg_path(d, 1.0, &HeightProfile::flat(), up_mode),
+            None => Err(String::from("no <path d=\"...\"> found")),
+        },
+        _ => Err(format!("unknown file type '{}'", extension)),
+    };
+
+    match result {
+        Ok(road_network) => println!("{}", serde_json::to_string_pretty(&road_network).unwrap()),
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            exit(1);
+        }
+    }
+}
+
+fn svg_first_path_data(svg: &str) -> Option<&str> {
+    let path_start = svg.find("<path")?;
+    let path = &svg[path_start..];
+    let d_start = path.find(" d=\"")? + 4;
+    let d_end = path[d_start..].find('"')?;
+    return Some(&path[d_start..d_start + d_end]);
+}
@@ -0,0 +1,346 @@
+//! AI vehicles driving along the road network.
+//!
+//! AI cars are regular hover vehicles: `RoadFollower` pulls them onto the
+//! road like every other vehicle (see `road_physics_system`), and an
+//! `AiDriver` gives them the driving input a player would. It steers towards
+//! a point `lookahead` meters further along the segment chain, holds the
+//! target speed of the segments it drives on (`Segment::target_speed`), and
+//! slows down or swerves for vehicles in the way.
+//!
+//! There are no AI cars by default, `--ai [count]` adds some.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    drive_input::DriveInput,
+    game::Game,
+    hover::{apply_drive_input, hover_thruster_system, HoverVehicle},
+    road_network_builder::RoadPath,
+    simulation::{add_simulation_system, SimulationStage},
+    traffic::TrafficVehicle,
+    vehicle::{spawn_player_vehicle_system, spawn_vehicle, Vehicle, VehicleRoster},
+};
+
+/// AI cars with `--ai` and no count.
+pub const DEFAULT_AI_VEHICLES: usize = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AiSettings {
+    /// Speed on segments without a `target_speed`, in m/s.
+    pub target_speed: f32,
+    /// Distance along the road to the point the car steers towards.
+    pub lookahead: f32,
+    /// Extra lookahead per m/s of speed, so fast cars take wider lines.
+    pub lookahead_time: f32,
+    /// Steering per radian between the car heading and the lookahead point.
+    pub steering_gain: f32,
+    /// Steering against the turn rate, per rad/s.
+    pub steering_damping: f32,
+    /// Throttle per m/s below the target speed.
+    pub throttle_gain: f32,
+    /// Vehicles closer than this ahead are avoided.
+    pub avoid_distance: f32,
+    /// Vehicles further than this to the side are not in the way.
+    pub avoid_width: f32,
+    /// Gap kept to a vehicle ahead that can't be passed.
+    pub min_gap: f32,
+}
+
+impl Default for AiSettings {
+    fn default() -> Self {
+        AiSettings {
+            target_speed: 25.0,
+            lookahead: 15.0,
+            lookahead_time: 0.5,
+            steering_gain: 2.0,
+            steering_damping: 0.5,
+            throttle_gain: 0.2,
+            avoid_distance: 40.0,
+            avoid_width: 4.0,
+            min_gap: 10.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AiConfig {
+    /// Vehicle the AI cars are spawned with.
+    pub vehicle: String,
+    /// AI cars spawned on the road once it is loaded. None by default.
+    pub count: usize,
+    /// Distance along the road between spawned cars, from the start of the road.
+    pub spacing: f32,
+    pub settings: AiSettings,
+}
+
+impl Default for AiConfig {
+    fn default() -> Self {
+        AiConfig {
+            vehicle: String::from("car_0002"),
+            count: 0,
+            spacing: 40.0,
+            settings: AiSettings::default(),
+        }
+    }
+}
+
+impl AiConfig {
+    /// AI cars given with `--ai [count]`, none otherwise.
+    pub fn from_args() -> Self {
+        #[allow(unused_mut)]
+        let mut config = AiConfig::default();
+
+        // This is only for native builds
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let args: Vec<String> = std::env::args().collect();
+            if let Some(index) = args.iter().position(|arg| arg == "--ai") {
+                config.count = match args.get(index + 1).filter(|arg| !arg.starts_with("--")) {
+                    Some(value) => match value.parse::<usize>() {
+                        Ok(value) => value,
+                        _ => {
+                            error!("--ai expects a number of cars");
+                            DEFAULT_AI_VEHICLES
+                        }
+                    },
+                    _ => DEFAULT_AI_VEHICLES,
+                };
+            }
+        }
+
+        return config;
+    }
+}
+
+/// Requires `SimulationPlugin` and `VehiclePlugin`.
+#[derive(Default)]
+pub struct AiPlugin {
+    pub config: AiConfig,
+}
+
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.config.clone())
+            .init_resource::<Game>()
+            .init_resource::<AiVehicles>();
+
+        add_simulation_system(app, SimulationStage::Vehicles, spawn_ai_vehicles_system.after(spawn_player_vehicle_system));
+        add_simulation_system(app, SimulationStage::VehicleForces, ai_drive_system.after(hover_thruster_system));
+    }
+}
+
+/// Drives the vehicle it is attached to.
+#[derive(Component, Debug, Clone, Default)]
+pub struct AiDriver {
+    pub settings: AiSettings,
+    /// Input given on the last tick.
+    pub drive: DriveInput,
+    /// Vehicle in the way on the last tick.
+    pub avoiding: Option<Entity>,
+}
+
+impl AiDriver {
+    pub fn new(settings: AiSettings) -> Self {
+        return AiDriver {
+            settings,
+            ..default()
+        };
+    }
+}
+
+/// AI cars spawned by `AiPlugin`.
+#[derive(Default, Debug)]
+pub struct AiVehicles {
+    pub entities: Vec<Entity>,
+    spawned: bool,
+}
+
+/// A vehicle an AI car may run into.
+#[derive(Debug, Clone, Copy)]
+pub struct Obstacle {
+    pub entity: Entity,
+    pub position: Vec3,
+    pub velocity: Vec3,
+}
+
+/// Obstacles in a grid of cubes, to find the ones near a car without going through all of them.
+pub struct ObstacleGrid {
+    cell_size: f32,
+    cells: HashMap<IVec3, Vec<Obstacle>>,
+}
+
+impl ObstacleGrid {
+    pub fn new(obstacles: impl IntoIterator<Item = Obstacle>, cell_size: f32) -> Self {
+        let mut grid = ObstacleGrid {
+            cell_size: cell_size.max(1.0),
+            cells: HashMap::new(),
+        };
+        for obstacle in obstacles {
+            grid.cells.entry(grid.cell(obstacle.position)).or_default().push(obstacle);
+        }
+        return grid;
+    }
+
+    fn cell(&self, position: Vec3) -> IVec3 {
+        return (position / self.cell_size).floor().as_ivec3();
+    }
+
+    /// Obstacles in the cells within `radius` of `position`, and maybe a bit further.
+    pub fn near(&self, position: Vec3, radius: f32) -> impl Iterator<Item = &Obstacle> {
+        let (min, max) = (self.cell(position - Vec3::splat(radius)), self.cell(position + Vec3::splat(radius)));
+        return (min.x..=max.x)
+            .flat_map(move |x| (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z))))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten();
+    }
+}
+
+/// Driving input of an AI car at `transform`, and the vehicle it avoids.
+pub fn ai_drive_input<'a>(
+    settings: &AiSettings,
+    path: &RoadPath,
+    transform: &Transform,
+    velocity: &Velocity,
+    obstacles: impl IntoIterator<Item = &'a Obstacle>,
+) -> (DriveInput, Option<Entity>) {
+    let position = transform.translation;
+    let (forward, right, up) = (transform.forward(), transform.right(), transform.up());
+    let forward_speed = velocity.linvel.dot(forward);
+
+    let along = match path.distance_along(position) {
+        Some((along, _)) => along,
+        _ => {
+            return (DriveInput::default(), None);
+        }
+    };
+    let lookahead = settings.lookahead + settings.lookahead_time * forward_speed.max(0.0);
+    let ahead = path.wrap(along + lookahead);
+    let (mut target, _, road_up) = match path.point_at(ahead) {
+        Some(point) => point,
+        _ => {
+            return (DriveInput::default(), None);
+        }
+    };
+    // Cars hover above the road
+    target += road_up * (position - target).dot(road_up).max(0.0);
+
+    // Slow down before slower segments
+    let target_speed_at = |distance: f32| path.target_speed_at(distance).unwrap_or(settings.target_speed);
+    let mut target_speed = target_speed_at(along).min(target_speed_at(ahead));
+    if !path.closed {
+        // Stop at the end of the road
+        target_speed = target_speed.min((path.length - along).max(0.0) * 0.5);
+    }
+
+    // Closest vehicle in the way
+    let blocking = obstacles
+        .into_iter()
+        .map(|obstacle| {
+            let offset = obstacle.position - position;
+            return (obstacle, offset.dot(forward), offset.dot(right));
+        })
+        .filter(|(_, ahead, lateral)| *ahead > 0.0 && *ahead < settings.avoid_distance && lateral.abs() < settings.avoid_width)
+        .min_by(|(_, a, _), (_, b, _)| a.total_cmp(b));
+
+    let avoiding = match blocking {
+        Some((obstacle, distance, lateral)) => {
+            // Follow at its speed, closing the gap slowly
+            let obstacle_speed = obstacle.velocity.dot(forward);
+            let follow_speed = obstacle_speed + (distance - settings.min_gap) * 0.5;
+            target_speed = target_speed.min(follow_speed.max(0.0));
+
+            // And try to get around it, on the side it leaves the most room
+            let side = if lateral > 0.0 { -1.0 } else { 1.0 };
+            target += right * side * (settings.avoid_width - lateral.abs());
+            Some(obstacle.entity)
+        },
+        _ => None,
+    };
+
+    let to_target = target - position;
+    let heading_error = forward.cross(to_target).dot(up).atan2(forward.dot(to_target));
+    let turn_rate = velocity.angvel.dot(up);
+    let steer = settings.steering_gain * heading_error - settings.steering_damping * turn_rate;
+    let throttle = (target_speed - forward_speed) * settings.throttle_gain;
+
+    let drive_input = DriveInput {
+        throttle: throttle.clamp(-1.0, 1.0),
+        steer: steer.clamp(-1.0, 1.0),
+        ..default()
+    };
+    return (drive_input, avoiding);
+}
+
+/// Spawns the AI cars of `AiConfig` along the road once it and their vehicle are loaded.
+pub fn spawn_ai_vehicles_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<AiConfig>,
+    game: Res<Game>,
+    roster: Res<VehicleRoster>,
+    mut ai_vehicles: ResMut<AiVehicles>,
+) {
+    if ai_vehicles.spawned || config.count == 0 || game.road_network.road_segments.is_empty() {
+        return;
+    }
+    let manifest = match roster.manifests.get(&config.vehicle) {
+        Some(manifest) => manifest,
+        _ => {
+            return;
+        }
+    };
+
+    let path = RoadPath::new(&game.road_network);
+    for index in 0..config.count {
+        let distance = path.wrap(config.spacing * (index + 1) as f32);
+        let (position, direction, up) = match path.point_at(distance) {
+            Some(point) => point,
+            _ => {
+                continue;
+            }
+        };
+        let position = position + up * manifest.hover.hover_height;
+        let transform = Transform::from_translation(position).looking_at(position + direction, up);
+        let entity = spawn_vehicle(&mut commands, &asset_server, &config.vehicle, manifest, transform);
+        commands.entity(entity).insert(AiDriver::new(config.settings.clone()));
+        ai_vehicles.entities.push(entity);
+    }
+    ai_vehicles.spawned = true;
+}
+
+type ObstacleQuery<'w, 's> = Query<'w, 's,
+    (Entity, &'static Transform, &'static Velocity),
+    Or<(With<Vehicle>, With<TrafficVehicle>)>,
+>;
+
+/// Drives every AI car, around other vehicles and traffic.
+pub fn ai_drive_system(
+    game: Res<Game>,
+    vehicles: ObstacleQuery,
+    mut drivers: Query<(Entity, &Transform, &Velocity, &Vehicle, &HoverVehicle, &mut AiDriver, &mut ExternalForce)>,
+) {
+    if drivers.is_empty() {
+        return;
+    }
+    let avoid_distance = drivers.iter().map(|(_, _, _, _, _, driver, _)| driver.settings.avoid_distance).fold(0.0, f32::max);
+    let obstacles = vehicles.iter().map(|(entity, transform, velocity)| Obstacle {
+        entity,
+        position: transform.translation,
+        velocity: velocity.linvel,
+    });
+    let grid = ObstacleGrid::new(obstacles, avoid_distance);
+    let path = RoadPath::new(&game.road_network);
+
+    for (entity, transform, velocity, vehicle, hover, mut driver, mut ext_force) in drivers.iter_mut() {
+        let others = grid
+            .near(transform.translation, driver.settings.avoid_distance)
+            .filter(|obstacle| obstacle.entity != entity);
+        let (drive_input, avoiding) = ai_drive_input(&driver.settings, &path, transform, velocity, others);
+        apply_drive_input(transform, velocity, &vehicle.manifest, &hover.settings, &drive_input, &mut ext_force);
+        driver.drive = drive_input;
+        driver.avoiding = avoiding;
+    }
+}
@@ -0,0 +1,599 @@
+//! Camera rigs following the players' vehicles.
+//!
+//! Each player's camera has a `CameraRig`. Its current `CameraMode` sets the
+//! player's `camera_target` every frame, and the camera eases towards it with
+//! critically damped smoothing. Modes cycle with `Action::NextCameraMode` (C).
+//! The mouse only turns the first player's camera.
+//!
+//! Cameras looking at the vehicle from a distance don't go through static
+//! geometry: they move in front of roads and pillars hiding the vehicle.
+
+use bevy::{
+    core_pipeline::clear_color::ClearColorConfig,
+    input::mouse::{MouseMotion, MouseWheel},
+    prelude::*,
+    render::camera::{Projection, ScalingMode},
+};
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    game::Game,
+    input_map::{Action, ActionState},
+    local_players::LocalPlayerInputs,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Behind and above the vehicle.
+    Chase,
+    /// On the hood, looking ahead.
+    Hood,
+    /// Around the vehicle, turned with the mouse (right button) and zoomed with the wheel.
+    Orbit,
+    /// Orthographic view from above the vehicle (or `CameraRig::top_down_focus`),
+    /// north up. Zoomed with Space and Shift. Used to edit roads.
+    TopDown,
+    /// Flies freely (I, J, K, L, Space, Shift), looks around with the mouse (right button).
+    FreeFly,
+}
+
+impl CameraMode {
+    pub fn next(&self) -> CameraMode {
+        return match self {
+            CameraMode::Chase => CameraMode::Hood,
+            CameraMode::Hood => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::TopDown,
+            CameraMode::TopDown => CameraMode::FreeFly,
+            CameraMode::FreeFly => CameraMode::Chase,
+        };
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CameraConfig {
+    /// Spawn a camera for each player. When false, set the players' `camera` to use your own.
+    pub spawn_camera: bool,
+    pub initial_mode: CameraMode,
+    /// Distance behind the vehicle.
+    pub follow_distance: f32,
+    /// Height above the vehicle.
+    pub follow_height: f32,
+    /// Hood camera position, in the vehicle's local space.
+    pub hood_offset: Vec3,
+    /// Height of the area seen by the top-down view, in world units.
+    pub top_down_height: f32,
+    /// Orbit and free-fly rotation, in radians per pixel.
+    pub mouse_sensitivity: f32,
+    /// Free-fly speed, in units per second.
+    pub free_fly_speed: f32,
+    /// Time the camera takes to catch up with its target, in seconds.
+    /// The hood camera uses a fraction of it.
+    pub smooth_time: f32,
+    /// Move in front of static colliders hiding the vehicle.
+    /// Requires `SimulationPlugin`.
+    pub collisions: bool,
+    /// Distance kept between the camera and colliders.
+    pub collision_margin: f32,
+    /// Time the camera takes to move back once nothing hides the vehicle.
+    pub collision_restore_time: f32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            spawn_camera: true,
+            initial_mode: CameraMode::Chase,
+            follow_distance: 20.0,
+            follow_height: 5.0,
+            hood_offset: Vec3::new(0.0, 1.2, -1.0),
+            top_down_height: 120.0,
+            mouse_sensitivity: 0.005,
+            free_fly_speed: 40.0,
+            smooth_time: 0.25,
+            collisions: true,
+            collision_margin: 0.5,
+            collision_restore_time: 0.6,
+        }
+    }
+}
+
+/// State of a camera rig: the current mode, its controls,
+/// and the smoothed camera placement. On each player's camera.
+#[derive(Component)]
+pub struct CameraRig {
+    pub mode: CameraMode,
+    /// Orbit angles around the vehicle, in radians.
+    pub orbit_yaw: f32,
+    pub orbit_pitch: f32,
+    pub orbit_distance: f32,
+    /// Free-fly camera placement.
+    pub free_position: Vec3,
+    pub free_yaw: f32,
+    pub free_pitch: f32,
+    /// Center of the top-down view, instead of the vehicle.
+    /// Moved with the free-fly keys.
+    pub top_down_focus: Option<Vec3>,
+    /// Multiplies `CameraConfig::top_down_height`.
+    pub top_down_zoom: f32,
+    position: SmoothedVec3,
+    look_at: SmoothedVec3,
+    up: SmoothedVec3,
+    collision: CameraCollision,
+    /// Whether the camera reached its first target, before which it snaps.
+    placed: bool,
+}
+
+impl CameraRig {
+    pub fn new(config: &CameraConfig) -> Self {
+        return CameraRig {
+            mode: config.initial_mode,
+            orbit_yaw: 0.0,
+            orbit_pitch: 0.3,
+            orbit_distance: config.follow_distance,
+            free_position: Vec3::ZERO,
+            free_yaw: 0.0,
+            free_pitch: 0.0,
+            top_down_focus: None,
+            top_down_zoom: 1.0,
+            position: SmoothedVec3::default(),
+            look_at: SmoothedVec3::default(),
+            up: SmoothedVec3::default(),
+            collision: CameraCollision::default(),
+            placed: false,
+        };
+    }
+
+    /// Jump to the target on the next frame instead of easing in,
+    /// after a teleport for example.
+    pub fn snap(&mut self) {
+        self.placed = false;
+    }
+}
+
+/// A value easing towards a target, see `smooth_damp`.
+#[derive(Default, Debug, Clone, Copy)]
+struct SmoothedVec3 {
+    value: Vec3,
+    velocity: Vec3,
+}
+
+impl SmoothedVec3 {
+    fn update(&mut self, target: Vec3, smooth_time: f32, dt: f32) -> Vec3 {
+        self.value = smooth_damp(self.value, target, &mut self.velocity, smooth_time, dt);
+        return self.value;
+    }
+
+    fn snap(&mut self, target: Vec3) {
+        self.value = target;
+        self.velocity = Vec3::ZERO;
+    }
+}
+
+/// Distance between the camera and what it looks at, shortened by colliders.
+///
+/// Shortens at once, so the view is never blocked, and lengthens smoothly.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct CameraCollision {
+    distance: Option<f32>,
+    velocity: Vec3,
+}
+
+impl CameraCollision {
+    /// `allowed` is the free distance along the view, if something is in the way.
+    pub fn update(&mut self, full_distance: f32, allowed: Option<f32>, restore_time: f32, dt: f32) -> f32 {
+        let current = self.distance.unwrap_or(full_distance);
+        let target = allowed.unwrap_or(full_distance).min(full_distance);
+
+        if target <= current {
+            self.velocity = Vec3::ZERO;
+            self.distance = Some(target);
+            return target;
+        }
+
+        let restored = smooth_damp(Vec3::X * current, Vec3::X * target, &mut self.velocity, restore_time, dt).x;
+        if target - restored < 0.01 {
+            // Back to normal
+            self.velocity = Vec3::ZERO;
+            self.distance = None;
+            return full_distance;
+        }
+        self.distance = Some(restored);
+        return restored;
+    }
+}
+
+/// Height of the top-down camera above what it looks at.
+/// Orthographic, so it only matters for what is above the camera.
+const TOP_DOWN_CAMERA_HEIGHT: f32 = 300.0;
+
+/// Moves `current` towards `target` like a critically damped spring,
+/// reaching it in roughly `smooth_time` seconds without overshooting.
+/// `velocity` carries the spring state between calls.
+pub fn smooth_damp(current: Vec3, target: Vec3, velocity: &mut Vec3, smooth_time: f32, dt: f32) -> Vec3 {
+    let omega = 2.0 / smooth_time.max(0.0001);
+    // Approximation of exp(-omega * dt), stable for large time steps
+    let x = omega * dt;
+    let decay = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+
+    let change = current - target;
+    let temp = (*velocity + change * omega) * dt;
+    *velocity = (*velocity - temp * omega) * decay;
+    return target + (change + temp) * decay;
+}
+
+/// Moves the players' cameras according to their `CameraRig`.
+/// Requires Bevy's `InputPlugin` for the mouse.
+#[derive(Default)]
+pub struct CameraPlugin {
+    pub config: CameraConfig,
+}
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.config.clone())
+            .init_resource::<Game>()
+            .init_resource::<ActionState>()
+            .add_system(camera_rig_setup_system)
+            .add_system(camera_mode_system
+                        .after(camera_rig_setup_system))
+            .add_system(camera_target_car_system
+                        .after(camera_mode_system))
+            .add_system(camera_target_target_system
+                        .after(camera_target_car_system))
+            .add_system(camera_projection_system
+                        .after(camera_mode_system));
+
+        if self.config.spawn_camera {
+            app.add_startup_system(setup_camera);
+        }
+    }
+}
+
+fn setup_camera(
+    mut commands: Commands,
+    config: Res<CameraConfig>,
+    mut game: ResMut<Game>,
+) {
+    for (index, player) in game.players.iter_mut().enumerate() {
+        let mut camera = commands.spawn_bundle(Camera3dBundle {
+            camera: Camera {
+                priority: index as isize,
+                ..default()
+            },
+            transform: Transform::from_xyz(-3.0, 3.0, 10.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..Default::default()
+        });
+        camera.insert(CameraRig::new(&config));
+        if index > 0 {
+            // The first camera clears the window, and shows the HUD
+            camera
+                .insert(Camera3d {
+                    clear_color: ClearColorConfig::None,
+                    ..default()
+                })
+                .insert(UiCameraConfig { show_ui: false });
+        }
+        player.camera = Some(camera.id());
+    }
+}
+
+/// Gives a `CameraRig` to player cameras set by the app.
+fn camera_rig_setup_system(
+    mut commands: Commands,
+    config: Res<CameraConfig>,
+    game: Res<Game>,
+    rigs: Query<&CameraRig>,
+) {
+    for camera in game.players.iter().filter_map(|player| player.camera) {
+        if rigs.get(camera).is_err() {
+            commands.entity(camera).insert(CameraRig::new(&config));
+        }
+    }
+}
+
+/// Switches modes and applies the orbit and free-fly controls of each player.
+#[allow(clippy::too_many_arguments)]
+fn camera_mode_system(
+    action_state: Res<ActionState>,
+    local_players: Option<Res<LocalPlayerInputs>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    time: Res<Time>,
+    config: Res<CameraConfig>,
+    game: Res<Game>,
+    mut rigs: Query<(&mut CameraRig, &Transform)>,
+) {
+    let mut mouse_delta = Vec2::ZERO;
+    for event in mouse_motion.iter() {
+        mouse_delta += event.delta;
+    }
+    if !mouse_buttons.pressed(MouseButton::Right) {
+        mouse_delta = Vec2::ZERO;
+    }
+    let mut scroll: f32 = 0.0;
+    for event in mouse_wheel.iter() {
+        scroll += event.y;
+    }
+
+    for (index, player) in game.players.iter().enumerate() {
+        let actions = match index {
+            0 => Some(&*action_state),
+            _ => local_players
+                .as_ref()
+                .and_then(|local_players| local_players.players.get(index - 1))
+                .map(|local_player| &local_player.actions),
+        };
+        let (actions, (mut rig, camera_transform)) = match (actions, player.camera.and_then(|camera| rigs.get_mut(camera).ok())) {
+            (Some(actions), Some(rig)) => (actions, rig),
+            _ => {
+                continue;
+            }
+        };
+
+        // The mouse belongs to the first player
+        let (mouse_delta, scroll) = if index == 0 { (mouse_delta, scroll) } else { (Vec2::ZERO, 0.0) };
+        control_rig(&mut rig, actions, camera_transform, mouse_delta, scroll, &config, time.delta_seconds());
+    }
+}
+
+fn control_rig(
+    rig: &mut CameraRig,
+    action_state: &ActionState,
+    camera_transform: &Transform,
+    mouse_delta: Vec2,
+    scroll: f32,
+    config: &CameraConfig,
+    dt: f32,
+) {
+    if action_state.just_pressed(Action::NextCameraMode) {
+        rig.mode = rig.mode.next();
+        info!("Camera: {:?}", rig.mode);
+
+        // Fly from where the camera is
+        if rig.mode == CameraMode::FreeFly {
+            let forward = camera_transform.forward();
+            rig.free_position = camera_transform.translation;
+            rig.free_yaw = (-forward.x).atan2(-forward.z);
+            rig.free_pitch = forward.y.clamp(-1.0, 1.0).asin();
+        }
+    }
+
+    let pitch_limit = std::f32::consts::FRAC_PI_2 - 0.05;
+    match rig.mode {
+        CameraMode::Orbit => {
+            rig.orbit_yaw -= mouse_delta.x * config.mouse_sensitivity;
+            rig.orbit_pitch = (rig.orbit_pitch + mouse_delta.y * config.mouse_sensitivity).clamp(-pitch_limit, pitch_limit);
+            rig.orbit_distance = (rig.orbit_distance * (1.0 - scroll * 0.1)).clamp(3.0, 200.0);
+        },
+        CameraMode::FreeFly => {
+            rig.free_yaw -= mouse_delta.x * config.mouse_sensitivity;
+            rig.free_pitch = (rig.free_pitch - mouse_delta.y * config.mouse_sensitivity).clamp(-pitch_limit, pitch_limit);
+
+            let rotation = Quat::from_euler(EulerRot::YXZ, rig.free_yaw, rig.free_pitch, 0.0);
+            let mut direction = Vec3::ZERO;
+            let controls = [
+                (Action::CameraForward, rotation * -Vec3::Z),
+                (Action::CameraBackward, rotation * Vec3::Z),
+                (Action::CameraLeft, rotation * -Vec3::X),
+                (Action::CameraRight, rotation * Vec3::X),
+                (Action::CameraUp, Vec3::Y),
+                (Action::CameraDown, -Vec3::Y),
+            ];
+            for (action, action_direction) in controls {
+                if action_state.pressed(action) {
+                    direction += action_direction;
+                }
+            }
+            rig.free_position += direction.normalize_or_zero() * config.free_fly_speed * dt;
+        },
+        CameraMode::TopDown => {
+            if action_state.pressed(Action::CameraUp) {
+                rig.top_down_zoom = (rig.top_down_zoom * (1.0 + dt)).min(20.0);
+            }
+            if action_state.pressed(Action::CameraDown) {
+                rig.top_down_zoom = (rig.top_down_zoom * (1.0 - dt)).max(0.05);
+            }
+
+            // Pan at the same speed on screen whatever the zoom
+            if let Some(focus) = rig.top_down_focus {
+                let mut direction = Vec3::ZERO;
+                let controls = [
+                    (Action::CameraForward, -Vec3::Z),
+                    (Action::CameraBackward, Vec3::Z),
+                    (Action::CameraLeft, -Vec3::X),
+                    (Action::CameraRight, Vec3::X),
+                ];
+                for (action, action_direction) in controls {
+                    if action_state.pressed(action) {
+                        direction += action_direction;
+                    }
+                }
+                let speed = config.top_down_height * rig.top_down_zoom * 0.5;
+                rig.top_down_focus = Some(focus + direction.normalize_or_zero() * speed * dt);
+            }
+        },
+        _ => {}
+    }
+}
+
+/// Sets each player's `camera_target` for the current mode of their rig.
+fn camera_target_car_system(
+    transforms: Query<&Transform>,
+    config: Res<CameraConfig>,
+    rigs: Query<&CameraRig>,
+    mut game: ResMut<Game>,
+) {
+    for player in game.players.iter_mut() {
+        let rig = match player.camera.and_then(|camera| rigs.get(camera).ok()) {
+            Some(rig) => rig,
+            _ => {
+                continue;
+            }
+        };
+        let car_transform = player.car.and_then(|car| transforms.get(car).ok());
+        if let Some((position, look_at, up)) = camera_placement(rig, &config, car_transform) {
+            player.camera_target.position = Some(position);
+            player.camera_target.look_at = Some(look_at);
+            player.camera_target.up = Some(up);
+        }
+    }
+}
+
+/// Camera position, point looked at and up direction for the mode of `rig`.
+fn camera_placement(rig: &CameraRig, config: &CameraConfig, car_transform: Option<&Transform>) -> Option<(Vec3, Vec3, Vec3)> {
+    if rig.mode == CameraMode::FreeFly {
+        let rotation = Quat::from_euler(EulerRot::YXZ, rig.free_yaw, rig.free_pitch, 0.0);
+        return Some((rig.free_position, rig.free_position + rotation * -Vec3::Z, Vec3::Y));
+    }
+
+    // Up is north, so the map doesn't turn with the vehicle
+    if rig.mode == CameraMode::TopDown {
+        let focus = match (rig.top_down_focus, car_transform) {
+            (Some(focus), _) => focus,
+            (_, Some(car_transform)) => car_transform.translation,
+            _ => {
+                return None;
+            }
+        };
+        return Some((focus + Vec3::Y * TOP_DOWN_CAMERA_HEIGHT, focus, -Vec3::Z));
+    }
+
+    let car_transform = car_transform?;
+    let car_position = car_transform.translation;
+
+    return match rig.mode {
+        CameraMode::Chase => Some((
+            car_position
+                + car_transform.forward() * -config.follow_distance
+                + car_transform.up() * config.follow_height,
+            car_position,
+            car_transform.up(),
+        )),
+        CameraMode::Hood => {
+            let position = car_transform.mul_vec3(config.hood_offset);
+            Some((position, position + car_transform.forward() * 50.0, car_transform.up()))
+        },
+        CameraMode::Orbit => {
+            let rotation = Quat::from_euler(EulerRot::YXZ, rig.orbit_yaw, -rig.orbit_pitch, 0.0);
+            Some((car_position + rotation * Vec3::Z * rig.orbit_distance, car_position, Vec3::Y))
+        },
+        _ => None,
+    };
+}
+
+/// Eases each player's camera towards their `camera_target`.
+fn camera_target_target_system(
+    mut cameras: Query<(&mut Transform, &mut CameraRig)>,
+    rapier_context: Option<Res<RapierContext>>,
+    time: Res<Time>,
+    config: Res<CameraConfig>,
+    game: Res<Game>,
+) {
+    for player in game.players.iter() {
+        let camera_entity = match player.camera { Some(x) => x, _ => { continue; } };
+        let (mut camera_transform, mut rig) = match cameras.get_mut(camera_entity) { Ok(x) => x, _ => { continue; } };
+        let camera_target_look_at = match player.camera_target.look_at { Some(x) => x, _ => { continue; } };
+        let camera_target_position = match player.camera_target.position { Some(x) => x, _ => { continue; } };
+        let camera_target_up = match player.camera_target.up { Some(x) => x, _ => { continue; } };
+
+        if !rig.placed {
+            rig.position.snap(camera_target_position);
+            rig.look_at.snap(camera_target_look_at);
+            rig.up.snap(camera_target_up);
+            rig.placed = true;
+        }
+
+        // The hood camera is mounted on the vehicle, it barely lags
+        let smooth_time = match rig.mode {
+            CameraMode::Hood => config.smooth_time * 0.2,
+            _ => config.smooth_time,
+        };
+        let dt = time.delta_seconds();
+
+        let position = rig.position.update(camera_target_position, smooth_time, dt);
+        let look_at = rig.look_at.update(camera_target_look_at, smooth_time, dt);
+        let up = rig.up.update(camera_target_up, smooth_time, dt);
+
+        // Pull in front of static geometry between the vehicle and the camera
+        let view = position - look_at;
+        let full_distance = view.length();
+        let occludable = matches!(rig.mode, CameraMode::Chase | CameraMode::Orbit);
+        let mut position = position;
+        if let (true, true, Some(rapier_context)) = (config.collisions, occludable, rapier_context.as_ref()) {
+            let direction = view.normalize_or_zero();
+            let hit = rapier_context.cast_ray(
+                look_at,
+                direction,
+                full_distance + config.collision_margin,
+                true,
+                QueryFilter::only_fixed().exclude_sensors(),
+            );
+            let allowed = hit.map(|(_, distance)| (distance - config.collision_margin).max(0.0));
+            let distance = rig.collision.update(full_distance, allowed, config.collision_restore_time, dt);
+            position = look_at + direction * distance;
+        }
+
+        camera_transform.translation = position;
+        camera_transform.look_at(look_at, up.normalize_or_zero());
+    }
+}
+
+/// Orthographic projection for the top-down view, perspective for the others.
+fn camera_projection_system(
+    mut cameras: Query<(&mut Projection, &CameraRig)>,
+    config: Res<CameraConfig>,
+    game: Res<Game>,
+) {
+    for camera_entity in game.players.iter().filter_map(|player| player.camera) {
+        let (mut projection, rig) = match cameras.get_mut(camera_entity) { Ok(x) => x, _ => { continue; } };
+
+        let height = config.top_down_height * rig.top_down_zoom;
+        match (rig.mode, projection.as_ref()) {
+            (CameraMode::TopDown, Projection::Orthographic(orthographic)) => {
+                // Only touch the projection when zooming, it is recomputed when changed
+                let current_height = match orthographic.scaling_mode {
+                    ScalingMode::FixedVertical(current_height) => Some(current_height),
+                    _ => None,
+                };
+                if current_height != Some(height) {
+                    if let Projection::Orthographic(orthographic) = projection.as_mut() {
+                        orthographic.scaling_mode = ScalingMode::FixedVertical(height);
+                    }
+                }
+            },
+            (CameraMode::TopDown, _) => {
+                *projection = Projection::Orthographic(OrthographicProjection {
+                    scaling_mode: ScalingMode::FixedVertical(height),
+                    far: TOP_DOWN_CAMERA_HEIGHT * 2.0,
+                    ..default()
+                });
+            },
+            (_, Projection::Orthographic(_)) => {
+                *projection = Projection::Perspective(PerspectiveProjection::default());
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Ray from the camera through the mouse cursor, as an origin and a direction.
+pub fn cursor_ray(windows: &Windows, camera: &Camera, camera_transform: &GlobalTransform) -> Option<(Vec3, Vec3)> {
+    let window = windows.get_primary()?;
+    let cursor = window.cursor_position()?;
+    let window_size = Vec2::new(window.width(), window.height());
+
+    // Viewports start at the top left, the cursor at the bottom left
+    let (min, max) = match camera.logical_viewport_rect() {
+        Some((min, max)) => (Vec2::new(min.x, window_size.y - max.y), Vec2::new(max.x, window_size.y - min.y)),
+        _ => (Vec2::ZERO, window_size),
+    };
+    let ndc = (cursor - min) / (max - min) * 2.0 - Vec2::ONE;
+
+    // Reversed depth: 1 is the near plane, 0 the far one (or infinity)
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+    let near = ndc_to_world.project_point3(ndc.extend(1.0));
+    let far = ndc_to_world.project_point3(ndc.extend(0.001));
+    let direction = (far - near).try_normalize()?;
+    return Some((near, direction));
+}
@@ -0,0 +1,136 @@
+//! Analog driving input, from the keyboard and gamepads.
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::input_map::{Action, ActionState, InputContext, InputDevices};
+
+/// What the player wants the vehicle to do this frame.
+/// Every axis is in the [-1, 1] range.
+#[derive(Default, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct DriveInput {
+    /// Positive accelerates, negative brakes/reverses.
+    pub throttle: f32,
+    /// Positive turns left.
+    pub steer: f32,
+    /// Positive rolls left.
+    pub roll: f32,
+    /// Positive pitches the nose down.
+    pub pitch: f32,
+}
+
+impl DriveInput {
+    /// The input with every axis clamped to [-1, 1], None if one isn't a number.
+    /// For input from elsewhere, like network clients.
+    pub fn validated(&self) -> Option<DriveInput> {
+        let axes = [self.throttle, self.steer, self.roll, self.pitch];
+        if !axes.iter().all(|axis| axis.is_finite()) {
+            return None;
+        }
+        return Some(DriveInput {
+            throttle: self.throttle.clamp(-1.0, 1.0),
+            steer: self.steer.clamp(-1.0, 1.0),
+            roll: self.roll.clamp(-1.0, 1.0),
+            pitch: self.pitch.clamp(-1.0, 1.0),
+        });
+    }
+}
+
+pub struct DriveInputSettings {
+    /// Stick values below this are ignored.
+    pub stick_deadzone: f32,
+    /// Trigger values below this are ignored.
+    pub trigger_deadzone: f32,
+}
+
+impl Default for DriveInputSettings {
+    fn default() -> Self {
+        DriveInputSettings {
+            stick_deadzone: 0.15,
+            trigger_deadzone: 0.05,
+        }
+    }
+}
+
+/// Zeroes values inside the deadzone and rescales the rest,
+/// so the output still covers the full [-1, 1] range without a jump.
+pub fn apply_deadzone(value: f32, deadzone: f32) -> f32 {
+    let magnitude = value.abs();
+    if magnitude <= deadzone {
+        return 0.0;
+    }
+    let rescaled = ((magnitude - deadzone) / (1.0 - deadzone)).min(1.0);
+    return rescaled * value.signum();
+}
+
+fn action_axis(action_state: &ActionState, positive: Action, negative: Action) -> f32 {
+    let mut value = 0.0;
+    if action_state.pressed(positive) {
+        value += 1.0;
+    }
+    if action_state.pressed(negative) {
+        value -= 1.0;
+    }
+    return value;
+}
+
+/// Driving input from the driving actions and the analog axes of `gamepads`.
+///
+/// Analog gamepad layout:
+///  - Right trigger: throttle, left trigger: brake/reverse
+///  - Left stick: steering (x) and pitch (y)
+///  - Right stick x: roll
+pub fn read_drive_input(
+    action_state: &ActionState,
+    gamepads: &[Gamepad],
+    axes: &Axis<GamepadAxis>,
+    button_axes: &Axis<GamepadButton>,
+    settings: &DriveInputSettings,
+) -> DriveInput {
+    let mut input = DriveInput {
+        throttle: action_axis(action_state, Action::Accelerate, Action::Brake),
+        steer: action_axis(action_state, Action::SteerLeft, Action::SteerRight),
+        roll: action_axis(action_state, Action::RollLeft, Action::RollRight),
+        pitch: action_axis(action_state, Action::PitchDown, Action::PitchUp),
+    };
+
+    // Analog axes follow the driving context like the bindings do
+    let driving = action_state.active_contexts.contains(&InputContext::Driving);
+
+    for gamepad in gamepads.iter().filter(|_| driving) {
+        let stick = |axis_type: GamepadAxisType| {
+            let value = axes.get(GamepadAxis::new(*gamepad, axis_type)).unwrap_or(0.0);
+            apply_deadzone(value, settings.stick_deadzone)
+        };
+        let trigger = |button_type: GamepadButtonType| {
+            let value = button_axes.get(GamepadButton::new(*gamepad, button_type)).unwrap_or(0.0);
+            apply_deadzone(value, settings.trigger_deadzone)
+        };
+
+        input.throttle += trigger(GamepadButtonType::RightTrigger2) - trigger(GamepadButtonType::LeftTrigger2);
+        input.steer -= stick(GamepadAxisType::LeftStickX);
+        input.pitch += stick(GamepadAxisType::LeftStickY);
+        input.roll -= stick(GamepadAxisType::RightStickX);
+    }
+
+    return DriveInput {
+        throttle: input.throttle.clamp(-1.0, 1.0),
+        steer: input.steer.clamp(-1.0, 1.0),
+        roll: input.roll.clamp(-1.0, 1.0),
+        pitch: input.pitch.clamp(-1.0, 1.0),
+    };
+}
+
+/// Reads the driving input of the first player into `DriveInput`.
+pub fn drive_input_system(
+    action_state: Res<ActionState>,
+    devices: Res<InputDevices>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    button_axes: Res<Axis<GamepadButton>>,
+    settings: Res<DriveInputSettings>,
+    mut drive_input: ResMut<DriveInput>,
+) {
+    let connected_gamepads = devices.connected_gamepads(&gamepads);
+    *drive_input = read_drive_input(&action_state, &connected_gamepads, &axes, &button_axes, &settings);
+}
@@ -0,0 +1,429 @@
+//! In game road editing: road building trailers and the editing actions
+//! (insert segment, hitch, dump, clear, macros).
+//!
+//! The road editor (Tab) shows the map from above: click to continue the
+//! road, drag nodes to move them, scroll to change their height. The clicked
+//! node gets a gizmo to move it along an axis and turn its heading and bank,
+//! see `road_gizmo`. It makes `RoadEdit`s, which build the same segments as
+//! the trailer.
+//!
+//! B applies the bank and elevation profile of `EditorConfig::road_profile_path`
+//! to the road, see `road_profile`.
+//!
+//! Also runs trailer coupling, hitch breaking and stability, see `trailer`.
+
+// This is only for native builds
+#[allow(unused_imports)]
+use std::fs::read_to_string;
+#[allow(unused_imports)]
+use crate::road_profile::RoadProfile;
+
+use bevy::{input::mouse::MouseWheel, prelude::*};
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    camera::{cursor_ray, CameraMode, CameraRig},
+    game::Game,
+    input_map::{Action, ActionState, InputContext},
+    road_gizmo::{closest_on_axis, pick_handle, road_gizmo_system, setup_road_gizmo, GizmoDrag, GizmoHandle, GIZMO_ROTATE_SPEED},
+    road_network_builder::{PendingRoadEdits, RoadEdit},
+    road_profile::ROAD_PROFILE_PATH,
+    road_systems::{road_edit_system, road_network_creation_system, RoadNetworkEvent},
+    simulation::{add_simulation_system, SimulationStage},
+    trailer::{
+        coupling_system, hitch_break_system, hitch_indicator_system, log_hitch_events_system,
+        setup_hitch_indicator, trailer_stability_system, HitchEvent,
+    },
+    vehicle::{spawn_vehicle, VehicleRoster},
+};
+
+pub const DEFAULT_TRAILER: &str = "road_trailer_0001";
+
+/// Clicks closer than this to a node grab it, in world units.
+const NODE_PICK_DISTANCE: f32 = 4.0;
+/// Height change per scroll wheel line.
+const NODE_HEIGHT_STEP: f32 = 1.0;
+
+#[derive(Clone, Debug)]
+pub struct EditorConfig {
+    /// Trailers spawned when the game starts, by manifest name.
+    pub trailers: Vec<(String, Transform)>,
+    /// Show the attach range and the trailer to hitch. Requires Bevy's rendering.
+    pub hitch_indicator: bool,
+    /// Road editor with the mouse (Tab). Requires Bevy's rendering and `CameraPlugin`.
+    pub road_editor: bool,
+    /// Bank and elevation profile applied with B, if any. Native builds only.
+    pub road_profile_path: Option<String>,
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        EditorConfig {
+            trailers: vec![
+                (String::from(DEFAULT_TRAILER), Transform::from_xyz(0.0, 0.0, -3.0)),
+                (String::from(DEFAULT_TRAILER), Transform::from_xyz(0.0, 0.0, 6.0)),
+            ],
+            hitch_indicator: true,
+            road_editor: true,
+            road_profile_path: Some(String::from(ROAD_PROFILE_PATH)),
+        }
+    }
+}
+
+/// Requires `SimulationPlugin`, `RoadNetworkPlugin` and `VehiclePlugin`.
+#[derive(Default)]
+pub struct EditorPlugin {
+    pub config: EditorConfig,
+}
+
+/// State of the mouse road editor.
+pub struct RoadEditor {
+    pub active: bool,
+    /// Height of new nodes above the ground.
+    pub height: f32,
+    /// Node being dragged, where it is now.
+    pub dragging: Option<Vec3>,
+    /// Node with the gizmo, where it is now.
+    pub selected: Option<Vec3>,
+    /// Gizmo handle under the cursor.
+    pub hovered_handle: Option<GizmoHandle>,
+    pub gizmo_drag: Option<GizmoDrag>,
+    /// Camera mode to go back to.
+    previous_camera_mode: CameraMode,
+    /// Marks the node under the cursor, or where the next one goes.
+    marker: Option<Entity>,
+}
+
+impl Default for RoadEditor {
+    fn default() -> Self {
+        RoadEditor {
+            active: false,
+            height: 0.0,
+            dragging: None,
+            selected: None,
+            hovered_handle: None,
+            gizmo_drag: None,
+            previous_camera_mode: CameraMode::Chase,
+            marker: None,
+        }
+    }
+}
+
+/// Trailers waiting for their manifest.
+struct PendingTrailers {
+    trailers: Vec<(String, Transform)>,
+}
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.config.clone())
+            .insert_resource(PendingTrailers {
+                trailers: self.config.trailers.clone(),
+            })
+            .init_resource::<Game>()
+            .add_event::<HitchEvent>()
+            .add_event::<RoadNetworkEvent>()
+            .add_system(log_hitch_events_system)
+            .add_system(apply_road_profile_system);
+
+        add_simulation_system(app, SimulationStage::Vehicles, spawn_trailers_system);
+        add_simulation_system(app, SimulationStage::Vehicles, hitch_break_system);
+        add_simulation_system(app, SimulationStage::Editing, road_network_creation_system);
+        add_simulation_system(app, SimulationStage::Editing, road_edit_system);
+        add_simulation_system(app, SimulationStage::VehicleForces, coupling_system);
+        add_simulation_system(app, SimulationStage::VehicleForces, trailer_stability_system);
+
+        if self.config.hitch_indicator {
+            app.add_startup_system(setup_hitch_indicator)
+                .add_system(hitch_indicator_system);
+        }
+
+        if self.config.road_editor {
+            app.init_resource::<RoadEditor>()
+                .add_startup_system(setup_road_editor_marker)
+                .add_startup_system(setup_road_gizmo)
+                .add_system(road_editor_toggle_system)
+                .add_system(road_editor_mouse_system
+                            .after(road_editor_toggle_system))
+                .add_system(road_gizmo_system
+                            .after(road_editor_mouse_system));
+        }
+    }
+}
+
+fn spawn_trailers_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    roster: Res<VehicleRoster>,
+    mut pending: ResMut<PendingTrailers>,
+) {
+    if pending.trailers.is_empty() {
+        return;
+    }
+
+    let mut waiting: Vec<(String, Transform)> = Vec::new();
+    for (name, transform) in pending.trailers.drain(..) {
+        match roster.manifests.get(&name) {
+            Some(manifest) => {
+                spawn_vehicle(&mut commands, &asset_server, &name, manifest, transform);
+            },
+            _ => {
+                waiting.push((name, transform));
+            }
+        }
+    }
+    pending.trailers = waiting;
+}
+
+/// Reads the road profile and applies it to the road (B).
+#[allow(unused_mut, unused_variables)]
+fn apply_road_profile_system(
+    action_state: Res<ActionState>,
+    config: Res<EditorConfig>,
+    mut pending_road_edits: ResMut<PendingRoadEdits>,
+    mut road_network_events: EventWriter<RoadNetworkEvent>,
+) {
+    if !action_state.just_released(Action::ApplyRoadProfile) {
+        return;
+    }
+    let path = match &config.road_profile_path {
+        Some(path) => path,
+        _ => {
+            return;
+        }
+    };
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        warn!("Road profiles can't be read in the browser");
+        road_network_events.send(RoadNetworkEvent::ProfileFailed);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let profile = match read_to_string(path).map_err(|error| error.to_string())
+            .and_then(|serialized| RoadProfile::from_json(&serialized).map_err(|error| error.to_string())) {
+            Ok(profile) => profile,
+            Err(error) => {
+                error!("Could not load road profile {}: {}", path, error);
+                road_network_events.send(RoadNetworkEvent::ProfileFailed);
+                return;
+            }
+        };
+
+        // Through the simulation input, so replays don't need the file
+        pending_road_edits.edits.push(RoadEdit::ApplyProfile { profile });
+        info!("Applied road profile {}", path);
+        road_network_events.send(RoadNetworkEvent::ProfileApplied);
+    }
+}
+
+fn setup_road_editor_marker(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut editor: ResMut<RoadEditor>,
+) {
+    editor.marker = Some(commands.spawn_bundle(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::UVSphere {
+            radius: 1.0,
+            ..default()
+        })),
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.2, 0.8, 1.0),
+            unlit: true,
+            ..default()
+        }),
+        visibility: Visibility { is_visible: false },
+        ..default()
+    }).id());
+}
+
+/// Enters and leaves the road editor (Tab).
+fn road_editor_toggle_system(
+    mut action_state: ResMut<ActionState>,
+    mut editor: ResMut<RoadEditor>,
+    mut rigs: Query<&mut CameraRig>,
+    transforms: Query<&Transform>,
+    game: Res<Game>,
+) {
+    if !action_state.just_pressed(Action::ToggleRoadEditor) {
+        return;
+    }
+    let mut rig = match game.camera().and_then(|camera| rigs.get_mut(camera).ok()) {
+        Some(rig) => rig,
+        _ => {
+            return;
+        }
+    };
+
+    editor.active = !editor.active;
+    editor.dragging = None;
+    editor.selected = None;
+    editor.hovered_handle = None;
+    editor.gizmo_drag = None;
+
+    if editor.active {
+        // Start above the vehicle, without driving it around
+        let focus = game.player_car()
+            .and_then(|car| transforms.get(car).ok())
+            .map(|transform| transform.translation)
+            .unwrap_or(Vec3::ZERO);
+        editor.previous_camera_mode = rig.mode;
+        rig.mode = CameraMode::TopDown;
+        rig.top_down_focus = Some(focus);
+        action_state.set_context_active(InputContext::Driving, false);
+    } else {
+        rig.mode = editor.previous_camera_mode;
+        rig.top_down_focus = None;
+        action_state.set_context_active(InputContext::Driving, true);
+    }
+}
+
+/// Places, selects and drags road nodes with the mouse, and drags the gizmo
+/// handles of the selected node.
+#[allow(clippy::too_many_arguments)]
+fn road_editor_mouse_system(
+    mouse_buttons: Res<Input<MouseButton>>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut markers: Query<(&mut Transform, &mut Visibility)>,
+    rapier_context: Res<RapierContext>,
+    mut editor: ResMut<RoadEditor>,
+    mut pending_road_edits: ResMut<PendingRoadEdits>,
+    game: Res<Game>,
+) {
+    let mut scroll: f32 = 0.0;
+    for event in mouse_wheel.iter() {
+        scroll += event.y;
+    }
+
+    let marker = match editor.marker.and_then(|marker| markers.get_mut(marker).ok()) {
+        Some(marker) => marker,
+        _ => {
+            return;
+        }
+    };
+    let (mut marker_transform, mut marker_visibility) = marker;
+    marker_visibility.is_visible = false;
+    editor.hovered_handle = None;
+
+    if !editor.active {
+        return;
+    }
+
+    let (camera, camera_transform) = match game.camera().and_then(|camera| cameras.get(camera).ok()) {
+        Some(camera) => camera,
+        _ => {
+            return;
+        }
+    };
+    let (origin, direction) = match cursor_ray(&windows, camera, camera_transform) {
+        Some(ray) => ray,
+        _ => {
+            return;
+        }
+    };
+    let cursor_x = match windows.get_primary().and_then(|window| window.cursor_position()) {
+        Some(cursor) => cursor.x,
+        _ => {
+            return;
+        }
+    };
+
+    // Drag the grabbed gizmo handle: arrows follow the cursor along their axis,
+    // rings turn with horizontal mouse movement
+    if let Some(drag) = editor.gizmo_drag {
+        let node = match editor.selected {
+            Some(node) if mouse_buttons.pressed(MouseButton::Left) => node,
+            _ => {
+                editor.gizmo_drag = None;
+                return;
+            }
+        };
+        let axis = drag.handle.axis(game.road_network.node_rotation(node).unwrap_or(Quat::IDENTITY));
+
+        if drag.handle.is_rotation() {
+            let angle = (cursor_x - drag.last) * GIZMO_ROTATE_SPEED;
+            if angle != 0.0 {
+                pending_road_edits.edits.push(RoadEdit::RotateNode { node, rotation: Quat::from_axis_angle(axis, angle) });
+            }
+            editor.gizmo_drag = Some(GizmoDrag { last: cursor_x, ..drag });
+        } else if let Some(along) = closest_on_axis(node, axis, origin, direction) {
+            let to = node + axis * (along - drag.last);
+            if to != node {
+                pending_road_edits.edits.push(RoadEdit::MoveNode { from: node, to });
+                editor.selected = Some(to);
+            }
+        }
+        return;
+    }
+
+    // Grab a handle of the gizmo, instead of placing a node under it
+    if editor.dragging.is_none() {
+        if let Some(node) = editor.selected {
+            let rotation = game.road_network.node_rotation(node);
+            editor.hovered_handle = pick_handle(node, rotation, origin, direction);
+            if let Some(handle) = editor.hovered_handle {
+                if mouse_buttons.just_pressed(MouseButton::Left) {
+                    let last = if handle.is_rotation() {
+                        Some(cursor_x)
+                    } else {
+                        closest_on_axis(node, handle.axis(Quat::IDENTITY), origin, direction)
+                    };
+                    editor.gizmo_drag = last.map(|last| GizmoDrag { handle, last });
+                }
+                return;
+            }
+        }
+    }
+
+    // Nodes go on the ground, not on the road being edited
+    let mut filter = QueryFilter::only_fixed().exclude_sensors();
+    if let Some(road_network_entity) = game.road_network_entity {
+        filter = filter.exclude_collider(road_network_entity);
+    }
+    let ground = match rapier_context.cast_ray(origin, direction, f32::MAX, true, filter) {
+        Some((_, distance)) => origin + direction * distance,
+        _ => {
+            return;
+        }
+    };
+
+    // Drag the grabbed node, scroll moves it up and down
+    if let Some(node) = editor.dragging {
+        if !mouse_buttons.pressed(MouseButton::Left) {
+            editor.dragging = None;
+            return;
+        }
+        let to = Vec3::new(ground.x, node.y + scroll * NODE_HEIGHT_STEP, ground.z);
+        if to != node {
+            pending_road_edits.edits.push(RoadEdit::MoveNode { from: node, to });
+            editor.dragging = Some(to);
+            editor.selected = Some(to);
+        }
+        marker_transform.translation = to;
+        marker_visibility.is_visible = true;
+        return;
+    }
+
+    editor.height += scroll * NODE_HEIGHT_STEP;
+    let hovered_node = game.road_network.closest_node(ground, NODE_PICK_DISTANCE);
+    let placement = ground + Vec3::Y * editor.height;
+    marker_transform.translation = hovered_node.unwrap_or(placement);
+    marker_visibility.is_visible = true;
+
+    // Clicked nodes get the gizmo, and so do new ones
+    if mouse_buttons.just_pressed(MouseButton::Left) {
+        match hovered_node {
+            Some(node) => {
+                editor.dragging = Some(node);
+                editor.selected = Some(node);
+            },
+            _ => {
+                pending_road_edits.edits.push(RoadEdit::AddNode { position: placement, up: Vec3::Y });
+                editor.selected = Some(placement);
+            }
+        }
+    }
+}
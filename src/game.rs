@@ -2,7 +2,6 @@ use bevy::prelude::*;
 
 use crate::road_network_builder::RoadNetwork;
 use serde::{Serialize, Deserialize};
-use bevy_rapier3d::dynamics::*;
 
 #[derive(Default, Serialize, Deserialize, Debug)]
 pub struct CameraTarget {
@@ -11,14 +10,40 @@ pub struct CameraTarget {
     pub look_at: Option<Vec3>,
 }
 
-
-#[derive(Default)]
-pub struct Game {
-    pub player_car: Option<Entity>,
-    pub trailer: Option<Entity>,
-    pub trailer_joint: Option<ImpulseJoint>,
+/// A local player: their vehicle and their view, see `local_players`.
+#[derive(Default, Debug)]
+pub struct Player {
+    pub car: Option<Entity>,
     pub camera_target: CameraTarget,
     pub camera: Option<Entity>,
+}
+
+pub struct Game {
+    /// Local players, at least one. The first one also edits the road,
+    /// races and is shown by the HUD.
+    pub players: Vec<Player>,
     pub road_network: RoadNetwork,
     pub road_network_entity: Option<Entity>,
 }
+
+impl Default for Game {
+    fn default() -> Self {
+        Game {
+            players: vec![Player::default()],
+            road_network: RoadNetwork::default(),
+            road_network_entity: None,
+        }
+    }
+}
+
+impl Game {
+    /// Vehicle of the first player.
+    pub fn player_car(&self) -> Option<Entity> {
+        return self.players.first().and_then(|player| player.car);
+    }
+
+    /// Camera of the first player.
+    pub fn camera(&self) -> Option<Entity> {
+        return self.players.first().and_then(|player| player.camera);
+    }
+}
@@ -0,0 +1,425 @@
+//! Ghost cars: the fastest lap of a track, replayed while racing.
+//!
+//! The player's car `Transform` is recorded on every tick of a lap. When a lap
+//! beats the current ghost it becomes the new ghost, and is saved next to the
+//! track: `assets/road_network.json` gets `assets/road_network.ghost.json`.
+//! Ghost files can be shared, `--ghost <file>` loads one from a team member.
+//!
+//! On every lap of race mode the ghost drives along as a translucent copy of
+//! the car it was recorded with, without physics. Ghosts only play on the
+//! track they were recorded on, see `RoadNetwork::track_id`.
+
+use std::{collections::HashMap, path::Path};
+// This is only for native builds
+#[allow(unused_imports)]
+use std::fs::{read_to_string, write};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::Game,
+    hud::Toasts,
+    race::{format_lap_time, lap_timing_system, Race, RaceEvent},
+    replay::SimulationInput,
+    road_systems::RoadNetworkConfig,
+    simulation::{add_simulation_system, SimulationStage, TIMESTEP},
+    vehicle::{Vehicle, VehicleRoster},
+};
+
+/// Ghost file used when the track wasn't loaded from a file.
+pub const GHOST_PATH: &str = "ghost.json";
+
+#[derive(Clone, Debug)]
+pub struct GhostConfig {
+    /// Load the track's ghost at startup (or the one given with `--ghost <file>`),
+    /// and save new ones. Native builds only.
+    pub files: bool,
+    /// Opacity of the ghost car.
+    pub alpha: f32,
+}
+
+impl Default for GhostConfig {
+    fn default() -> Self {
+        GhostConfig {
+            files: true,
+            alpha: 0.35,
+        }
+    }
+}
+
+/// Requires `RacePlugin` and `VehiclePlugin`.
+#[derive(Default)]
+pub struct GhostPlugin {
+    pub config: GhostConfig,
+}
+
+impl Plugin for GhostPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.config.clone())
+            .init_resource::<Game>()
+            .init_resource::<Ghosts>()
+            .init_resource::<GhostPlayer>()
+            .add_event::<RaceEvent>()
+            .add_startup_system(load_ghost)
+            .add_system(save_ghost_system)
+            .add_system(ghost_playback_system)
+            .add_system(ghost_material_system.after(ghost_playback_system));
+
+        add_simulation_system(app, SimulationStage::Gameplay, ghost_recording_system.after(lap_timing_system));
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct GhostSample {
+    pub translation: Vec3,
+    pub rotation: Quat,
+}
+
+/// A recorded lap, one sample per tick from the start of the lap.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GhostLap {
+    /// Track the lap was driven on, see `RoadNetwork::track_id`.
+    pub track_id: String,
+    /// Vehicle the lap was driven with.
+    pub vehicle: String,
+    /// Lap time in seconds.
+    pub time: f32,
+    /// Simulation timestep the samples were taken at.
+    pub timestep: f32,
+    pub samples: Vec<GhostSample>,
+}
+
+impl GhostLap {
+    pub fn from_json(json: &str) -> Result<GhostLap, serde_json::Error> {
+        return serde_json::from_str(json);
+    }
+
+    /// Where the ghost is at `time` into the lap, staying on the line after the lap.
+    pub fn sample_at(&self, time: f32) -> Option<GhostSample> {
+        let last = self.samples.len().checked_sub(1)?;
+        let position = (time.max(0.0) / self.timestep).min(last as f32);
+        let index = position.floor() as usize;
+        let (a, b) = (self.samples[index], self.samples[(index + 1).min(last)]);
+        let t = position - index as f32;
+        return Some(GhostSample {
+            translation: a.translation.lerp(b.translation, t),
+            rotation: a.rotation.slerp(b.rotation, t),
+        });
+    }
+}
+
+/// Ghost file next to a track file: `tracks/oval.json` gets `tracks/oval.ghost.json`.
+pub fn ghost_path(track_path: &str) -> String {
+    return Path::new(track_path).with_extension("ghost.json").to_string_lossy().into_owned();
+}
+
+/// The ghost, and the lap being recorded.
+#[derive(Default, Debug)]
+pub struct Ghosts {
+    /// Fastest lap known, replayed while racing on its track.
+    pub best: Option<GhostLap>,
+    /// The best lap changed since it was last saved.
+    unsaved: bool,
+    /// Samples of the current lap.
+    recording: Vec<GhostSample>,
+    /// `Race::lap_start` and `Race::laps` of the lap being recorded.
+    recording_lap: Option<u64>,
+    recording_laps: u32,
+}
+
+/// Records the player's car during race laps, and keeps the fastest complete one.
+pub fn ghost_recording_system(
+    game: Res<Game>,
+    race: Res<Race>,
+    vehicles: Query<(&Transform, &Vehicle)>,
+    mut ghosts: ResMut<Ghosts>,
+) {
+    if race.lap_start != ghosts.recording_lap {
+        // The recorded lap is over, it counts if the car made it around
+        let completed = ghosts.recording_lap.is_some() && race.laps > ghosts.recording_laps;
+        let vehicle = game.player_car().and_then(|car| vehicles.get(car).ok()).map(|(_, vehicle)| vehicle.name.clone());
+        match (completed, race.last_lap, vehicle) {
+            (true, Some(time), Some(vehicle)) => {
+                let track_id = game.road_network.track_id();
+                let faster = match &ghosts.best {
+                    Some(best) => best.track_id != track_id || time < best.time,
+                    _ => true,
+                };
+                if faster {
+                    let samples = std::mem::take(&mut ghosts.recording);
+                    ghosts.best = Some(GhostLap { track_id, vehicle, time, timestep: TIMESTEP, samples });
+                    ghosts.unsaved = true;
+                }
+            },
+            _ => {},
+        }
+
+        ghosts.recording.clear();
+        ghosts.recording_lap = race.lap_start;
+        ghosts.recording_laps = race.laps;
+    }
+
+    if race.lap_start.is_none() {
+        return;
+    }
+    let transform = match game.player_car().and_then(|car| vehicles.get(car).ok()) {
+        Some((transform, _)) => transform,
+        _ => {
+            return;
+        }
+    };
+    ghosts.recording.push(GhostSample {
+        translation: transform.translation,
+        rotation: transform.rotation,
+    });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn load_ghost_file(path: &str) -> Result<GhostLap, String> {
+    let serialized = read_to_string(path).map_err(|error| error.to_string())?;
+    return GhostLap::from_json(&serialized).map_err(|error| error.to_string());
+}
+
+/// Loads the ghost given with `--ghost <file>`, or else the one saved next to the track.
+#[allow(unused_mut, unused_variables)]
+pub fn load_ghost(
+    config: Res<GhostConfig>,
+    road_network_config: Option<Res<RoadNetworkConfig>>,
+    mut ghosts: ResMut<Ghosts>,
+) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if !config.files {
+            return;
+        }
+
+        let args: Vec<String> = std::env::args().collect();
+        let path = match args.iter().position(|arg| arg == "--ghost") {
+            Some(index) => match args.get(index + 1) {
+                Some(path) => path.clone(),
+                _ => {
+                    error!("--ghost expects a file");
+                    return;
+                }
+            },
+            _ => {
+                let path = track_ghost_path(road_network_config.as_deref());
+                // No ghost for this track yet
+                if !Path::new(&path).exists() {
+                    return;
+                }
+                path
+            },
+        };
+
+        match load_ghost_file(&path) {
+            Ok(ghost) => {
+                info!("Loaded ghost {} ({})", path, format_lap_time(ghost.time));
+                ghosts.best = Some(ghost);
+            },
+            Err(error) => {
+                error!("Could not load ghost {}: {}", path, error);
+            }
+        }
+    }
+}
+
+fn track_ghost_path(road_network_config: Option<&RoadNetworkConfig>) -> String {
+    return match road_network_config.and_then(|config| config.road_network_path.as_ref()) {
+        Some(track_path) => ghost_path(track_path),
+        _ => String::from(GHOST_PATH),
+    };
+}
+
+/// Saves the ghost next to the track when a lap beats it.
+#[allow(unused_variables)]
+pub fn save_ghost_system(
+    config: Res<GhostConfig>,
+    road_network_config: Option<Res<RoadNetworkConfig>>,
+    mut ghosts: ResMut<Ghosts>,
+    toasts: Option<ResMut<Toasts>>,
+) {
+    if !ghosts.unsaved {
+        return;
+    }
+    ghosts.unsaved = false;
+    if !config.files {
+        return;
+    }
+    let ghost = match &ghosts.best {
+        Some(ghost) => ghost,
+        _ => {
+            return;
+        }
+    };
+
+    let path = track_ghost_path(road_network_config.as_deref());
+    let serialized = serde_json::to_string(ghost).unwrap();
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        web_sys::console::log_1(&serialized.into());
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        write(&path, serialized).unwrap();
+        info!("Saved ghost to {}", path);
+    }
+
+    if let Some(mut toasts) = toasts {
+        toasts.push(format!("Ghost saved ({})", format_lap_time(ghost.time)));
+    }
+}
+
+/// Attached to the ghost car.
+#[derive(Component)]
+pub struct GhostVehicle;
+
+/// Marks ghost meshes already given a translucent material.
+#[derive(Component)]
+pub struct GhostMaterial;
+
+/// The ghost car being played.
+#[derive(Default)]
+pub struct GhostPlayer {
+    /// The ghost car, and the vehicle it shows.
+    entity: Option<(Entity, String)>,
+    /// The ghost was recorded on the current track, checked on every lap
+    /// and for every new ghost.
+    on_track: bool,
+    /// Lap time of the ghost checked last.
+    checked: Option<f32>,
+    /// Translucent copies of the car materials.
+    materials: HashMap<Handle<StandardMaterial>, Handle<StandardMaterial>>,
+}
+
+/// Drives the ghost car along the ghost lap while a lap is timed.
+#[allow(clippy::too_many_arguments)]
+pub fn ghost_playback_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    roster: Res<VehicleRoster>,
+    game: Res<Game>,
+    race: Res<Race>,
+    input: Res<SimulationInput>,
+    ghosts: Res<Ghosts>,
+    mut player: ResMut<GhostPlayer>,
+    mut transforms: Query<&mut Transform, With<GhostVehicle>>,
+    mut race_events: EventReader<RaceEvent>,
+) {
+    let lap_started = race_events.iter().any(|event| matches!(event, RaceEvent::LapStarted(_)));
+    let best_time = ghosts.best.as_ref().map(|ghost| ghost.time);
+    if lap_started || player.checked != best_time {
+        player.on_track = match &ghosts.best {
+            Some(ghost) => ghost.track_id == game.road_network.track_id(),
+            _ => false,
+        };
+        player.checked = best_time;
+    }
+
+    let ghost = match (&ghosts.best, race.lap_time(input.tick)) {
+        (Some(ghost), Some(time)) if race.active && player.on_track => Some((ghost, time)),
+        _ => None,
+    };
+
+    // A new ghost may be another vehicle
+    let spawned_vehicle = player.entity.as_ref().map(|(_, vehicle)| vehicle.clone());
+    let wanted_vehicle = ghost.map(|(ghost, _)| ghost.vehicle.clone());
+    if spawned_vehicle.is_some() && spawned_vehicle != wanted_vehicle {
+        if let Some((entity, _)) = player.entity.take() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    let (ghost, time) = match ghost {
+        Some(ghost) => ghost,
+        _ => {
+            return;
+        }
+    };
+    let sample = match ghost.sample_at(time) {
+        Some(sample) => sample,
+        _ => {
+            return;
+        }
+    };
+    let transform = Transform::from_translation(sample.translation).with_rotation(sample.rotation);
+
+    match &player.entity {
+        Some((entity, _)) => {
+            if let Ok(mut ghost_transform) = transforms.get_mut(*entity) {
+                *ghost_transform = transform;
+            }
+        },
+        _ => {
+            let manifest = match roster.manifests.get(&ghost.vehicle) {
+                Some(manifest) => manifest,
+                _ => {
+                    return;
+                }
+            };
+            let entity = commands
+                .spawn_bundle(SceneBundle {
+                    scene: asset_server.load(&manifest.scene_path(&ghost.vehicle)),
+                    transform,
+                    ..default()
+                })
+                .insert(GhostVehicle)
+                .id();
+            player.entity = Some((entity, ghost.vehicle.clone()));
+        },
+    }
+}
+
+/// Swaps the materials of the ghost car's meshes for translucent copies,
+/// as its scene gets spawned.
+pub fn ghost_material_system(
+    mut commands: Commands,
+    config: Res<GhostConfig>,
+    mut player: ResMut<GhostPlayer>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    children: Query<&Children>,
+    mut meshes: Query<&mut Handle<StandardMaterial>, Without<GhostMaterial>>,
+) {
+    let mut pending = match &player.entity {
+        Some((entity, _)) => vec![*entity],
+        _ => {
+            return;
+        }
+    };
+
+    while let Some(entity) = pending.pop() {
+        if let Ok(entity_children) = children.get(entity) {
+            pending.extend(entity_children.iter());
+        }
+
+        let mut handle = match meshes.get_mut(entity) {
+            Ok(handle) => handle,
+            _ => {
+                continue;
+            }
+        };
+        let translucent = match player.materials.get(&*handle) {
+            Some(translucent) => translucent.clone(),
+            _ => {
+                let mut material = match materials.get(&*handle) {
+                    Some(material) => material.clone(),
+                    _ => {
+                        // Not loaded yet
+                        continue;
+                    }
+                };
+                material.base_color.set_a(config.alpha);
+                material.alpha_mode = AlphaMode::Blend;
+                let translucent = materials.add(material);
+                player.materials.insert(handle.clone(), translucent.clone());
+                translucent
+            },
+        };
+
+        *handle = translucent;
+        commands.entity(entity).insert(GhostMaterial);
+    }
+}
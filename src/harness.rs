@@ -0,0 +1,338 @@
+//! Headless simulation, for automated tests.
+//!
+//! Runs the same simulation schedule as the game without a window or a
+//! renderer, stepping one tick per `step`:
+//!
+//! ```no_run
+//! # use osd::harness::Harness;
+//! # use osd::drive_input::DriveInput;
+//! # use osd::road_import::{road_network_from_points, UpMode};
+//! # use bevy::prelude::*;
+//! let road = road_network_from_points(&[Vec3::ZERO, Vec3::new(0.0, 0.0, -200.0)], UpMode::Level);
+//! let mut harness = Harness::new();
+//! harness.load_road_network(road);
+//! let car = harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, -5.0));
+//! harness.set_drive_input(DriveInput { throttle: 1.0, ..Default::default() });
+//! harness.step(600);
+//! println!("{}", harness.lateral_distance_to_road(harness.transform(car).translation));
+//! ```
+
+use bevy::{
+    asset::AssetPlugin,
+    ecs::system::SystemState,
+    hierarchy::HierarchyPlugin,
+    prelude::*,
+    scene::ScenePlugin,
+    transform::TransformPlugin,
+};
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    ai::{AiConfig, AiDriver, AiPlugin, AiSettings},
+    drive_input::DriveInput,
+    editor::{EditorConfig, EditorPlugin},
+    game::Game,
+    ghost::{GhostConfig, GhostPlugin},
+    input_map::{Action, ActionState},
+    local_players::{LocalPlayerInputs, LocalPlayersConfig, LocalPlayersPlugin},
+    race::{RaceConfig, RacePlugin},
+    replay::{InputRecording, Replay, SimulationInput},
+    road_network_builder::{build_road_network, PendingRoadEdits, RoadEdit, RoadNetwork},
+    road_systems::{RoadNetworkConfig, RoadNetworkPlugin},
+    simulation::{SimulationConfig, SimulationPlugin},
+    traffic::{TrafficConfig, TrafficPlugin},
+    trailer::{road_train, Coupling, Trailer, COUPLING_TICKS},
+    vehicle::{spawn_vehicle, VehicleConfig, VehiclePlugin, VehicleRoster},
+};
+
+type RoadBuildParams = (
+    Commands<'static, 'static>,
+    ResMut<'static, Assets<Mesh>>,
+    ResMut<'static, Assets<StandardMaterial>>,
+);
+
+pub struct Harness {
+    pub app: App,
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Harness::new()
+    }
+}
+
+impl Harness {
+    /// An empty world with the vehicle manifests from `assets/`
+    /// and the default road following settings.
+    pub fn new() -> Self {
+        return Harness::with_players(1);
+    }
+
+    /// Like `new`, with `count` local players.
+    pub fn with_players(count: usize) -> Self {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(HierarchyPlugin)
+            .add_plugin(AssetPlugin)
+            .add_plugin(ScenePlugin)
+            .add_asset::<Mesh>()
+            .add_asset::<StandardMaterial>()
+            .add_plugin(SimulationPlugin {
+                config: SimulationConfig {
+                    // One tick per update, independent of the wall clock
+                    fixed_timestep: false,
+                    device_input: false,
+                    replays: false,
+                    ..default()
+                },
+            })
+            .add_plugin(LocalPlayersPlugin {
+                config: LocalPlayersConfig {
+                    split_screen: false,
+                    device_input: false,
+                    ..LocalPlayersConfig::with_players(count)
+                },
+            })
+            .add_plugin(RoadNetworkPlugin {
+                config: RoadNetworkConfig {
+                    road_network_path: None,
+                    road_follow_path: None,
+                    tuning_panel: false,
+                },
+            })
+            .add_plugin(VehiclePlugin {
+                config: VehicleConfig {
+                    player_vehicle: None,
+                    manifest_names: Some(vec![
+                        String::from("car_0001"),
+                        String::from("car_0002"),
+                        String::from("road_trailer_0001"),
+                    ]),
+                    ..default()
+                },
+            })
+            .add_plugin(EditorPlugin {
+                config: EditorConfig {
+                    trailers: Vec::new(),
+                    hitch_indicator: false,
+                    road_editor: false,
+                    road_profile_path: None,
+                },
+            })
+            .add_plugin(RacePlugin {
+                config: RaceConfig {
+                    best_laps_path: None,
+                    gates: false,
+                },
+            })
+            .add_plugin(AiPlugin {
+                config: AiConfig {
+                    count: 0,
+                    ..default()
+                },
+            })
+            .add_plugin(TrafficPlugin {
+                config: TrafficConfig {
+                    max_vehicles: 0,
+                    ..default()
+                },
+            })
+            .add_plugin(GhostPlugin {
+                config: GhostConfig {
+                    files: false,
+                    ..default()
+                },
+            });
+
+        // Run startup systems (manifest loading). This also simulates a tick
+        // of the empty world, start counting ticks and recording after it.
+        app.update();
+        app.insert_resource(SimulationInput::default())
+            .insert_resource(Replay::recording());
+
+        return Harness { app };
+    }
+
+    /// Replaces the road network and builds its mesh and collider.
+    pub fn load_road_network(&mut self, road_network: RoadNetwork) {
+        let world = &mut self.app.world;
+        let mut system_state: SystemState<RoadBuildParams> = SystemState::new(world);
+
+        let (commands, meshes, materials) = system_state.get_mut(world);
+        let entity = build_road_network(&road_network, commands, meshes, materials);
+        system_state.apply(world);
+
+        let mut game = world.resource_mut::<Game>();
+        game.road_network = road_network;
+        let previous = game.road_network_entity.replace(entity);
+        if let Some(previous) = previous {
+            world.entity_mut(previous).despawn_recursive();
+        }
+    }
+
+    /// Spawns any object of the roster.
+    pub fn spawn(&mut self, name: &str, transform: Transform) -> Entity {
+        let manifest = self.app.world.resource::<VehicleRoster>().manifests[name].clone();
+
+        let world = &mut self.app.world;
+        let mut system_state: SystemState<(Commands, Res<AssetServer>)> = SystemState::new(world);
+        let (mut commands, asset_server) = system_state.get_mut(world);
+        let entity = spawn_vehicle(&mut commands, &asset_server, name, &manifest, transform);
+        system_state.apply(world);
+
+        return entity;
+    }
+
+    /// Spawns the player's car, before the game spawns the default one.
+    pub fn spawn_car(&mut self, name: &str, transform: Transform) -> Entity {
+        return self.spawn_player_car(0, name, transform);
+    }
+
+    /// Spawns the car of a local player, see `with_players`.
+    pub fn spawn_player_car(&mut self, player: usize, name: &str, transform: Transform) -> Entity {
+        let entity = self.spawn(name, transform);
+        self.app.world.resource_mut::<Game>().players[player].car = Some(entity);
+        return entity;
+    }
+
+    /// Spawns a car driven by an `AiDriver`.
+    pub fn spawn_ai_car(&mut self, name: &str, transform: Transform, settings: AiSettings) -> Entity {
+        let entity = self.spawn(name, transform);
+        self.app.world.entity_mut(entity).insert(AiDriver::new(settings));
+        return entity;
+    }
+
+    /// Spawns a trailer. Use `tap(Action::ToggleTrailer)` to hitch it.
+    pub fn spawn_trailer(&mut self, name: &str, transform: Transform) -> Entity {
+        return self.spawn(name, transform);
+    }
+
+    /// Trailers hitched behind the player's car.
+    pub fn road_train(&mut self) -> Vec<Entity> {
+        let player_car = match self.game().player_car() {
+            Some(entity) => entity,
+            _ => {
+                return Vec::new();
+            }
+        };
+
+        let world = &mut self.app.world;
+        let mut system_state: SystemState<Query<(Entity, &ImpulseJoint), With<Trailer>>> = SystemState::new(world);
+        let joints = system_state.get(world);
+        return road_train(player_car, &joints);
+    }
+
+    /// Input used for every following tick.
+    pub fn set_drive_input(&mut self, drive_input: DriveInput) {
+        *self.app.world.resource_mut::<DriveInput>() = drive_input;
+    }
+
+    /// Input of a local player used for every following tick.
+    pub fn set_player_drive_input(&mut self, player: usize, drive_input: DriveInput) {
+        if player == 0 {
+            self.set_drive_input(drive_input);
+            return;
+        }
+        self.app.world.resource_mut::<LocalPlayerInputs>().players[player - 1].drive = drive_input;
+    }
+
+    /// Road editor change, applied on the next tick.
+    pub fn edit_road(&mut self, edit: RoadEdit) {
+        self.app.world.resource_mut::<PendingRoadEdits>().edits.push(edit);
+    }
+
+    pub fn press(&mut self, action: Action) {
+        self.app.world.resource_mut::<ActionState>().press(action);
+    }
+
+    pub fn release(&mut self, action: Action) {
+        self.app.world.resource_mut::<ActionState>().release(action);
+    }
+
+    /// Presses and releases an action, over two ticks.
+    pub fn tap(&mut self, action: Action) {
+        self.press(action);
+        self.step(1);
+        self.release(action);
+        self.step(1);
+    }
+
+    /// Taps `ToggleTrailer` and runs until the coupling is over.
+    /// Returns the number of ticks it took.
+    pub fn hitch(&mut self) -> usize {
+        self.tap(Action::ToggleTrailer);
+        return self.step_while(COUPLING_TICKS as usize + 1, |harness| {
+            let world = &mut harness.app.world;
+            world.query::<&Coupling>().iter(world).next().is_some()
+        });
+    }
+
+    /// Plays back recorded input instead of the scripted input.
+    pub fn play(&mut self, recording: InputRecording) {
+        self.app.insert_resource(Replay::playback(recording));
+    }
+
+    /// Runs `ticks` simulation ticks.
+    pub fn step(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.app.update();
+        }
+    }
+
+    /// Steps until `condition` is false or `ticks` have run.
+    /// Returns the number of ticks for which the condition held.
+    pub fn step_while(&mut self, ticks: usize, mut condition: impl FnMut(&mut Harness) -> bool) -> usize {
+        for tick in 0..ticks {
+            if !condition(self) {
+                return tick;
+            }
+            self.step(1);
+        }
+        return ticks;
+    }
+
+    pub fn tick(&self) -> u64 {
+        return self.app.world.resource::<SimulationInput>().tick;
+    }
+
+    pub fn game(&self) -> &Game {
+        return self.app.world.resource::<Game>();
+    }
+
+    pub fn transform(&self, entity: Entity) -> Transform {
+        return *self.app.world.get::<Transform>(entity).unwrap();
+    }
+
+    pub fn velocity(&self, entity: Entity) -> Velocity {
+        return *self.app.world.get::<Velocity>(entity).unwrap();
+    }
+
+    /// Distance from a point to the closest road segment, ignoring the height
+    /// above the road (vehicles hover over it).
+    pub fn lateral_distance_to_road(&self, point: Vec3) -> f32 {
+        let mut closest = f32::INFINITY;
+        for segment in &self.game().road_network.road_segments {
+            let ab = segment.b - segment.a;
+            if ab.length_squared() < f32::EPSILON {
+                continue;
+            }
+            let t = ((point - segment.a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0);
+            let delta = point - (segment.a + ab * t);
+            let up = segment.up.normalize();
+            let lateral = delta - up * delta.dot(up);
+            closest = closest.min(lateral.length());
+        }
+        return closest;
+    }
+}
+
+/// Points of a closed circle starting at the origin, heading towards -z (the default forward).
+pub fn circle(radius: f32, points: usize) -> Vec<Vec3> {
+    return (0..=points)
+        .map(|i| {
+            let angle = i as f32 / points as f32 * std::f32::consts::TAU;
+            Vec3::new(radius * (1.0 - angle.cos()), 0.0, -radius * angle.sin())
+        })
+        .collect();
+}
@@ -0,0 +1,210 @@
+//! Hover vehicle dynamics.
+//!
+//! Each vehicle has hover thrusters at the bottom corners of its collider.
+//! Thrusters cast rays towards the road and behave like springs with a
+//! damper, pushing the vehicle to `hover_height` and pulling it back when it
+//! gets too high (so vehicles stick to loops). A lateral grip force
+//! keeps the vehicle from sliding sideways and thrust fades out near top speed.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::{drive_input::DriveInput, game::Game, replay::SimulationInput, vehicle::{Vehicle, VehicleManifest}};
+
+/// Hover parameters, read from the `hover` object of a vehicle manifest.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HoverSettings {
+    /// Distance between the surface and the center of the vehicle at rest.
+    pub hover_height: f32,
+    /// Thrusters ignore surfaces further than this.
+    pub max_ray_length: f32,
+    /// Natural frequency of the suspension, in Hz.
+    pub spring_frequency: f32,
+    /// 1.0 is critically damped, lower values bounce.
+    pub damping_ratio: f32,
+    /// Maximum thruster force, as a multiple of the vehicle weight.
+    pub max_force_ratio: f32,
+    /// How fast sideways velocity is cancelled, per second.
+    pub lateral_grip: f32,
+    /// Forward thrust fades to zero at this speed.
+    pub max_speed: f32,
+    /// Backward thrust fades to zero at this speed.
+    pub max_reverse_speed: f32,
+}
+
+impl Default for HoverSettings {
+    fn default() -> Self {
+        HoverSettings {
+            hover_height: 5.5,
+            max_ray_length: 15.0,
+            spring_frequency: 1.5,
+            damping_ratio: 0.7,
+            max_force_ratio: 3.0,
+            lateral_grip: 2.0,
+            max_speed: 80.0,
+            max_reverse_speed: 25.0,
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone)]
+pub struct HoverVehicle {
+    pub settings: HoverSettings,
+    /// Thruster positions, in the vehicle's local space.
+    pub thrusters: Vec<Vec3>,
+    /// Whether any thruster reached a surface during the last update.
+    pub grounded: bool,
+}
+
+impl HoverVehicle {
+    /// One thruster under each bottom corner of the collider.
+    pub fn from_manifest(manifest: &VehicleManifest) -> Self {
+        let half = manifest.collider;
+        let thrusters = vec![
+            Vec3::new(-half.x, -half.y, -half.z),
+            Vec3::new(half.x, -half.y, -half.z),
+            Vec3::new(-half.x, -half.y, half.z),
+            Vec3::new(half.x, -half.y, half.z),
+        ];
+
+        return HoverVehicle {
+            settings: manifest.hover.clone(),
+            thrusters,
+            grounded: false,
+        };
+    }
+}
+
+/// How much of the thrust remains at a given speed.
+/// Quadratic falloff, full thrust when stopped or going the other way.
+pub fn thrust_curve(speed: f32, max_speed: f32) -> f32 {
+    if max_speed <= 0.0 {
+        return 0.0;
+    }
+    let ratio = speed.max(0.0) / max_speed;
+    return (1.0 - ratio * ratio).max(0.0);
+}
+
+pub fn hover_thruster_system(
+    rapier_context: Res<RapierContext>,
+    rapier_configuration: Res<RapierConfiguration>,
+    mut vehicles: Query<(&Transform, &Velocity, &ReadMassProperties, &mut HoverVehicle, &mut ExternalForce)>,
+) {
+    for (transform, velocity, mass_properties, mut hover, mut ext_force) in vehicles.iter_mut() {
+        let mass = mass_properties.0.mass;
+        if mass <= 0.0 {
+            // Mass is not known until the collider is initialized
+            continue;
+        }
+
+        let settings = &hover.settings;
+        let up = transform.up();
+        let down = -up;
+        let center_of_mass = transform.mul_vec3(mass_properties.0.local_center_of_mass);
+        let thruster_count = hover.thrusters.len() as f32;
+
+        // Suspension, split between thrusters
+        let angular_frequency = settings.spring_frequency * std::f32::consts::TAU;
+        let stiffness = mass * angular_frequency * angular_frequency / thruster_count;
+        let damping = 2.0 * settings.damping_ratio * mass * angular_frequency / thruster_count;
+        let weight = mass * rapier_configuration.gravity.length();
+        let max_force = settings.max_force_ratio * weight / thruster_count;
+
+        let mut grounded = false;
+
+        for thruster in &hover.thrusters {
+            let origin = transform.mul_vec3(*thruster);
+            let rest_length = settings.hover_height + thruster.y;
+
+            // Only hover over static geometry (roads, ground)
+            let hit = rapier_context.cast_ray(
+                origin,
+                down,
+                settings.max_ray_length,
+                true,
+                QueryFilter::only_fixed().exclude_sensors(),
+            );
+
+            let distance = match hit {
+                Some((_, distance)) => distance,
+                _ => {
+                    continue;
+                }
+            };
+            grounded = true;
+
+            let lever = origin - center_of_mass;
+            let point_velocity = velocity.linvel + velocity.angvel.cross(lever);
+            let compression = rest_length - distance;
+            let magnitude = (stiffness * compression - damping * point_velocity.dot(up))
+                .clamp(-max_force, max_force);
+
+            let force = up * magnitude;
+            ext_force.force += force;
+            ext_force.torque += lever.cross(force);
+        }
+
+        if grounded {
+            // Cancel sideways sliding
+            let right = transform.right();
+            let lateral_speed = velocity.linvel.dot(right);
+            ext_force.force -= right * lateral_speed * settings.lateral_grip * mass;
+        }
+
+        hover.grounded = grounded;
+    }
+}
+
+/// Applies the players' driving input to their vehicles.
+pub fn hover_drive_system(
+    input: Res<SimulationInput>,
+    game: Res<Game>,
+    mut vehicles: Query<(&Transform, &Velocity, &Vehicle, &HoverVehicle, &mut ExternalForce)>,
+) {
+    for (index, player) in game.players.iter().enumerate() {
+        let entity = match player.car {
+            Some(entity) => entity,
+            _ => {
+                continue;
+            }
+        };
+        let (transform, velocity, vehicle, hover, mut ext_force) = match vehicles.get_mut(entity) {
+            Ok(components) => components,
+            _ => {
+                continue;
+            }
+        };
+        apply_drive_input(transform, velocity, &vehicle.manifest, &hover.settings, &input.drive_of(index), &mut ext_force);
+    }
+}
+
+/// Thrust and torque of a hover vehicle driven with `drive_input`.
+/// Steering, roll and pitch are combined, so they can all be used at once.
+pub fn apply_drive_input(
+    transform: &Transform,
+    velocity: &Velocity,
+    manifest: &VehicleManifest,
+    settings: &HoverSettings,
+    drive_input: &DriveInput,
+    ext_force: &mut ExternalForce,
+) {
+    let forward = transform.forward();
+    let forward_speed = velocity.linvel.dot(forward);
+
+    if drive_input.throttle > 0.0 {
+        let curve = thrust_curve(forward_speed, settings.max_speed);
+        ext_force.force += forward * manifest.forward_thrust * drive_input.throttle * curve;
+    } else if drive_input.throttle < 0.0 {
+        let curve = thrust_curve(-forward_speed, settings.max_reverse_speed);
+        ext_force.force += forward * manifest.backward_thrust * drive_input.throttle * curve;
+    }
+
+    let local_torque = Vec3::new(
+        -manifest.pitch_torque * drive_input.pitch,
+        manifest.turn_torque * drive_input.steer,
+        manifest.roll_torque * drive_input.roll,
+    );
+    ext_force.torque += transform.rotation * local_torque;
+}
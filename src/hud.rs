@@ -0,0 +1,419 @@
+//! On screen display: speed, road and editor state, lap times, key hints, and
+//! toasts for editing actions (segments, macros, dumps, hitches, saves) and
+//! races.
+//!
+//! Other systems add toasts with `Toasts::push`, or send a `RoadNetworkEvent`,
+//! a `HitchEvent`, a `RaceEvent`, a `ReplayEvent` or a `RoadFollowEvent`.
+
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    camera::CameraRig,
+    editor::RoadEditor,
+    game::Game,
+    input_map::{Action, Binding, InputMap},
+    race::{format_delta, format_lap_time, BestLaps, Race, RaceEvent},
+    replay::{ReplayEvent, SimulationInput},
+    road_follow::RoadFollowEvent,
+    road_systems::RoadNetworkEvent,
+    trailer::{road_train, Coupling, HitchEvent, HitchEventKind, Trailer},
+};
+
+#[derive(Clone, Debug)]
+pub struct HudConfig {
+    /// Seconds a toast stays on screen.
+    pub toast_duration: f32,
+    /// Older toasts are dropped past this many.
+    pub max_toasts: usize,
+    /// Show the keys of the actions available in the current mode.
+    pub key_hints: bool,
+}
+
+impl Default for HudConfig {
+    fn default() -> Self {
+        HudConfig {
+            toast_duration: 3.0,
+            max_toasts: 4,
+            key_hints: true,
+        }
+    }
+}
+
+/// Requires Bevy's UI. Reads `RoadEditor`, `CameraRig` and `Race` when they exist.
+#[derive(Default)]
+pub struct HudPlugin {
+    pub config: HudConfig,
+}
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.config.clone())
+            .insert_resource(Toasts::new(self.config.toast_duration, self.config.max_toasts))
+            .init_resource::<Game>()
+            .init_resource::<InputMap>()
+            .init_resource::<SimulationInput>()
+            .add_event::<HitchEvent>()
+            .add_event::<RoadNetworkEvent>()
+            .add_event::<RaceEvent>()
+            .add_event::<ReplayEvent>()
+            .add_event::<RoadFollowEvent>()
+            .add_startup_system(setup_hud)
+            .add_system(toast_events_system)
+            .add_system(hud_system)
+            .add_system(toast_system
+                        .after(toast_events_system));
+    }
+}
+
+struct Toast {
+    message: String,
+    remaining: f32,
+}
+
+/// Short messages shown for a few seconds, newest last.
+pub struct Toasts {
+    toasts: VecDeque<Toast>,
+    duration: f32,
+    max_toasts: usize,
+}
+
+impl Default for Toasts {
+    fn default() -> Self {
+        let config = HudConfig::default();
+        return Toasts::new(config.toast_duration, config.max_toasts);
+    }
+}
+
+impl Toasts {
+    pub fn new(duration: f32, max_toasts: usize) -> Self {
+        return Toasts {
+            toasts: VecDeque::new(),
+            duration,
+            max_toasts,
+        };
+    }
+
+    pub fn push(&mut self, message: impl Into<String>) {
+        self.toasts.push_back(Toast {
+            message: message.into(),
+            remaining: self.duration,
+        });
+        while self.toasts.len() > self.max_toasts {
+            self.toasts.pop_front();
+        }
+    }
+
+    /// Ages the toasts by `dt` seconds and drops the expired ones.
+    pub fn update(&mut self, dt: f32) {
+        for toast in self.toasts.iter_mut() {
+            toast.remaining -= dt;
+        }
+        self.toasts.retain(|toast| toast.remaining > 0.0);
+    }
+
+    pub fn messages(&self) -> Vec<&str> {
+        return self.toasts.iter().map(|toast| toast.message.as_str()).collect();
+    }
+}
+
+/// Text entities of the HUD.
+pub struct Hud {
+    status: Entity,
+    hints: Entity,
+    toasts: Entity,
+}
+
+fn setup_hud(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/DejaVuSansMono.ttf"),
+        font_size: 16.0,
+        color: Color::WHITE,
+    };
+    let absolute = |position: UiRect<Val>| Style {
+        position_type: PositionType::Absolute,
+        position,
+        ..default()
+    };
+
+    let status = commands
+        .spawn_bundle(
+            TextBundle::from_section("", text_style.clone())
+                .with_style(absolute(UiRect { top: Val::Px(10.0), left: Val::Px(10.0), ..default() })),
+        )
+        .id();
+
+    let hints = commands
+        .spawn_bundle(
+            TextBundle::from_section("", TextStyle { color: Color::GRAY, ..text_style.clone() })
+                .with_style(absolute(UiRect { bottom: Val::Px(10.0), left: Val::Px(10.0), ..default() })),
+        )
+        .id();
+
+    // Centered at the top of the screen
+    let mut toasts = None;
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect { top: Val::Px(10.0), left: Val::Px(0.0), ..default() },
+                size: Size::new(Val::Percent(100.0), Val::Auto),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            toasts = Some(parent
+                .spawn_bundle(TextBundle::from_section("", TextStyle { color: Color::YELLOW, ..text_style }))
+                .id());
+        });
+
+    commands.insert_resource(Hud {
+        status,
+        hints,
+        toasts: toasts.unwrap(),
+    });
+}
+
+/// Key of an action in the input map, for the hints.
+pub fn binding_label(input_map: &InputMap, action: Action) -> String {
+    let bindings = match input_map.bindings.get(&action) {
+        Some(bindings) => bindings,
+        _ => {
+            return String::from("-");
+        }
+    };
+
+    // Prefer keys, the hints are about the keyboard
+    let binding = bindings
+        .iter()
+        .find(|binding| matches!(binding, Binding::Key(_)))
+        .or_else(|| bindings.first());
+    return match binding {
+        Some(Binding::Key(key_code)) => format!("{:?}", key_code),
+        Some(Binding::GamepadButton(button)) => format!("{:?}", button),
+        _ => String::from("-"),
+    };
+}
+
+fn toast_events_system(
+    mut hitch_events: EventReader<HitchEvent>,
+    mut road_network_events: EventReader<RoadNetworkEvent>,
+    mut race_events: EventReader<RaceEvent>,
+    mut replay_events: EventReader<ReplayEvent>,
+    mut road_follow_events: EventReader<RoadFollowEvent>,
+    mut toasts: ResMut<Toasts>,
+) {
+    for event in hitch_events.iter() {
+        toasts.push(match event.kind {
+            HitchEventKind::CouplingStarted => "Coupling trailer...",
+            HitchEventKind::Hitched => "Trailer attached",
+            HitchEventKind::Unhitched => "Trailer detached",
+            HitchEventKind::CouplingFailed => "Coupling failed",
+            HitchEventKind::Broke => "Hitch broke!",
+        });
+    }
+
+    for event in road_network_events.iter() {
+        match event {
+            // Too frequent to be worth a toast, the segment count shows it
+            RoadNetworkEvent::SegmentInserted => {},
+            RoadNetworkEvent::Dumped => toasts.push("Road network dumped"),
+            RoadNetworkEvent::Cleared => toasts.push("Road network cleared"),
+            RoadNetworkEvent::MacroRecorded(index) => toasts.push(format!("Recorded macro {}", index + 1)),
+            RoadNetworkEvent::MacroPlayed(index, segments) => {
+                toasts.push(format!("Played macro {} ({} segments)", index + 1, segments));
+            },
+            RoadNetworkEvent::ProfileApplied => toasts.push("Road profile applied"),
+            RoadNetworkEvent::ProfileFailed => toasts.push("Could not load the road profile"),
+        }
+    }
+
+    let with_delta = |time: f32, delta: Option<f32>| match delta {
+        Some(delta) => format!("{} ({})", format_lap_time(time), format_delta(delta)),
+        _ => format_lap_time(time),
+    };
+    for event in race_events.iter() {
+        match *event {
+            RaceEvent::Started => toasts.push("Race mode"),
+            RaceEvent::Stopped => toasts.push("Race mode off"),
+            RaceEvent::LapStarted(lap) => toasts.push(format!("Lap {}", lap)),
+            RaceEvent::CheckpointPassed { index, split, delta } => {
+                toasts.push(format!("Checkpoint {}: {}", index + 1, with_delta(split, delta)));
+            },
+            RaceEvent::LapCompleted { time, delta, best } => {
+                let record = if best { ", best lap!" } else { "" };
+                toasts.push(format!("Lap time {}{}", with_delta(time, delta), record));
+            },
+            RaceEvent::MissedCheckpoint => toasts.push("Missed a checkpoint, lap not counted"),
+            RaceEvent::CheckpointsChanged(count) => toasts.push(format!("{} checkpoints", count)),
+        }
+    }
+
+    for event in replay_events.iter() {
+        match *event {
+            ReplayEvent::Saved(ticks) => toasts.push(format!("Replay saved ({} ticks)", ticks)),
+            ReplayEvent::NotRecording => toasts.push("Not recording, start with --record"),
+        }
+    }
+
+    for event in road_follow_events.iter() {
+        match event {
+            RoadFollowEvent::TuningSaved => toasts.push("Road follow tuning saved"),
+        }
+    }
+}
+
+fn toast_system(
+    time: Res<Time>,
+    hud: Res<Hud>,
+    mut toasts: ResMut<Toasts>,
+    mut texts: Query<&mut Text>,
+) {
+    toasts.update(time.delta_seconds());
+
+    let mut text = match texts.get_mut(hud.toasts) {
+        Ok(text) => text,
+        _ => {
+            return;
+        }
+    };
+    let content = toasts.messages().join("\n");
+    if text.sections[0].value != content {
+        text.sections[0].value = content;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn hud_system(
+    config: Res<HudConfig>,
+    hud: Res<Hud>,
+    game: Res<Game>,
+    input_map: Res<InputMap>,
+    road_editor: Option<Res<RoadEditor>>,
+    rigs: Query<&CameraRig>,
+    race: Option<Res<Race>>,
+    best_laps: Option<Res<BestLaps>>,
+    input: Res<SimulationInput>,
+    bodies: Query<(&Transform, &Velocity)>,
+    joints: Query<(Entity, &ImpulseJoint), With<Trailer>>,
+    couplings: Query<&Coupling>,
+    mut texts: Query<&mut Text>,
+) {
+    let (editing, selected) = match road_editor {
+        Some(road_editor) => (road_editor.active, road_editor.selected),
+        _ => (false, None),
+    };
+    let road_network = &game.road_network;
+
+    let mut status = String::new();
+    if let Some(player_car) = game.player_car() {
+        if let Ok((transform, velocity)) = bodies.get(player_car) {
+            status.push_str(&format!("Speed     {:>6.0} km/h\n", velocity.linvel.length() * 3.6));
+            match road_network.distance_to(transform.translation) {
+                Some(distance) => status.push_str(&format!("To road   {:>6.1} m\n", distance)),
+                _ => status.push_str("To road        -\n"),
+            }
+        }
+
+        let train = road_train(player_car, &joints);
+        let trailers = if !couplings.is_empty() {
+            String::from("coupling")
+        } else if train.is_empty() {
+            String::from("none")
+        } else {
+            format!("{} attached", train.len())
+        };
+        status.push_str(&format!("Trailers  {}\n", trailers));
+    }
+
+    let mode = if editing { "Road editor" } else { "Driving" };
+    status.push_str(&format!("Mode      {}\n", mode));
+    if let (true, Some(node)) = (editing, selected) {
+        status.push_str(&format!("Selected  {:.1} {:.1} {:.1}\n", node.x, node.y, node.z));
+    }
+    if let Some(rig) = game.camera().and_then(|camera| rigs.get(camera).ok()) {
+        status.push_str(&format!("Camera    {:?}\n", rig.mode));
+    }
+
+    status.push_str(&format!(
+        "Road      {} segments, {:.0} m\n",
+        road_network.road_segments.len(),
+        road_network.length()
+    ));
+    match road_network.last_position {
+        Some(last_position) => status.push_str(&format!(
+            "Last node {:.1} {:.1} {:.1}\n",
+            last_position.x, last_position.y, last_position.z
+        )),
+        _ => status.push_str("Last node -\n"),
+    }
+
+    if let Some(race) = race.filter(|race| race.active) {
+        let lap_time = match race.lap_time(input.tick) {
+            Some(time) => format_lap_time(time),
+            _ => String::from("cross the line"),
+        };
+        let best = best_laps
+            .and_then(|best_laps| best_laps.tracks.get(&road_network.track_id()).map(|record| record.time));
+        let optional_time = |time: Option<f32>| time.map(format_lap_time).unwrap_or_else(|| String::from("-"));
+        status.push_str(&format!("Lap       {} {}\n", race.laps + 1, lap_time));
+        status.push_str(&format!("Last      {}\n", optional_time(race.last_lap)));
+        status.push_str(&format!("Best      {}\n", optional_time(best)));
+        status.push_str(&format!("Checkpoint {}/{}\n", race.next_checkpoint, road_network.checkpoints.len()));
+    }
+
+    // Play macro uses the last one
+    status.push_str(&format!("Macros    {}\n", road_network.macros.len()));
+    let macro_count = road_network.macros.len();
+    for (index, road_macro) in road_network.macros.iter().enumerate() {
+        let marker = if index + 1 == macro_count { ">" } else { " " };
+        status.push_str(&format!("{} {:>2}: {} segments\n", marker, index + 1, road_macro.road_segments.len()));
+    }
+
+    let key = |action: Action| binding_label(&input_map, action);
+    let hints = if !config.key_hints {
+        String::new()
+    } else if editing {
+        format!(
+            "Click: add/select node  Drag: move node  Wheel: height  Arrows: move along axis  Rings: heading/bank  {}/{}/{}/{}: pan  {}: exit editor",
+            key(Action::CameraForward),
+            key(Action::CameraLeft),
+            key(Action::CameraBackward),
+            key(Action::CameraRight),
+            key(Action::ToggleRoadEditor),
+        )
+    } else {
+        format!(
+            "{}: segment  {}: trailer  {}: unhitch  {}: record macro  {}: play macro  {}: dump  {}: clear  {}: road profile  {}: checkpoint  {}: race  {}: road editor  {}: camera  {}: save replay",
+            key(Action::InsertSegment),
+            key(Action::ToggleTrailer),
+            key(Action::UnhitchTrailer),
+            key(Action::RecordMacro),
+            key(Action::PlayMacro),
+            key(Action::DumpRoadNetwork),
+            key(Action::ClearRoadNetwork),
+            key(Action::ApplyRoadProfile),
+            key(Action::PlaceCheckpoint),
+            key(Action::ToggleRaceMode),
+            key(Action::ToggleRoadEditor),
+            key(Action::NextCameraMode),
+            key(Action::SaveReplay),
+        )
+    };
+
+    for (entity, content) in [(hud.status, status), (hud.hints, hints)] {
+        if let Ok(mut text) = texts.get_mut(entity) {
+            if text.sections[0].value != content {
+                text.sections[0].value = content;
+            }
+        }
+    }
+}
@@ -0,0 +1,486 @@
+//! Action based input, loaded from `assets/input_map.json`.
+//!
+//! Systems ask `ActionState` about actions (`Action::InsertSegment`)
+//! instead of keys, so controls can be rebound without touching code.
+
+use std::collections::{HashMap, HashSet};
+
+// This is only for native builds
+#[allow(unused_imports)]
+use std::fs::read_to_string;
+
+use bevy::{
+    input::{keyboard::KeyCode, Input},
+    prelude::*,
+};
+use serde::{Serialize, Deserialize};
+
+use crate::simulation::SimulationConfig;
+
+// This is only for wasm builds
+#[allow(unused_imports)]
+use crate::windowmailer;
+
+pub const INPUT_MAP_PATH: &str = "assets/input_map.json";
+
+// The code here is not used in native builds
+#[allow(dead_code)]
+const INPUT_MAP_CHANNEL: &str = "INPUT_MAP_DATA";
+
+/// Group of actions that are enabled or disabled together.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputContext {
+    Driving,
+    Editing,
+    /// Camera modes and free-fly camera.
+    Camera,
+    /// Developer tools, like the road follow tuning panel.
+    Debug,
+}
+
+impl InputContext {
+    /// Whether actions of this context change the simulation,
+    /// and so are part of replays.
+    pub fn is_simulated(&self) -> bool {
+        match self {
+            InputContext::Driving | InputContext::Editing => true,
+            InputContext::Camera | InputContext::Debug => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Action {
+    // Driving
+    Accelerate,
+    Brake,
+    SteerLeft,
+    SteerRight,
+    RollLeft,
+    RollRight,
+    PitchDown,
+    PitchUp,
+    SwitchVehicle,
+    ToggleRaceMode,
+
+    // Editing
+    InsertSegment,
+    ToggleTrailer,
+    UnhitchTrailer,
+    DumpRoadNetwork,
+    ClearRoadNetwork,
+    RecordMacro,
+    PlayMacro,
+    ToggleRoadEditor,
+    ApplyRoadProfile,
+    PlaceCheckpoint,
+
+    // Camera
+    NextCameraMode,
+    CameraForward,
+    CameraBackward,
+    CameraLeft,
+    CameraRight,
+    CameraUp,
+    CameraDown,
+
+    // Debug
+    ToggleTuningPanel,
+    TuningNextClass,
+    TuningPreviousParameter,
+    TuningNextParameter,
+    TuningIncrease,
+    TuningDecrease,
+    SaveTuning,
+    SaveReplay,
+}
+
+impl Action {
+    pub fn context(&self) -> InputContext {
+        match self {
+            Action::Accelerate
+            | Action::Brake
+            | Action::SteerLeft
+            | Action::SteerRight
+            | Action::RollLeft
+            | Action::RollRight
+            | Action::PitchDown
+            | Action::PitchUp
+            | Action::SwitchVehicle
+            | Action::ToggleRaceMode => InputContext::Driving,
+
+            Action::InsertSegment
+            | Action::ToggleTrailer
+            | Action::UnhitchTrailer
+            | Action::DumpRoadNetwork
+            | Action::ClearRoadNetwork
+            | Action::RecordMacro
+            | Action::PlayMacro
+            | Action::ToggleRoadEditor
+            | Action::ApplyRoadProfile
+            | Action::PlaceCheckpoint => InputContext::Editing,
+
+            Action::NextCameraMode
+            | Action::CameraForward
+            | Action::CameraBackward
+            | Action::CameraLeft
+            | Action::CameraRight
+            | Action::CameraUp
+            | Action::CameraDown => InputContext::Camera,
+
+            Action::ToggleTuningPanel
+            | Action::TuningNextClass
+            | Action::TuningPreviousParameter
+            | Action::TuningNextParameter
+            | Action::TuningIncrease
+            | Action::TuningDecrease
+            | Action::SaveTuning
+            | Action::SaveReplay => InputContext::Debug,
+        }
+    }
+}
+
+/// A physical input that triggers an action.
+///
+/// In the config file: `{"Key": "W"}` or `{"GamepadButton": "South"}`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(KeyCode),
+    GamepadButton(GamepadButtonType),
+}
+
+/// Two actions sharing a binding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BindingConflict {
+    pub binding: Binding,
+    pub first: Action,
+    pub second: Action,
+    /// Conflicts inside a context make one of the actions unreachable.
+    /// Across contexts, they only matter while both contexts are active.
+    pub same_context: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InputMap {
+    pub bindings: HashMap<Action, Vec<Binding>>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let key = |key_code: KeyCode| vec![Binding::Key(key_code)];
+        let bindings: HashMap<Action, Vec<Binding>> = HashMap::from([
+            (Action::Accelerate, key(KeyCode::W)),
+            (Action::Brake, key(KeyCode::S)),
+            (Action::SteerLeft, key(KeyCode::Left)),
+            (Action::SteerRight, key(KeyCode::Right)),
+            (Action::RollLeft, key(KeyCode::A)),
+            (Action::RollRight, key(KeyCode::D)),
+            (Action::PitchDown, key(KeyCode::Up)),
+            (Action::PitchUp, key(KeyCode::Down)),
+            (Action::SwitchVehicle, key(KeyCode::V)),
+            (Action::ToggleRaceMode, key(KeyCode::N)),
+            (Action::InsertSegment, key(KeyCode::E)),
+            (Action::ToggleTrailer, key(KeyCode::T)),
+            (Action::UnhitchTrailer, key(KeyCode::Y)),
+            (Action::DumpRoadNetwork, key(KeyCode::O)),
+            (Action::ClearRoadNetwork, key(KeyCode::X)),
+            (Action::RecordMacro, key(KeyCode::R)),
+            (Action::PlayMacro, key(KeyCode::P)),
+            (Action::ToggleRoadEditor, key(KeyCode::Tab)),
+            (Action::ApplyRoadProfile, key(KeyCode::B)),
+            (Action::PlaceCheckpoint, key(KeyCode::G)),
+            (Action::NextCameraMode, key(KeyCode::C)),
+            (Action::CameraForward, key(KeyCode::I)),
+            (Action::CameraBackward, key(KeyCode::K)),
+            (Action::CameraLeft, key(KeyCode::J)),
+            (Action::CameraRight, key(KeyCode::L)),
+            (Action::CameraUp, key(KeyCode::Space)),
+            (Action::CameraDown, key(KeyCode::LShift)),
+            (Action::ToggleTuningPanel, key(KeyCode::F2)),
+            (Action::TuningNextClass, key(KeyCode::F3)),
+            (Action::TuningPreviousParameter, key(KeyCode::PageUp)),
+            (Action::TuningNextParameter, key(KeyCode::PageDown)),
+            (Action::TuningIncrease, vec![Binding::Key(KeyCode::Equals), Binding::Key(KeyCode::NumpadAdd)]),
+            (Action::TuningDecrease, vec![Binding::Key(KeyCode::Minus), Binding::Key(KeyCode::NumpadSubtract)]),
+            (Action::SaveTuning, key(KeyCode::F4)),
+            (Action::SaveReplay, key(KeyCode::F6)),
+        ]);
+
+        return InputMap { bindings };
+    }
+}
+
+impl InputMap {
+    pub fn from_json(json: &str) -> Result<InputMap, serde_json::Error> {
+        return serde_json::from_str(json);
+    }
+
+    /// Lists every binding used by more than one action.
+    pub fn conflicts(&self) -> Vec<BindingConflict> {
+        let mut actions_by_binding: HashMap<Binding, Vec<Action>> = HashMap::new();
+        for (action, bindings) in &self.bindings {
+            for binding in bindings {
+                let actions = actions_by_binding.entry(*binding).or_default();
+                if !actions.contains(action) {
+                    actions.push(*action);
+                }
+            }
+        }
+
+        let mut conflicts: Vec<BindingConflict> = Vec::new();
+        for (binding, mut actions) in actions_by_binding {
+            // Stable output, HashMap order is random
+            actions.sort_by_key(|action| format!("{:?}", action));
+            for (index, first) in actions.iter().enumerate() {
+                for second in &actions[index + 1..] {
+                    conflicts.push(BindingConflict {
+                        binding,
+                        first: *first,
+                        second: *second,
+                        same_context: first.context() == second.context(),
+                    });
+                }
+            }
+        }
+
+        return conflicts;
+    }
+
+    /// Fills actions missing from a user config with the default bindings,
+    /// so an old config file doesn't leave new actions unbound.
+    pub fn with_defaults(mut self) -> InputMap {
+        for (action, bindings) in InputMap::default().bindings {
+            self.bindings.entry(action).or_insert(bindings);
+        }
+        return self;
+    }
+}
+
+/// State of every action for the current frame.
+#[derive(Debug, Clone)]
+pub struct ActionState {
+    pub active_contexts: HashSet<InputContext>,
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    just_released: HashSet<Action>,
+}
+
+impl Default for ActionState {
+    fn default() -> Self {
+        ActionState {
+            active_contexts: HashSet::from([InputContext::Driving, InputContext::Editing, InputContext::Camera, InputContext::Debug]),
+            pressed: HashSet::new(),
+            just_pressed: HashSet::new(),
+            just_released: HashSet::new(),
+        }
+    }
+}
+
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        return self.pressed.contains(&action);
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        return self.just_pressed.contains(&action);
+    }
+
+    pub fn just_released(&self, action: Action) -> bool {
+        return self.just_released.contains(&action);
+    }
+
+    pub fn set_context_active(&mut self, context: InputContext, active: bool) {
+        if active {
+            self.active_contexts.insert(context);
+        } else {
+            self.active_contexts.remove(&context);
+        }
+    }
+
+    /// Marks an action as pressed. Also used by scripted input.
+    pub fn press(&mut self, action: Action) {
+        if self.pressed.insert(action) {
+            self.just_pressed.insert(action);
+        }
+    }
+
+    /// Marks an action as released. Also used by scripted input.
+    pub fn release(&mut self, action: Action) {
+        if self.pressed.remove(&action) {
+            self.just_released.insert(action);
+        }
+    }
+
+    /// Releases an action without reporting it as just released,
+    /// so disabling a context doesn't trigger its actions.
+    pub fn cancel(&mut self, action: Action) {
+        self.pressed.remove(&action);
+        self.just_pressed.remove(&action);
+    }
+
+    /// Pressed actions, sorted.
+    pub fn pressed_actions(&self) -> Vec<Action> {
+        let mut actions: Vec<Action> = self.pressed.iter().cloned().collect();
+        actions.sort();
+        return actions;
+    }
+
+    /// Presses exactly the given actions and releases the others,
+    /// updating the just pressed/released state like a new frame would.
+    pub fn set_pressed(&mut self, actions: &[Action]) {
+        self.clear_just_changed();
+        for action in self.pressed_actions() {
+            if !actions.contains(&action) {
+                self.release(action);
+            }
+        }
+        for action in actions {
+            self.press(*action);
+        }
+    }
+
+    pub fn clear_just_changed(&mut self) {
+        self.just_pressed.clear();
+        self.just_released.clear();
+    }
+}
+
+fn log_conflicts(input_map: &InputMap) {
+    for conflict in input_map.conflicts() {
+        if conflict.same_context {
+            error!(
+                "{:?} is bound to both {:?} and {:?} in the same context, only one of them can be used.",
+                conflict.binding, conflict.first, conflict.second
+            );
+        } else {
+            warn!(
+                "{:?} is bound to both {:?} and {:?}, they will trigger together while both contexts are active.",
+                conflict.binding, conflict.first, conflict.second
+            );
+        }
+    }
+}
+
+fn parse_input_map(serialized_input_map: &str) -> InputMap {
+    let input_map = match InputMap::from_json(serialized_input_map) {
+        Ok(input_map) => input_map.with_defaults(),
+        Err(error) => {
+            warn!("Invalid input map, using default controls: {}", error);
+            InputMap::default()
+        }
+    };
+    log_conflicts(&input_map);
+    return input_map;
+}
+
+#[allow(unused_mut, unused_variables)]
+pub fn load_input_map(config: Res<SimulationConfig>, mut input_map: ResMut<InputMap>) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        windowmailer::fetch_to_channel(config.input_map_path.clone(), String::from(INPUT_MAP_CHANNEL));
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        // A missing file just means default controls
+        if let Ok(serialized_input_map) = read_to_string(&config.input_map_path) {
+            *input_map = parse_input_map(&serialized_input_map);
+        }
+    }
+}
+
+#[allow(unused_mut, unused_variables)]
+// Waits for the input map fetched by wasm builds
+pub fn input_map_load_check(mut input_map: ResMut<InputMap>) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        if windowmailer::message_count(String::from(INPUT_MAP_CHANNEL)) == 0 {
+            return;
+        }
+        let serialized_input_map: String = windowmailer::read_message(String::from(INPUT_MAP_CHANNEL));
+        *input_map = parse_input_map(&serialized_input_map);
+    }
+}
+
+/// Devices a local player reads their input from, see `local_players`.
+/// As a resource, the devices of the first player.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputDevices {
+    pub keyboard: bool,
+    /// Ids of the gamepads, None reads every gamepad.
+    pub gamepads: Option<Vec<usize>>,
+}
+
+impl Default for InputDevices {
+    fn default() -> Self {
+        InputDevices {
+            keyboard: true,
+            gamepads: None,
+        }
+    }
+}
+
+impl InputDevices {
+    pub fn gamepad(id: usize) -> Self {
+        return InputDevices {
+            keyboard: false,
+            gamepads: Some(vec![id]),
+        };
+    }
+
+    /// The connected gamepads among these devices.
+    pub fn connected_gamepads(&self, gamepads: &Gamepads) -> Vec<Gamepad> {
+        return gamepads
+            .iter()
+            .filter(|gamepad| match &self.gamepads {
+                Some(ids) => ids.contains(&gamepad.id),
+                _ => true,
+            })
+            .copied()
+            .collect();
+    }
+}
+
+/// Updates `action_state` from the keys and gamepad buttons of `devices`.
+pub fn read_actions(
+    action_state: &mut ActionState,
+    input_map: &InputMap,
+    devices: &InputDevices,
+    keyboard_input: &Input<KeyCode>,
+    gamepads: &Gamepads,
+    gamepad_buttons: &Input<GamepadButton>,
+) {
+    action_state.clear_just_changed();
+    let connected_gamepads = devices.connected_gamepads(gamepads);
+
+    for (action, bindings) in &input_map.bindings {
+        if !action_state.active_contexts.contains(&action.context()) {
+            action_state.cancel(*action);
+            continue;
+        }
+
+        let pressed = bindings.iter().any(|binding| match binding {
+            Binding::Key(key_code) => devices.keyboard && keyboard_input.pressed(*key_code),
+            Binding::GamepadButton(button_type) => connected_gamepads
+                .iter()
+                .any(|gamepad| gamepad_buttons.pressed(GamepadButton::new(*gamepad, *button_type))),
+        });
+
+        if pressed {
+            action_state.press(*action);
+        } else {
+            action_state.release(*action);
+        }
+    }
+}
+
+/// Translates keys and gamepad buttons to actions, for the first player.
+pub fn action_state_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepads: Res<Gamepads>,
+    input_map: Res<InputMap>,
+    devices: Res<InputDevices>,
+    mut action_state: ResMut<ActionState>,
+) {
+    read_actions(&mut action_state, &input_map, &devices, &keyboard_input, &gamepads, &gamepad_buttons);
+}
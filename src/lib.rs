@@ -1,4 +1,69 @@
+//! Road building and hover vehicles for Bevy.
+//!
+//! The game is split in plugins that can be embedded in other Bevy apps:
+//!
+//! ```no_run
+//! # use bevy::prelude::*;
+//! use osd::{
+//!     ai::AiPlugin,
+//!     camera::CameraPlugin,
+//!     editor::EditorPlugin,
+//!     ghost::GhostPlugin,
+//!     hud::HudPlugin,
+//!     local_players::LocalPlayersPlugin,
+//!     race::RacePlugin,
+//!     road_systems::{RoadNetworkConfig, RoadNetworkPlugin},
+//!     simulation::SimulationPlugin,
+//!     traffic::TrafficPlugin,
+//!     vehicle::VehiclePlugin,
+//! };
+//!
+//! App::new()
+//!     .add_plugins(DefaultPlugins)
+//!     // Must come first, the other plugins add systems to its schedule
+//!     .add_plugin(SimulationPlugin::default())
+//!     .add_plugin(LocalPlayersPlugin::default())
+//!     .add_plugin(RoadNetworkPlugin {
+//!         config: RoadNetworkConfig {
+//!             road_network_path: Some(String::from("assets/my_roads.json")),
+//!             ..default()
+//!         },
+//!     })
+//!     .add_plugin(VehiclePlugin::default())
+//!     .add_plugin(AiPlugin::default())
+//!     .add_plugin(TrafficPlugin::default())
+//!     .add_plugin(CameraPlugin::default())
+//!     .add_plugin(EditorPlugin::default())
+//!     .add_plugin(RacePlugin::default())
+//!     .add_plugin(GhostPlugin::default())
+//!     .add_plugin(HudPlugin::default())
+//!     .run();
+//! ```
+
 pub mod game;
 pub mod windowmailer;
 pub mod road_network_builder;
 pub mod road_systems;
+pub mod road_import;
+pub mod vehicle;
+pub mod drive_input;
+pub mod input_map;
+pub mod hover;
+pub mod road_follow;
+pub mod simulation;
+pub mod replay;
+#[cfg(any(test, feature = "harness"))]
+pub mod harness;
+pub mod camera;
+pub mod editor;
+pub mod trailer;
+pub mod hud;
+pub mod road_gizmo;
+pub mod road_profile;
+pub mod race;
+pub mod ghost;
+pub mod ai;
+pub mod traffic;
+pub mod local_players;
+pub mod websocket;
+pub mod net;
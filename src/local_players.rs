@@ -0,0 +1,244 @@
+//! Local multiplayer: several players on the same machine.
+//!
+//! Every player has an entry in `Game::players`, with their own vehicle and
+//! camera. The first player reads their input into the `ActionState` and
+//! `DriveInput` resources like a single player does; the others read theirs
+//! into `LocalPlayerInputs`, from their own `InputDevices`. Their input goes
+//! through `SimulationInput` and replays along with the first player's.
+//!
+//! With `split_screen`, the window is divided between the players' cameras.
+
+use bevy::{
+    core_pipeline::clear_color::ClearColorConfig,
+    input::InputSystem,
+    prelude::*,
+    render::camera::Viewport,
+};
+
+use crate::{
+    drive_input::{read_drive_input, DriveInput, DriveInputSettings},
+    game::{Game, Player},
+    input_map::{input_map_load_check, read_actions, ActionState, InputContext, InputDevices, InputMap},
+};
+
+#[derive(Clone, Debug)]
+pub struct LocalPlayersConfig {
+    /// Input devices of each player, one entry per player.
+    pub devices: Vec<InputDevices>,
+    /// Divide the window between the players' cameras.
+    pub split_screen: bool,
+    /// Read the input of the players after the first from their devices.
+    /// When false, set `LocalPlayerInputs` yourself.
+    pub device_input: bool,
+}
+
+impl Default for LocalPlayersConfig {
+    fn default() -> Self {
+        LocalPlayersConfig {
+            devices: vec![InputDevices::default()],
+            split_screen: true,
+            device_input: true,
+        }
+    }
+}
+
+impl LocalPlayersConfig {
+    /// `count` players: the first one on the keyboard, the others on a gamepad each.
+    /// A single player uses every device.
+    pub fn with_players(count: usize) -> Self {
+        let devices = match count {
+            0 | 1 => vec![InputDevices::default()],
+            _ => (0..count)
+                .map(|player| match player {
+                    0 => InputDevices {
+                        keyboard: true,
+                        gamepads: Some(Vec::new()),
+                    },
+                    _ => InputDevices::gamepad(player - 1),
+                })
+                .collect(),
+        };
+        return LocalPlayersConfig {
+            devices,
+            ..default()
+        };
+    }
+
+    /// Number of players given with `--players <count>`, one player otherwise.
+    pub fn from_args() -> Self {
+        #[allow(unused_mut)]
+        let mut count = 1;
+
+        // This is only for native builds
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let args: Vec<String> = std::env::args().collect();
+            if let Some(index) = args.iter().position(|arg| arg == "--players") {
+                match args.get(index + 1).and_then(|value| value.parse::<usize>().ok()) {
+                    Some(value) if value > 0 => {
+                        count = value;
+                    },
+                    _ => {
+                        error!("--players expects a number of players");
+                    }
+                }
+            }
+        }
+
+        return LocalPlayersConfig::with_players(count);
+    }
+
+    pub fn player_count(&self) -> usize {
+        return self.devices.len().max(1);
+    }
+}
+
+/// Input of a local player after the first.
+#[derive(Debug, Clone)]
+pub struct LocalPlayerInput {
+    pub devices: InputDevices,
+    pub actions: ActionState,
+    pub drive: DriveInput,
+}
+
+impl LocalPlayerInput {
+    pub fn new(devices: InputDevices) -> Self {
+        // Editing and debugging stay with the first player
+        let mut actions = ActionState::default();
+        actions.active_contexts.retain(|context| matches!(context, InputContext::Driving | InputContext::Camera));
+        return LocalPlayerInput {
+            devices,
+            actions,
+            drive: DriveInput::default(),
+        };
+    }
+}
+
+/// Input of the local players after the first, in player order.
+#[derive(Debug, Clone, Default)]
+pub struct LocalPlayerInputs {
+    pub players: Vec<LocalPlayerInput>,
+}
+
+/// Requires `SimulationPlugin`. Add before the plugins spawning vehicles and cameras.
+#[derive(Default)]
+pub struct LocalPlayersPlugin {
+    pub config: LocalPlayersConfig,
+}
+
+impl Plugin for LocalPlayersPlugin {
+    fn build(&self, app: &mut App) {
+        let count = self.config.player_count();
+        let first_devices = self.config.devices.first().cloned().unwrap_or_default();
+        let other_players = LocalPlayerInputs {
+            players: self.config.devices.iter().skip(1).cloned().map(LocalPlayerInput::new).collect(),
+        };
+
+        app.insert_resource(self.config.clone())
+            .init_resource::<Game>()
+            .insert_resource(first_devices)
+            .insert_resource(other_players);
+        app.world.resource_mut::<Game>().players.resize_with(count, Player::default);
+
+        if self.config.device_input {
+            app.add_system_to_stage(CoreStage::PreUpdate, local_player_input_system
+                                    .after(InputSystem)
+                                    .after(input_map_load_check));
+        }
+        if self.config.split_screen {
+            app.add_system(split_screen_system);
+        }
+    }
+}
+
+/// Reads the actions and driving input of the players after the first.
+#[allow(clippy::too_many_arguments)]
+fn local_player_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    button_axes: Res<Axis<GamepadButton>>,
+    input_map: Res<InputMap>,
+    settings: Res<DriveInputSettings>,
+    mut local_players: ResMut<LocalPlayerInputs>,
+) {
+    for player in local_players.players.iter_mut() {
+        read_actions(&mut player.actions, &input_map, &player.devices, &keyboard_input, &gamepads, &gamepad_buttons);
+        let connected_gamepads = player.devices.connected_gamepads(&gamepads);
+        player.drive = read_drive_input(&player.actions, &connected_gamepads, &axes, &button_axes, &settings);
+    }
+}
+
+/// Region of the window of a player, as a position from the top left and a size,
+/// in a grid of `count` regions as square as possible.
+pub fn split_screen_region(index: usize, count: usize, window_size: UVec2) -> (UVec2, UVec2) {
+    let count = count.max(1) as u32;
+    let columns = (count as f32).sqrt().ceil() as u32;
+    let rows = count.div_ceil(columns);
+    let size = UVec2::new(window_size.x / columns, window_size.y / rows);
+    let (column, row) = (index as u32 % columns, index as u32 / columns);
+    return (UVec2::new(column, row) * size, size);
+}
+
+/// Gives every player's camera its region of the window.
+fn split_screen_system(
+    windows: Res<Windows>,
+    game: Res<Game>,
+    mut cameras: Query<(&mut Camera, Option<&mut Camera3d>)>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        _ => {
+            return;
+        }
+    };
+    let window_size = UVec2::new(window.physical_width(), window.physical_height());
+    let count = game.players.len();
+
+    for (index, player) in game.players.iter().enumerate() {
+        let (mut camera, camera_3d) = match player.camera.and_then(|camera| cameras.get_mut(camera).ok()) {
+            Some(camera) => camera,
+            _ => {
+                continue;
+            }
+        };
+
+        // A single player has the whole window
+        let viewport = match count {
+            1 => None,
+            _ => {
+                let (physical_position, physical_size) = split_screen_region(index, count, window_size);
+                Some(Viewport {
+                    physical_position,
+                    physical_size,
+                    ..default()
+                })
+            },
+        };
+        let unchanged = match (&camera.viewport, &viewport) {
+            (None, None) => true,
+            (Some(current), Some(viewport)) => {
+                current.physical_position == viewport.physical_position && current.physical_size == viewport.physical_size
+            },
+            _ => false,
+        };
+        if !unchanged {
+            camera.viewport = viewport;
+        }
+
+        // Cameras are drawn in player order, only the first one clears the window
+        if camera.priority != index as isize {
+            camera.priority = index as isize;
+        }
+        if let Some(mut camera_3d) = camera_3d {
+            let clears = !matches!(camera_3d.clear_color, ClearColorConfig::None);
+            if clears != (index == 0) {
+                camera_3d.clear_color = match index {
+                    0 => ClearColorConfig::Default,
+                    _ => ClearColorConfig::None,
+                };
+            }
+        }
+    }
+}
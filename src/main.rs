@@ -1,167 +1,131 @@
-use std::{
-    ops::Mul,
-    str,
-};
-
-// This is only for native builds
-#[allow(unused_imports)]
-use std::fs::read_to_string;
-
-use serde_json;
+use std::time::Duration;
 
 use bevy::{
-    input::{keyboard::KeyCode, Input},
+    app::ScheduleRunnerSettings,
+    asset::AssetPlugin,
+    hierarchy::HierarchyPlugin,
+    log::LogPlugin,
     pbr::DirectionalLightShadowMap,
-    prelude::*
+    prelude::*,
+    scene::ScenePlugin,
+    transform::TransformPlugin,
 };
 use bevy_rapier3d::prelude::*;
 
-
-mod game;
-use game::Game;
-
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
-enum RoadNetworkLoadingState {
-    Loading,
-    Loaded,
-}
-
-use wasm_bindgen::{prelude::*, JsCast};
-use wasm_bindgen_futures::JsFuture;
-use web_sys::{
-    Response,
-    ReadableStream,
-    ReadableStreamDefaultReader
+use osd::{
+    ai::{AiConfig, AiPlugin},
+    camera::CameraPlugin,
+    editor::EditorPlugin,
+    ghost::GhostPlugin,
+    hud::HudPlugin,
+    local_players::{LocalPlayersConfig, LocalPlayersPlugin},
+    net::{NetClientConfig, NetClientPlugin, NetServerConfig, NetServerPlugin},
+    race::RacePlugin,
+    road_systems::{RoadNetworkConfig, RoadNetworkPlugin},
+    simulation::{SimulationConfig, SimulationPlugin, TIMESTEP},
+    traffic::{TrafficConfig, TrafficPlugin},
+    vehicle::{VehicleConfig, VehiclePlugin},
 };
-use js_sys::Uint8Array;
-
-mod windowmailer;
-mod road_network_builder;
-mod road_systems;
-
-use road_systems::*;
-use bevy_rapier3d::dynamics::*;
-
-// The code here is not used in native builds
-#[allow(dead_code)]
-const ROAD_NETWORK_DATA_CHANNEL: &str = "ROAD_NETWORK_DATA";
 
 fn main() {
-    #[cfg(not(target_arch = "wasm32"))]
-    let default_road_state = RoadNetworkLoadingState::Loaded;
-    #[cfg(target_arch = "wasm32")]
-    let default_road_state = RoadNetworkLoadingState::Loading;
+    if let Some(config) = NetServerConfig::from_args() {
+        run_server(config);
+        return;
+    }
 
-    App::new()
-        .init_resource::<Game>()
-        .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
+    // The server owns the road network and every vehicle
+    let net_client = NetClientConfig::from_args();
+    let mut road_network_config = RoadNetworkConfig::default();
+    let mut vehicle_config = VehicleConfig::default();
+    let mut ai_config = AiConfig::from_args();
+    let mut traffic_config = TrafficConfig::from_args();
+    if net_client.is_some() {
+        road_network_config.road_network_path = None;
+        vehicle_config.player_vehicle = None;
+        ai_config.count = 0;
+        traffic_config.max_vehicles = 0;
+    }
+
+    let mut app = App::new();
+    app.insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
         .insert_resource(DirectionalLightShadowMap { size: 2048 })
         .insert_resource(AmbientLight {
             color: Color::rgb(1.0, 0.8, 0.9),
             brightness: 0.6,
         })
-        .add_state(default_road_state)
         .add_plugins(DefaultPlugins)
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+        // Physics and everything moving bodies runs on a fixed timestep
+        .add_plugin(SimulationPlugin::default())
+        .add_plugin(LocalPlayersPlugin {
+            config: LocalPlayersConfig::from_args(),
+        })
+        .add_plugin(RoadNetworkPlugin {
+            config: road_network_config,
+        })
+        .add_plugin(VehiclePlugin {
+            config: vehicle_config,
+        })
+        .add_plugin(AiPlugin {
+            config: ai_config,
+        })
+        .add_plugin(TrafficPlugin {
+            config: traffic_config,
+        })
+        .add_plugin(CameraPlugin::default())
+        .add_plugin(EditorPlugin::default())
+        .add_plugin(RacePlugin::default())
+        .add_plugin(GhostPlugin::default())
+        .add_plugin(HudPlugin::default())
         .add_plugin(RapierDebugRenderPlugin::default())
         .add_startup_system(setup_graphics)
         .add_startup_system(setup_dynamic_objects)
-        .add_startup_system(setup_window_size)
-        .add_system(camera_target_car_system)
-        .add_system(camera_target_target_system)
-        .add_system(reset_forces_system)
-        .add_system(keyboard_input_system
-                    .after(reset_forces_system))
-        .add_system(road_network_creation_system
-                    .after(reset_forces_system))
-        .add_system(road_physics_system
-                    .after(reset_forces_system))
-        .add_startup_system(load_road_network)
-        .add_system_set(
-            SystemSet::on_update(RoadNetworkLoadingState::Loading)
-                .with_system(road_network_load_check)
-        )
-        .run();
-}
-
-
-// The code here is not used in native builds
-#[allow(dead_code)]
-async fn response_to_string(message: JsValue) -> Result<String, JsValue>{
-    let response: Response = message.dyn_into()?;
-    let stream: ReadableStream = response.body().unwrap();
-    let reader: ReadableStreamDefaultReader = stream.get_reader().dyn_into()?;
-    let result_value: JsValue = JsFuture::from(reader.read()).await?;
-    let array: Uint8Array = js_sys::Reflect::get(&result_value, &JsValue::from("value"))?.dyn_into()?;
-
-    let rust_vec: Vec<u8> = array.to_vec();
-    let str_string: &str = str::from_utf8(&rust_vec).unwrap();
-    let string: String = str_string.to_string();
-
-    return Ok(string);
-}
-
-
-fn load_road_network(
-    _commands: Commands,
-    mut _game: ResMut<Game>,
-    _meshes: ResMut<Assets<Mesh>>,
-    _materials: ResMut<Assets<StandardMaterial>>,
-) {
-    #[cfg(target_arch = "wasm32")]
-    {
-        let load_assets = async move {
-            let window = web_sys::window().unwrap();
-            let url = String::from("assets/road_network.json");
-            let future = JsFuture::from(window.fetch_with_str(&url)).await;
+        .add_startup_system(setup_window_size);
 
-            match future {
-                Ok(future) => {
-                    let string: String = response_to_string(future).await.unwrap();
-                    windowmailer::send_message(String::from(ROAD_NETWORK_DATA_CHANNEL), string);
-                }
-                _ => {
-
-                    web_sys::console::log_1(&JsValue::from(String::from("Error in fetch")));
-                }
-            }
-        };
-
-        wasm_bindgen_futures::spawn_local(load_assets);
-    }
-    #[cfg(not(target_arch = "wasm32"))]
-    {
-        let serialized_road_data: String = read_to_string("assets/road_network.json").unwrap();
-        _game.road_network = serde_json::from_str(&serialized_road_data).unwrap();
-        refresh_road_network(_game, _meshes, _materials, _commands);
+    if let Some(config) = net_client {
+        app.add_plugin(NetClientPlugin { config });
     }
+    app.run();
 }
 
-
-#[allow(unused_mut, unused_variables)]
-// Waits for roads to load
-fn road_network_load_check(
-    commands: Commands,
-    mut game: ResMut<Game>,
-    _meshes: ResMut<Assets<Mesh>>,
-    _materials: ResMut<Assets<StandardMaterial>>,
-    mut road_network_loading_state: ResMut<State<RoadNetworkLoadingState>>
-) {
-    #[cfg(target_arch = "wasm32")]
-    {
-        if windowmailer::message_count(String::from(ROAD_NETWORK_DATA_CHANNEL)) <= 0 {
-            return;
-        }
-        let serialized_road_data: String = windowmailer::read_message(String::from(ROAD_NETWORK_DATA_CHANNEL));
-        game.road_network = serde_json::from_str(&serialized_road_data).unwrap();
-
-        refresh_road_network(game, _meshes, _materials, commands);
-
-        road_network_loading_state.set(RoadNetworkLoadingState::Loaded).unwrap();
-    }
+/// Headless server: the simulation without a window, at the tick rate.
+fn run_server(config: NetServerConfig) {
+    App::new()
+        .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f32(TIMESTEP)))
+        .add_plugins(MinimalPlugins)
+        .add_plugin(LogPlugin)
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(AssetPlugin)
+        .add_plugin(ScenePlugin)
+        .add_asset::<Mesh>()
+        .add_asset::<StandardMaterial>()
+        .add_plugin(SimulationPlugin {
+            config: SimulationConfig {
+                device_input: false,
+                replays: false,
+                ..default()
+            },
+        })
+        .add_plugin(RoadNetworkPlugin {
+            config: RoadNetworkConfig {
+                tuning_panel: false,
+                ..default()
+            },
+        })
+        .add_plugin(VehiclePlugin {
+            config: VehicleConfig {
+                player_vehicle: None,
+                ..default()
+            },
+        })
+        .add_plugin(AiPlugin {
+            config: AiConfig::from_args(),
+        })
+        .add_plugin(NetServerPlugin { config })
+        .add_startup_system(setup_dynamic_objects)
+        .run();
 }
 
-
 #[cfg(target_arch = "wasm32")]
 fn setup_window_size(mut windows: ResMut<Windows>) {
     let window = match windows.get_primary_mut() {
@@ -190,14 +154,7 @@ fn setup_window_size() {
 
 fn setup_graphics(
     mut commands: Commands,
-    mut game: ResMut<Game>,
 ) {
-    game.camera = Some(
-        commands.spawn_bundle(Camera3dBundle {
-            transform: Transform::from_xyz(-3.0, 3.0, 10.0).looking_at(Vec3::ZERO, Vec3::Y),
-            ..Default::default()
-        }).id());
-
     commands.spawn_bundle(DirectionalLightBundle {
         directional_light: DirectionalLight {
             shadows_enabled: true,
@@ -214,146 +171,8 @@ fn setup_graphics(
     });
 }
 
-fn reset_forces_system(
-    game: ResMut<Game>,
-    mut ext_forces: Query<&mut ExternalForce>,
-) {
-    let vehicle_entity = match game.player_car {
-        Some(entity) => entity,
-        _ => {
-            return;
-        }
-    };
-
-    let trailer_entity = match game.trailer {
-        Some(entity) => entity,
-        _ => {
-            return;
-        }
-    };
-
-    let entities: Vec<Entity> = vec!(vehicle_entity, trailer_entity);
-
-    for entity in entities {
-        let mut ext_force = match ext_forces.get_mut(entity) {
-            Ok(ext_force) => ext_force,
-            _ => {
-                return;
-            }
-        };
-
-        ext_force.force = Vec3::ZERO;
-        ext_force.torque = Vec3::ZERO;
-    }
-}
-
-fn keyboard_input_system(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut transforms: Query<&mut Transform>,
-    game: ResMut<Game>,
-    mut ext_forces: Query<&mut ExternalForce>,
-) {
-    let entity = match game.player_car {
-        Some(entity) => entity,
-        _ => {
-            return;
-        }
-    };
-    let mut ext_force = match ext_forces.get_mut(entity) {
-        Ok(ext_force) => ext_force,
-        _ => {
-            return;
-        }
-    };
-    let transform = match transforms.get_mut(entity) {
-        Ok(transform) => transform,
-        _ => {
-            return;
-        }
-    };
-
-    // Apply forces
-    let forward_speed: f32 = 160.0;
-    let backward_speed: f32 = -80.0;
-
-    if keyboard_input.pressed(KeyCode::W) {
-        ext_force.force = transform.forward().mul(Vec3 { x: forward_speed, y: forward_speed, z: forward_speed });
-    }
-
-    if keyboard_input.pressed(KeyCode::S) {
-        ext_force.force = transform.forward().mul(Vec3 { x: backward_speed, y: backward_speed, z: backward_speed });
-    }
-
-    let torque: f32 = 22.0;
-
-    if keyboard_input.pressed(KeyCode::Left) {
-        ext_force.torque = transform.rotation * Vec3::new(0.0, torque, 0.0);
-    }
-
-    if keyboard_input.pressed(KeyCode::Right) {
-        ext_force.torque = transform.rotation * Vec3::new(0.0, -torque, 0.0);
-    }
-
-    if keyboard_input.pressed(KeyCode::A) {
-        ext_force.torque = transform.rotation * Vec3::new(0.0, 0.0, torque);
-    }
-
-    if keyboard_input.pressed(KeyCode::D) {
-        ext_force.torque = transform.rotation * Vec3::new(0.0, 0.0, -torque);
-    }
-
-    let up_down_torque = 25.0;
-
-    if keyboard_input.pressed(KeyCode::Up) {
-        ext_force.torque = transform.rotation * Vec3::new(-up_down_torque, 0.0, 0.0);
-    }
-
-    if keyboard_input.pressed(KeyCode::Down) {
-        ext_force.torque = transform.rotation * Vec3::new(up_down_torque, 0.0, 0.0);
-    }
-}
-
-
-fn camera_target_car_system(
-    mut transforms: Query<&mut Transform>,
-    mut game: ResMut<Game>,
-) {
-    let car_entity = match game.player_car {
-        Some(entity) => entity,
-        _ => {
-            return;
-        }
-    };
-    let car_transform = match transforms.get_mut(car_entity) {
-        Ok(transform) => transform,
-        _ => {
-            return;
-        }
-    };
-    game.camera_target.look_at = Some(car_transform.translation);
-    game.camera_target.up = Some(car_transform.up());
-    game.camera_target.position = Some(car_transform.translation + car_transform.forward() * -20.0 + (car_transform.up() * 5.0));
-}
-
-fn camera_target_target_system(
-    mut transforms: Query<&mut Transform>,
-    game: ResMut<Game>,
-) {
-    let camera_entity = match game.camera { Some(x) => x, _ => { return; } };
-    let mut camera_transform = match transforms.get_mut(camera_entity) { Ok(x) => x, _ => { return; } };
-    let camera_target_look_at = match game.camera_target.look_at { Some(x) => x, _ => { return; } };
-    let camera_target_position = match game.camera_target.position { Some(x) => x, _ => { return; } };
-    let camera_target_up = match game.camera_target.up { Some(x) => x, _ => { return; } };
-
-    // TODO: smooth camera transform.
-    camera_transform.look_at(camera_target_look_at, camera_target_up);
-    camera_transform.translation = camera_target_position;
-}
-
 fn setup_dynamic_objects(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut game: ResMut<Game>,
     _meshes: ResMut<Assets<Mesh>>,
 ) {
     // Create the ground.
@@ -362,55 +181,7 @@ fn setup_dynamic_objects(
         .insert(Collider::cuboid(100.0, 0.1, 100.0))
         .insert_bundle(TransformBundle::from(Transform::from_xyz(0.0, -2.0, 0.0)));
 
-    let my_gltf = asset_server.load("car_0001/model.glb#Scene0");
-
-    game.player_car =
-        Some(
-            commands
-                .spawn_bundle(SceneBundle {
-                    scene: my_gltf,
-                    transform: Transform::from_xyz(2.0, 0.0, -5.0),
-                    ..Default::default()
-                })
-                .insert(RigidBody::Dynamic)
-                .insert(Collider::cuboid(1.0, 1.0, 3.0))
-                .insert(ColliderMassProperties::Density(0.1))
-                .insert(Friction::coefficient(0.0))
-                .insert(Damping { linear_damping: 0.8, angular_damping: 0.4 })
-                .insert(Velocity {
-                    linvel: Vec3::new(0.0, 0.0, 0.0),
-                    angvel: Vec3::new(0.0, 0.0, 0.0),
-                })
-                .insert(ExternalForce {
-                    force: Vec3::new(0.0, 0.0, 0.0),
-                    torque: Vec3::new(0.0, 0.0, 0.0),
-                })
-                .id());
-
-    let trailer = asset_server.load("road_trailer_0001/model.glb#Scene0");
-
-    game.trailer =
-        Some(
-            commands
-                .spawn_bundle(SceneBundle {
-                    scene: trailer,
-                    transform: Transform::from_xyz(0.0, 0.0, -3.0),
-                    ..Default::default()
-                })
-                .insert(Friction::coefficient(0.0))
-                .insert(RigidBody::Dynamic)
-                .insert(Collider::cuboid(1.5, 0.3, 1.5))
-                .insert(ColliderMassProperties::Density(0.3))
-                .insert(Damping { linear_damping: 0.8, angular_damping: 0.9 })
-                .insert(Velocity {
-                    linvel: Vec3::new(0.0, 0.0, 0.0),
-                    angvel: Vec3::new(0.0, 0.0, 0.0),
-                })
-                .insert(ExternalForce {
-                    force: Vec3::new(0.0, 0.0, 0.0),
-                    torque: Vec3::new(0.0, 0.0, 0.0),
-                })
-                .id());
+    // The player's vehicle and the trailer are spawned by VehiclePlugin and EditorPlugin.
 
 
     // let ball_amount_per_dimension = 30;
@@ -0,0 +1,813 @@
+//! Networked multiplayer: a headless server owning the simulation, and
+//! clients showing it.
+//!
+//! The server (`cargo run -- --server`) runs the simulation schedule with the
+//! road network and every vehicle. Clients (`cargo run -- --connect
+//! ws://127.0.0.1:9001`, or `?connect=` in the browser) connect through a
+//! WebSocket, send their driving input and road editor changes, and receive
+//! snapshots of the vehicles a few times per tick rate. They show the
+//! vehicles slightly in the past, interpolated between the snapshots around
+//! that time, so they move smoothly whatever the network jitter.
+//!
+//! Road edits go through the server: it applies them and sends them to every
+//! client, which applies them like replayed edits. Clients joining later get
+//! the road network as it is.
+//!
+//! Messages are JSON, like replays and saved files.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{SocketAddr, TcpListener},
+};
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    drive_input::DriveInput,
+    game::Game,
+    hover::{apply_drive_input, hover_thruster_system, HoverVehicle},
+    replay::{simulation_input_system, SimulationInput},
+    road_network_builder::{PendingRoadEdits, RoadEdit, RoadNetwork},
+    road_systems::refresh_road_network,
+    simulation::{add_simulation_system, SimulationStage, TIMESTEP},
+    vehicle::{spawn_player_vehicle_system, spawn_vehicle, Vehicle, VehicleConfig, VehicleRoster, DEFAULT_PLAYER_VEHICLE, PLAYER_SPACING},
+    websocket::{WebSocket, WebSocketState},
+};
+
+pub const DEFAULT_SERVER_ADDRESS: &str = "127.0.0.1:9001";
+
+/// Sent by clients.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ClientMessage {
+    /// First message, with the vehicle to drive.
+    Hello { vehicle: String },
+    /// Driving input, sent when it changes.
+    Input { drive: DriveInput },
+    /// A road editor change, to apply for everyone.
+    RoadEdit { edit: RoadEdit },
+}
+
+/// Sent by the server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ServerMessage {
+    /// Answer to `Hello`, with the road network as it is.
+    Welcome { client: u32, road_network: RoadNetwork },
+    Snapshot(Snapshot),
+    /// A road editor change of any client, including the receiver.
+    RoadEdit { edit: RoadEdit },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BodySnapshot {
+    /// Same id in every snapshot of the body.
+    pub id: u64,
+    pub vehicle: String,
+    pub translation: Vec3,
+    pub rotation: Quat,
+}
+
+/// Every vehicle of the server at a tick.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub tick: u64,
+    /// Body driven by the receiver.
+    pub car: Option<u64>,
+    pub bodies: Vec<BodySnapshot>,
+}
+
+impl Snapshot {
+    /// Server time of the snapshot, in seconds.
+    pub fn time(&self) -> f32 {
+        return self.tick as f32 * TIMESTEP;
+    }
+}
+
+/// Snapshots received by a client, oldest first.
+#[derive(Default, Debug)]
+pub struct SnapshotBuffer {
+    pub snapshots: VecDeque<Snapshot>,
+}
+
+/// Snapshots kept, enough for a second at the default rate.
+const SNAPSHOT_BUFFER_SIZE: usize = 32;
+
+impl SnapshotBuffer {
+    /// Adds a snapshot. Snapshots older than the latest one are dropped.
+    pub fn push(&mut self, snapshot: Snapshot) {
+        if let Some(latest) = self.snapshots.back() {
+            if snapshot.tick <= latest.tick {
+                return;
+            }
+        }
+        self.snapshots.push_back(snapshot);
+        while self.snapshots.len() > SNAPSHOT_BUFFER_SIZE {
+            self.snapshots.pop_front();
+        }
+    }
+
+    pub fn latest(&self) -> Option<&Snapshot> {
+        return self.snapshots.back();
+    }
+
+    /// Bodies at `time`, interpolated between the snapshots around it.
+    /// Bodies show up once the time reaches their first snapshot, and
+    /// stay at their last snapshot when there is no newer one.
+    pub fn sample(&self, time: f32) -> Vec<(u64, String, Transform)> {
+        let after = self.snapshots.iter().position(|snapshot| snapshot.time() > time);
+        let (before, after) = match after {
+            Some(0) => (&self.snapshots[0], None),
+            Some(index) => (&self.snapshots[index - 1], Some(&self.snapshots[index])),
+            _ => match self.snapshots.back() {
+                Some(latest) => (latest, None),
+                _ => {
+                    return Vec::new();
+                }
+            },
+        };
+
+        return before.bodies
+            .iter()
+            .map(|body| {
+                let mut transform = Transform::from_translation(body.translation).with_rotation(body.rotation);
+                let next = after.and_then(|after| {
+                    let next = after.bodies.iter().find(|next| next.id == body.id)?;
+                    let alpha = (time - before.time()) / (after.time() - before.time());
+                    return Some((next, alpha.clamp(0.0, 1.0)));
+                });
+                if let Some((next, alpha)) = next {
+                    transform.translation = body.translation.lerp(next.translation, alpha);
+                    transform.rotation = body.rotation.slerp(next.rotation, alpha);
+                }
+                (body.id, body.vehicle.clone(), transform)
+            })
+            .collect();
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NetServerConfig {
+    /// Address the server listens on.
+    pub address: String,
+    /// Vehicle of clients asking for one that doesn't exist.
+    pub vehicle: String,
+    /// Ticks between snapshots.
+    pub snapshot_interval: u64,
+}
+
+impl Default for NetServerConfig {
+    fn default() -> Self {
+        NetServerConfig {
+            address: String::from(DEFAULT_SERVER_ADDRESS),
+            vehicle: String::from(DEFAULT_PLAYER_VEHICLE),
+            snapshot_interval: 3,
+        }
+    }
+}
+
+impl NetServerConfig {
+    /// Server listening on `--server [address]`, if given.
+    pub fn from_args() -> Option<Self> {
+        #[allow(unused_mut)]
+        let mut config = None;
+
+        // This is only for native builds
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let args: Vec<String> = std::env::args().collect();
+            if let Some(index) = args.iter().position(|arg| arg == "--server") {
+                let mut server_config = NetServerConfig::default();
+                if let Some(address) = args.get(index + 1).filter(|arg| !arg.starts_with("--")) {
+                    server_config.address = address.clone();
+                }
+                config = Some(server_config);
+            }
+        }
+
+        return config;
+    }
+}
+
+/// A connected client, on the server.
+pub struct RemoteClient {
+    pub id: u32,
+    socket: WebSocket,
+    /// Vehicle asked for in `Hello`, None until then.
+    pub vehicle: Option<String>,
+    pub car: Option<Entity>,
+    /// Starting place of the car, kept while the client is connected.
+    pub slot: Option<usize>,
+    pub drive: DriveInput,
+}
+
+#[derive(Default)]
+pub struct NetServer {
+    listener: Option<TcpListener>,
+    pub clients: Vec<RemoteClient>,
+    next_client: u32,
+    last_snapshot: Option<u64>,
+}
+
+impl NetServer {
+    /// Address the server listens on, None when it couldn't listen.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        return self.listener.as_ref().and_then(|listener| listener.local_addr().ok());
+    }
+
+    fn broadcast(&mut self, message: &ServerMessage) {
+        let serialized = serde_json::to_string(message).unwrap();
+        for client in self.clients.iter_mut().filter(|client| client.vehicle.is_some()) {
+            client.socket.send(&serialized);
+        }
+    }
+}
+
+/// Accepts clients and replicates the simulation to them.
+/// Requires `SimulationPlugin` and `VehiclePlugin`, usually without device input,
+/// see `main.rs` for a headless server.
+#[derive(Default)]
+pub struct NetServerPlugin {
+    pub config: NetServerConfig,
+}
+
+impl Plugin for NetServerPlugin {
+    fn build(&self, app: &mut App) {
+        let listener = TcpListener::bind(&self.config.address).and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            return Ok(listener);
+        });
+        let listener = match listener {
+            Ok(listener) => {
+                info!("Server listening on {}", self.config.address);
+                Some(listener)
+            },
+            Err(error) => {
+                error!("Can't listen on {}: {}", self.config.address, error);
+                None
+            }
+        };
+
+        app.insert_resource(self.config.clone())
+            .init_resource::<Game>()
+            .init_resource::<PendingRoadEdits>()
+            .insert_resource(NetServer {
+                listener,
+                ..default()
+            })
+            .add_system(server_receive_system)
+            .add_system_to_stage(CoreStage::PostUpdate, server_snapshot_system);
+
+        add_simulation_system(app, SimulationStage::Vehicles, server_spawn_system.after(spawn_player_vehicle_system));
+        add_simulation_system(app, SimulationStage::VehicleForces, server_drive_system.after(hover_thruster_system));
+    }
+}
+
+/// Accepts new clients and handles their messages.
+fn server_receive_system(
+    mut commands: Commands,
+    mut server: ResMut<NetServer>,
+    mut pending_road_edits: ResMut<PendingRoadEdits>,
+    game: Res<Game>,
+) {
+    if let Some(listener) = &server.listener {
+        let mut accepted = Vec::new();
+        while let Ok((stream, address)) = listener.accept() {
+            match WebSocket::accept(stream) {
+                Ok(socket) => {
+                    info!("Client connected from {}", address);
+                    accepted.push(socket);
+                },
+                Err(error) => {
+                    warn!("Can't accept {}: {}", address, error);
+                }
+            }
+        }
+        for socket in accepted {
+            let id = server.next_client;
+            server.next_client += 1;
+            server.clients.push(RemoteClient {
+                id,
+                socket,
+                vehicle: None,
+                car: None,
+                slot: None,
+                drive: DriveInput::default(),
+            });
+        }
+    }
+
+    let mut edits = Vec::new();
+    for client in server.clients.iter_mut() {
+        for message in client.socket.receive() {
+            let message: ClientMessage = match serde_json::from_str(&message) {
+                Ok(message) => message,
+                Err(error) => {
+                    warn!("Invalid message from client {}: {}", client.id, error);
+                    continue;
+                }
+            };
+            match message {
+                ClientMessage::Hello { vehicle } => {
+                    // With the edits sent to the others but not applied yet.
+                    // Edits received during this update are sent to it too.
+                    let mut road_network = game.road_network.clone();
+                    for edit in pending_road_edits.edits.iter() {
+                        road_network.apply_edit(edit);
+                    }
+                    let welcome = ServerMessage::Welcome { client: client.id, road_network };
+                    client.socket.send(&serde_json::to_string(&welcome).unwrap());
+                    client.vehicle = Some(vehicle);
+                },
+                ClientMessage::Input { drive } => match drive.validated() {
+                    Some(drive) => {
+                        client.drive = drive;
+                    },
+                    _ => {
+                        warn!("Invalid input from client {}", client.id);
+                    }
+                },
+                ClientMessage::RoadEdit { edit } => {
+                    if edit.is_finite() {
+                        edits.push(edit);
+                    } else {
+                        warn!("Invalid road edit from client {}", client.id);
+                    }
+                },
+            }
+        }
+    }
+
+    for edit in edits {
+        server.broadcast(&ServerMessage::RoadEdit { edit: edit.clone() });
+        pending_road_edits.edits.push(edit);
+    }
+
+    // Disconnected clients leave with their car
+    for client in server.clients.iter().filter(|client| client.socket.state() == WebSocketState::Closed) {
+        info!("Client {} disconnected", client.id);
+        if let Some(car) = client.car {
+            commands.entity(car).despawn_recursive();
+        }
+    }
+    server.clients.retain(|client| client.socket.state() != WebSocketState::Closed);
+}
+
+/// Spawns the cars of new clients side by side, like local players,
+/// in the first starting place no connected client has.
+fn server_spawn_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<NetServerConfig>,
+    vehicle_config: Res<VehicleConfig>,
+    roster: Res<VehicleRoster>,
+    mut server: ResMut<NetServer>,
+) {
+    let mut used_slots: Vec<usize> = server.clients.iter().filter_map(|client| client.slot).collect();
+    for client in server.clients.iter_mut() {
+        let requested = match (&client.vehicle, client.car) {
+            (Some(vehicle), None) => vehicle,
+            _ => {
+                continue;
+            }
+        };
+        let (name, manifest) = match roster.manifests.get(requested) {
+            Some(manifest) => (requested.clone(), manifest),
+            _ => match roster.manifests.get(&config.vehicle) {
+                Some(manifest) => (config.vehicle.clone(), manifest),
+                _ => {
+                    continue;
+                }
+            },
+        };
+
+        let slot = match client.slot {
+            Some(slot) => slot,
+            _ => (0..).find(|slot| !used_slots.contains(slot)).unwrap_or(0),
+        };
+        used_slots.push(slot);
+        client.slot = Some(slot);
+
+        let mut transform = vehicle_config.player_transform;
+        transform.translation += transform.right() * PLAYER_SPACING * slot as f32;
+        client.car = Some(spawn_vehicle(&mut commands, &asset_server, &name, manifest, transform));
+    }
+}
+
+/// Applies the driving input of every client to their car.
+fn server_drive_system(
+    server: Res<NetServer>,
+    mut vehicles: Query<(&Transform, &Velocity, &Vehicle, &HoverVehicle, &mut ExternalForce)>,
+) {
+    for client in server.clients.iter() {
+        let (transform, velocity, vehicle, hover, mut ext_force) = match client.car.and_then(|car| vehicles.get_mut(car).ok()) {
+            Some(components) => components,
+            _ => {
+                continue;
+            }
+        };
+        apply_drive_input(transform, velocity, &vehicle.manifest, &hover.settings, &client.drive, &mut ext_force);
+    }
+}
+
+/// Sends the vehicles to every client, every `snapshot_interval` ticks.
+fn server_snapshot_system(
+    config: Res<NetServerConfig>,
+    input: Res<SimulationInput>,
+    mut server: ResMut<NetServer>,
+    vehicles: Query<(Entity, &Vehicle, &Transform)>,
+) {
+    if let Some(last_snapshot) = server.last_snapshot {
+        if input.tick < last_snapshot + config.snapshot_interval {
+            return;
+        }
+    }
+    server.last_snapshot = Some(input.tick);
+
+    let bodies: Vec<BodySnapshot> = vehicles
+        .iter()
+        .map(|(entity, vehicle, transform)| BodySnapshot {
+            id: entity.to_bits(),
+            vehicle: vehicle.name.clone(),
+            translation: transform.translation,
+            rotation: transform.rotation,
+        })
+        .collect();
+
+    for client in server.clients.iter_mut().filter(|client| client.vehicle.is_some()) {
+        let snapshot = ServerMessage::Snapshot(Snapshot {
+            tick: input.tick,
+            car: client.car.map(|car| car.to_bits()),
+            bodies: bodies.clone(),
+        });
+        client.socket.send(&serde_json::to_string(&snapshot).unwrap());
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NetClientConfig {
+    /// WebSocket url of the server.
+    pub url: String,
+    pub vehicle: String,
+    /// How far in the past vehicles are shown, in seconds.
+    /// A few snapshot intervals, so there is usually a snapshot on each side.
+    pub interpolation_delay: f32,
+}
+
+impl Default for NetClientConfig {
+    fn default() -> Self {
+        NetClientConfig {
+            url: format!("ws://{}", DEFAULT_SERVER_ADDRESS),
+            vehicle: String::from(DEFAULT_PLAYER_VEHICLE),
+            interpolation_delay: 0.1,
+        }
+    }
+}
+
+impl NetClientConfig {
+    /// Client of `--connect <url>`, or of `?connect=<url>` in the browser.
+    pub fn from_args() -> Option<Self> {
+        #[allow(unused_mut)]
+        let mut url: Option<String> = None;
+
+        // This is only for native builds
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let args: Vec<String> = std::env::args().collect();
+            if let Some(index) = args.iter().position(|arg| arg == "--connect") {
+                url = Some(match args.get(index + 1).filter(|arg| !arg.starts_with("--")) {
+                    Some(url) => url.clone(),
+                    _ => NetClientConfig::default().url,
+                });
+            }
+        }
+
+        // This is only for wasm builds
+        #[cfg(target_arch = "wasm32")]
+        {
+            let search = web_sys::window().and_then(|window| window.location().search().ok()).unwrap_or_default();
+            for parameter in search.trim_start_matches('?').split('&') {
+                if let Some(value) = parameter.strip_prefix("connect=") {
+                    url = js_sys::decode_uri_component(value).ok().map(String::from);
+                }
+            }
+        }
+
+        return url.map(|url| NetClientConfig {
+            url,
+            ..default()
+        });
+    }
+}
+
+/// Connection of a client to the server: a `WebSocket` on native builds,
+/// the browser's WebSocket on wasm builds.
+pub struct NetConnection {
+    #[cfg(not(target_arch = "wasm32"))]
+    socket: WebSocket,
+    #[cfg(target_arch = "wasm32")]
+    socket: web_sys::WebSocket,
+    /// Messages sent before the connection opened.
+    #[cfg(target_arch = "wasm32")]
+    queued: Vec<String>,
+}
+
+// The code here is not used in native builds
+#[allow(dead_code)]
+const NET_MESSAGES_CHANNEL: &str = "NET_MESSAGES";
+
+impl NetConnection {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn connect(url: &str) -> Result<Self, String> {
+        return match WebSocket::connect(url) {
+            Ok(socket) => Ok(NetConnection { socket }),
+            Err(error) => Err(error.to_string()),
+        };
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn connect(url: &str) -> Result<Self, String> {
+        use wasm_bindgen::{prelude::*, JsCast};
+
+        let socket = web_sys::WebSocket::new(url).map_err(|error| format!("{:?}", error))?;
+        // Received messages wait in a channel until the next frame
+        let on_message = Closure::<dyn FnMut(web_sys::MessageEvent)>::new(|event: web_sys::MessageEvent| {
+            if let Some(message) = event.data().as_string() {
+                crate::windowmailer::send_message(String::from(NET_MESSAGES_CHANNEL), message);
+            }
+        });
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        on_message.forget();
+
+        return Ok(NetConnection {
+            socket,
+            queued: Vec::new(),
+        });
+    }
+
+    pub fn send(&mut self, message: &ClientMessage) {
+        let serialized = serde_json::to_string(message).unwrap();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.socket.send(&serialized);
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.queued.push(serialized);
+            self.flush();
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn flush(&mut self) {
+        if self.socket.ready_state() != web_sys::WebSocket::OPEN {
+            return;
+        }
+        for message in self.queued.drain(..) {
+            let _ = self.socket.send_with_str(&message);
+        }
+    }
+
+    /// Messages received since the last call.
+    pub fn receive(&mut self) -> Vec<ServerMessage> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let messages = self.socket.receive();
+        #[cfg(target_arch = "wasm32")]
+        let messages = {
+            self.flush();
+            let channel = String::from(NET_MESSAGES_CHANNEL);
+            let mut messages = Vec::new();
+            while crate::windowmailer::message_count(channel.clone()) > 0 {
+                messages.push(crate::windowmailer::read_message(channel.clone()));
+            }
+            messages
+        };
+
+        return messages
+            .iter()
+            .filter_map(|message| match serde_json::from_str(message) {
+                Ok(message) => Some(message),
+                Err(error) => {
+                    warn!("Invalid message from the server: {}", error);
+                    None
+                }
+            })
+            .collect();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            return self.socket.state() == WebSocketState::Closed;
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            return self.socket.ready_state() == web_sys::WebSocket::CLOSED;
+        }
+    }
+}
+
+/// State of a client.
+#[derive(Default, Debug)]
+pub struct NetClient {
+    /// Id given by the server.
+    pub client: Option<u32>,
+    pub snapshots: SnapshotBuffer,
+    /// Server time shown, `interpolation_delay` behind the latest snapshot.
+    pub playback_time: Option<f32>,
+    /// Entities showing the bodies of the server, by id.
+    pub bodies: HashMap<u64, Entity>,
+    /// Road edits made here, to send to the server.
+    outgoing_edits: Vec<RoadEdit>,
+    /// Road edits of the server, to apply on the next tick.
+    incoming_edits: Vec<RoadEdit>,
+    last_drive: Option<DriveInput>,
+    disconnected: bool,
+}
+
+/// Connects to a server, sends it the input, and shows its vehicles.
+/// The server owns the road network and the vehicles: use it without
+/// a road network path nor a player vehicle.
+/// Requires `SimulationPlugin` and `VehiclePlugin`.
+#[derive(Default)]
+pub struct NetClientPlugin {
+    pub config: NetClientConfig,
+}
+
+impl Plugin for NetClientPlugin {
+    fn build(&self, app: &mut App) {
+        match NetConnection::connect(&self.config.url) {
+            Ok(mut connection) => {
+                info!("Connecting to {}", self.config.url);
+                connection.send(&ClientMessage::Hello { vehicle: self.config.vehicle.clone() });
+                app.insert_non_send_resource(connection);
+            },
+            Err(error) => {
+                error!("Can't connect to {}: {}", self.config.url, error);
+            }
+        }
+
+        app.insert_resource(self.config.clone())
+            .init_resource::<Game>()
+            .init_resource::<NetClient>()
+            .add_system(client_receive_system)
+            .add_system(client_body_system
+                        .after(client_receive_system))
+            .add_system(client_send_system
+                        .after(client_receive_system));
+
+        add_simulation_system(app, SimulationStage::Input, client_road_edit_system.before(simulation_input_system));
+    }
+}
+
+/// Handles the messages of the server.
+fn client_receive_system(
+    connection: Option<NonSendMut<NetConnection>>,
+    mut client: ResMut<NetClient>,
+    mut game: ResMut<Game>,
+    meshes: ResMut<Assets<Mesh>>,
+    materials: ResMut<Assets<StandardMaterial>>,
+    commands: Commands,
+) {
+    let mut connection = match connection {
+        Some(connection) => connection,
+        _ => {
+            return;
+        }
+    };
+
+    let mut road_network = None;
+    for message in connection.receive() {
+        match message {
+            ServerMessage::Welcome { client: id, road_network: network } => {
+                info!("Connected as client {}", id);
+                client.client = Some(id);
+                road_network = Some(network);
+            },
+            ServerMessage::Snapshot(snapshot) => {
+                client.snapshots.push(snapshot);
+            },
+            ServerMessage::RoadEdit { edit } => {
+                client.incoming_edits.push(edit);
+            },
+        }
+    }
+
+    if connection.is_closed() && !client.disconnected {
+        error!("Disconnected from the server");
+        client.disconnected = true;
+    }
+
+    if let Some(network) = road_network {
+        game.road_network = network;
+        refresh_road_network(game, meshes, materials, commands);
+    }
+}
+
+/// Sends the driving input when it changes, and the road edits.
+fn client_send_system(
+    connection: Option<NonSendMut<NetConnection>>,
+    drive_input: Res<DriveInput>,
+    mut client: ResMut<NetClient>,
+) {
+    let mut connection = match connection {
+        Some(connection) => connection,
+        _ => {
+            return;
+        }
+    };
+    if client.client.is_none() {
+        return;
+    }
+
+    if client.last_drive != Some(*drive_input) {
+        connection.send(&ClientMessage::Input { drive: *drive_input });
+        client.last_drive = Some(*drive_input);
+    }
+    for edit in client.outgoing_edits.drain(..) {
+        connection.send(&ClientMessage::RoadEdit { edit });
+    }
+}
+
+/// Takes the road edits made here before the tick applies them, and gives it
+/// the edits of the server instead.
+fn client_road_edit_system(
+    mut pending_road_edits: ResMut<PendingRoadEdits>,
+    mut client: ResMut<NetClient>,
+) {
+    let client = &mut *client;
+    client.outgoing_edits.append(&mut pending_road_edits.edits);
+    pending_road_edits.edits.append(&mut client.incoming_edits);
+}
+
+/// Largest gap between the time shown and its target before jumping to it, in seconds.
+const MAX_PLAYBACK_DRIFT: f32 = 0.25;
+
+/// Shows the vehicles of the server at the playback time, and follows the client's car.
+#[allow(clippy::too_many_arguments)]
+fn client_body_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<NetClientConfig>,
+    roster: Res<VehicleRoster>,
+    time: Res<Time>,
+    mut client: ResMut<NetClient>,
+    mut game: ResMut<Game>,
+    mut transforms: Query<&mut Transform>,
+) {
+    let (target, car) = match client.snapshots.latest() {
+        Some(latest) => (latest.time() - config.interpolation_delay, latest.car),
+        _ => {
+            return;
+        }
+    };
+
+    // Runs at the frame rate, slowly catching up with the snapshots
+    let playback_time = match client.playback_time {
+        Some(playback_time) if (target - playback_time).abs() < MAX_PLAYBACK_DRIFT => {
+            let playback_time = playback_time + time.delta_seconds();
+            playback_time + (target - playback_time) * 0.1
+        },
+        _ => target,
+    };
+    client.playback_time = Some(playback_time);
+
+    let bodies = client.snapshots.sample(playback_time);
+    for (id, vehicle, transform) in bodies.iter() {
+        if let Some(mut body_transform) = client.bodies.get(id).and_then(|entity| transforms.get_mut(*entity).ok()) {
+            *body_transform = *transform;
+            continue;
+        }
+        let manifest = match roster.manifests.get(vehicle) {
+            Some(manifest) => manifest,
+            _ => {
+                continue;
+            }
+        };
+        let entity = commands
+            .spawn_bundle(SceneBundle {
+                scene: asset_server.load(&manifest.scene_path(vehicle)),
+                transform: *transform,
+                ..default()
+            })
+            .id();
+        client.bodies.insert(*id, entity);
+    }
+
+    // Bodies gone from the server
+    let removed: Vec<u64> = client.bodies.keys().filter(|id| !bodies.iter().any(|(body, _, _)| body == *id)).copied().collect();
+    for id in removed {
+        if let Some(entity) = client.bodies.remove(&id) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    // The camera follows the car of this client
+    let car = car.and_then(|car| client.bodies.get(&car).copied());
+    if game.player_car() != car {
+        if let Some(player) = game.players.first_mut() {
+            player.car = car;
+        }
+    }
+}
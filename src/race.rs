@@ -0,0 +1,450 @@
+//! Race mode: lap and split timing, checkpoints and best laps.
+//!
+//! The start/finish line is at the start of the road. The player's car is
+//! followed by its projected distance along the road: a lap ends when it gets
+//! around to the line again, after driving through every checkpoint in order.
+//! Laps need a closed road, on an open one the lap ends at its end.
+//!
+//! N starts and stops race mode. When it starts on the line the timer runs
+//! right away, otherwise it waits for the car to cross the line.
+//! G adds a checkpoint where the car is on the road, or removes the one there.
+//! Checkpoints are saved with the road network.
+//!
+//! The best lap of each track is kept in `best_laps.json`.
+
+use std::collections::HashMap;
+// This is only for native builds
+#[allow(unused_imports)]
+use std::fs::{read_to_string, write};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::Game,
+    input_map::Action,
+    replay::SimulationInput,
+    simulation::{add_simulation_system, SimulationStage, TIMESTEP},
+};
+
+pub const BEST_LAPS_PATH: &str = "best_laps.json";
+
+/// Closer than this to the start of the road, race mode starts timing at once.
+const START_LINE_WINDOW: f32 = 20.0;
+/// Further than this from the road, the car is off track and timing waits.
+const OFF_TRACK_DISTANCE: f32 = 30.0;
+/// Placing a checkpoint this close to another removes it instead.
+const CHECKPOINT_TOGGLE_DISTANCE: f32 = 10.0;
+const GATE_WIDTH: f32 = 12.0;
+const GATE_HEIGHT: f32 = 8.0;
+
+#[derive(Clone, Debug)]
+pub struct RaceConfig {
+    /// Best laps, loaded at startup and saved on every new record. Native builds only.
+    pub best_laps_path: Option<String>,
+    /// Show the start line and checkpoint gates. Requires Bevy's rendering.
+    pub gates: bool,
+}
+
+impl Default for RaceConfig {
+    fn default() -> Self {
+        RaceConfig {
+            best_laps_path: Some(String::from(BEST_LAPS_PATH)),
+            gates: true,
+        }
+    }
+}
+
+/// Requires `SimulationPlugin`.
+#[derive(Default)]
+pub struct RacePlugin {
+    pub config: RaceConfig,
+}
+
+impl Plugin for RacePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.config.clone())
+            .init_resource::<Game>()
+            .init_resource::<Race>()
+            .init_resource::<BestLaps>()
+            .add_event::<RaceEvent>()
+            .add_startup_system(load_best_laps)
+            .add_system(save_best_laps_system);
+
+        add_simulation_system(app, SimulationStage::Editing, checkpoint_placement_system);
+        add_simulation_system(app, SimulationStage::Gameplay, lap_timing_system);
+
+        if self.config.gates {
+            app.add_startup_system(setup_checkpoint_gates)
+                .add_system(checkpoint_gates_system);
+        }
+    }
+}
+
+/// State of race mode. Times are in seconds of simulation.
+#[derive(Default, Debug)]
+pub struct Race {
+    pub active: bool,
+    /// Tick the current lap started on, None until the car crosses the line.
+    pub lap_start: Option<u64>,
+    /// Distance driven along the road since the line.
+    pub progress: f32,
+    /// Projected distance along the road on the previous tick.
+    last_distance: Option<f32>,
+    /// Index of the checkpoint to drive through next.
+    pub next_checkpoint: usize,
+    /// Times at the checkpoints passed this lap.
+    pub splits: Vec<f32>,
+    pub laps: u32,
+    pub last_lap: Option<f32>,
+}
+
+impl Race {
+    /// Time since the start of the lap at `tick`.
+    pub fn lap_time(&self, tick: u64) -> Option<f32> {
+        return self.lap_start.map(|lap_start| (tick - lap_start) as f32 * TIMESTEP);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LapRecord {
+    pub time: f32,
+    /// Times at each checkpoint.
+    pub splits: Vec<f32>,
+}
+
+/// Best lap of each track, by `RoadNetwork::track_id`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BestLaps {
+    pub tracks: HashMap<String, LapRecord>,
+}
+
+/// Sent by race mode, for feedback. Deltas compare to the best lap, negative is faster.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RaceEvent {
+    Started,
+    Stopped,
+    /// Number of the lap.
+    LapStarted(u32),
+    CheckpointPassed { index: usize, split: f32, delta: Option<f32> },
+    LapCompleted { time: f32, delta: Option<f32>, best: bool },
+    /// The car crossed the line without driving through every checkpoint.
+    MissedCheckpoint,
+    /// Number of checkpoints after adding or removing one.
+    CheckpointsChanged(usize),
+}
+
+/// Lap time as `m:ss.mmm`.
+pub fn format_lap_time(seconds: f32) -> String {
+    let milliseconds = (seconds.max(0.0) * 1000.0).round() as u64;
+    return format!("{}:{:02}.{:03}", milliseconds / 60000, milliseconds / 1000 % 60, milliseconds % 1000);
+}
+
+/// Time difference, with its sign.
+pub fn format_delta(seconds: f32) -> String {
+    let sign = if seconds < 0.0 { "-" } else { "+" };
+    return format!("{}{:.3}", sign, seconds.abs());
+}
+
+/// Adds or removes a checkpoint where the player's car is (G).
+pub fn checkpoint_placement_system(
+    input: Res<SimulationInput>,
+    transforms: Query<&Transform>,
+    mut game: ResMut<Game>,
+    mut race_events: EventWriter<RaceEvent>,
+) {
+    if !input.actions.just_released(Action::PlaceCheckpoint) {
+        return;
+    }
+
+    let position = match game.player_car().and_then(|car| transforms.get(car).ok()) {
+        Some(transform) => transform.translation,
+        _ => {
+            return;
+        }
+    };
+    let distance = match game.road_network.distance_along(position) {
+        Some((distance, off)) if off <= OFF_TRACK_DISTANCE => distance,
+        _ => {
+            return;
+        }
+    };
+
+    game.road_network.toggle_checkpoint(distance, CHECKPOINT_TOGGLE_DISTANCE);
+    race_events.send(RaceEvent::CheckpointsChanged(game.road_network.checkpoints.len()));
+}
+
+/// Starts and stops race mode (N), and times the laps of the player's car.
+pub fn lap_timing_system(
+    input: Res<SimulationInput>,
+    game: Res<Game>,
+    transforms: Query<&Transform>,
+    mut race: ResMut<Race>,
+    mut best_laps: ResMut<BestLaps>,
+    mut race_events: EventWriter<RaceEvent>,
+) {
+    let started = input.actions.just_released(Action::ToggleRaceMode) && !race.active;
+    if input.actions.just_released(Action::ToggleRaceMode) {
+        *race = Race {
+            active: !race.active,
+            ..default()
+        };
+        race_events.send(if race.active { RaceEvent::Started } else { RaceEvent::Stopped });
+    }
+    if !race.active {
+        return;
+    }
+
+    let position = match game.player_car().and_then(|car| transforms.get(car).ok()) {
+        Some(transform) => transform.translation,
+        _ => {
+            return;
+        }
+    };
+    let road_network = &game.road_network;
+    let length = road_network.length();
+    let distance = match road_network.distance_along(position) {
+        Some((distance, off)) if off <= OFF_TRACK_DISTANCE && length > 0.0 => distance,
+        _ => {
+            // Off track, pick up from wherever the car comes back
+            race.last_distance = None;
+            return;
+        }
+    };
+
+    let tick = input.tick;
+    let previous = race.last_distance.replace(distance);
+    if started {
+        race.progress = distance;
+        if distance < START_LINE_WINDOW {
+            race.lap_start = Some(tick);
+            race_events.send(RaceEvent::LapStarted(1));
+        }
+        return;
+    }
+
+    match previous {
+        Some(previous) => {
+            // Going around a closed road jumps from its end back to its start
+            let mut delta = distance - previous;
+            if delta < -length / 2.0 {
+                delta += length;
+            } else if delta > length / 2.0 {
+                delta -= length;
+            }
+            race.progress += delta;
+        },
+        _ => {
+            // Back on track, wherever that is, in the same lap
+            let lap_offset = (race.progress / length).floor() * length;
+            race.progress = distance + lap_offset;
+        }
+    }
+
+    // Over the line
+    if race.progress >= length {
+        race.progress -= length;
+
+        if let Some(time) = race.lap_time(tick) {
+            if race.next_checkpoint == road_network.checkpoints.len() {
+                let track = road_network.track_id();
+                let previous_best = best_laps.tracks.get(&track).map(|record| record.time);
+                let best = match previous_best {
+                    Some(previous_best) => time < previous_best,
+                    _ => true,
+                };
+                if best {
+                    best_laps.tracks.insert(track, LapRecord { time, splits: race.splits.clone() });
+                }
+
+                race.laps += 1;
+                race.last_lap = Some(time);
+                race_events.send(RaceEvent::LapCompleted {
+                    time,
+                    delta: previous_best.map(|previous_best| time - previous_best),
+                    best,
+                });
+            } else {
+                race_events.send(RaceEvent::MissedCheckpoint);
+            }
+        }
+
+        race.lap_start = Some(tick);
+        race.next_checkpoint = 0;
+        race.splits.clear();
+        race_events.send(RaceEvent::LapStarted(race.laps + 1));
+        return;
+    }
+
+    let time = match race.lap_time(tick) {
+        Some(time) => time,
+        _ => {
+            return;
+        }
+    };
+    while let Some(checkpoint) = road_network.checkpoints.get(race.next_checkpoint) {
+        if race.progress < checkpoint.distance {
+            break;
+        }
+
+        let index = race.next_checkpoint;
+        let best_split = best_laps.tracks
+            .get(&road_network.track_id())
+            .and_then(|record| record.splits.get(index))
+            .copied();
+        race.splits.push(time);
+        race.next_checkpoint += 1;
+        race_events.send(RaceEvent::CheckpointPassed {
+            index,
+            split: time,
+            delta: best_split.map(|best_split| time - best_split),
+        });
+    }
+}
+
+/// Loads the best laps given in `RaceConfig`.
+#[allow(unused_mut, unused_variables)]
+pub fn load_best_laps(config: Res<RaceConfig>, mut best_laps: ResMut<BestLaps>) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = match &config.best_laps_path {
+            Some(path) => path,
+            _ => {
+                return;
+            }
+        };
+        // No records yet
+        let serialized = match read_to_string(path) {
+            Ok(serialized) => serialized,
+            _ => {
+                return;
+            }
+        };
+        match serde_json::from_str(&serialized) {
+            Ok(loaded) => {
+                *best_laps = loaded;
+            },
+            Err(error) => {
+                error!("Could not load best laps {}: {}", path, error);
+            }
+        }
+    }
+}
+
+/// Saves the best laps when one of them is beaten.
+#[allow(unused_variables)]
+pub fn save_best_laps_system(
+    config: Res<RaceConfig>,
+    best_laps: Res<BestLaps>,
+    mut race_events: EventReader<RaceEvent>,
+) {
+    let new_best = race_events
+        .iter()
+        .any(|event| matches!(event, RaceEvent::LapCompleted { best: true, .. }));
+    if !new_best {
+        return;
+    }
+    let path = match &config.best_laps_path {
+        Some(path) => path,
+        _ => {
+            return;
+        }
+    };
+
+    let serialized = serde_json::to_string_pretty(&*best_laps).unwrap();
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        web_sys::console::log_1(&serialized.into());
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        write(path, serialized).unwrap();
+        info!("Saved best laps to {}", path);
+    }
+}
+
+/// Start line and checkpoint gates, rebuilt when they move.
+pub struct CheckpointGates {
+    entities: Vec<Entity>,
+    /// Distances and road length the gates were built for.
+    built_for: (Vec<f32>, f32),
+    post: Handle<Mesh>,
+    bar: Handle<Mesh>,
+    start_material: Handle<StandardMaterial>,
+    checkpoint_material: Handle<StandardMaterial>,
+}
+
+pub fn setup_checkpoint_gates(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let material = |color: Color| StandardMaterial {
+        base_color: color,
+        unlit: true,
+        ..default()
+    };
+
+    commands.insert_resource(CheckpointGates {
+        entities: Vec::new(),
+        built_for: (Vec::new(), 0.0),
+        post: meshes.add(Mesh::from(shape::Box::new(0.4, GATE_HEIGHT, 0.4))),
+        bar: meshes.add(Mesh::from(shape::Box::new(GATE_WIDTH, 0.6, 0.4))),
+        start_material: materials.add(material(Color::WHITE)),
+        checkpoint_material: materials.add(material(Color::ORANGE)),
+    });
+}
+
+pub fn checkpoint_gates_system(
+    mut commands: Commands,
+    game: Res<Game>,
+    mut gates: ResMut<CheckpointGates>,
+) {
+    let road_network = &game.road_network;
+    let distances: Vec<f32> = road_network.checkpoints.iter().map(|checkpoint| checkpoint.distance).collect();
+    let built_for = (distances, road_network.length());
+    if gates.built_for == built_for {
+        return;
+    }
+
+    for entity in gates.entities.drain(..) {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    // The start line, then the checkpoints
+    let gate_distances = std::iter::once(0.0).chain(built_for.0.iter().copied());
+    for (index, distance) in gate_distances.enumerate() {
+        let (position, direction, up) = match road_network.point_at(distance) {
+            Some(point) => point,
+            _ => {
+                continue;
+            }
+        };
+        let material = if index == 0 { gates.start_material.clone() } else { gates.checkpoint_material.clone() };
+        let transform = Transform::from_translation(position).looking_at(position + direction, up);
+
+        let gate = commands
+            .spawn_bundle(SpatialBundle::from_transform(transform))
+            .with_children(|parent| {
+                for side in [-1.0, 1.0] {
+                    parent.spawn_bundle(PbrBundle {
+                        mesh: gates.post.clone(),
+                        material: material.clone(),
+                        transform: Transform::from_xyz(side * GATE_WIDTH / 2.0, GATE_HEIGHT / 2.0, 0.0),
+                        ..default()
+                    });
+                }
+                parent.spawn_bundle(PbrBundle {
+                    mesh: gates.bar.clone(),
+                    material: material.clone(),
+                    transform: Transform::from_xyz(0.0, GATE_HEIGHT, 0.0),
+                    ..default()
+                });
+            })
+            .id();
+        gates.entities.push(gate);
+    }
+
+    gates.built_for = built_for;
+}
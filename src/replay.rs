@@ -0,0 +1,302 @@
+//! Input recording and playback.
+//!
+//! With `--record`, every simulation tick records the driving input, the
+//! pressed actions and the road editor changes.
+//! F6 saves the session to `replay.json`, and `--replay <file>` plays a saved
+//! session back instead of reading the devices.
+//!
+//! Replays only store input: they expect the same assets (road network,
+//! manifests, road follow settings) as when they were recorded.
+
+// This is only for native builds
+#[allow(unused_imports)]
+use std::fs::{read_to_string, write};
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    drive_input::DriveInput,
+    input_map::{Action, ActionState},
+    local_players::LocalPlayerInputs,
+    road_network_builder::{PendingRoadEdits, RoadEdit},
+    simulation::TIMESTEP,
+};
+
+pub const REPLAY_PATH: &str = "replay.json";
+
+/// Input of a single simulation tick.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TickInput {
+    pub drive: DriveInput,
+    /// Pressed actions, sorted.
+    pub actions: Vec<Action>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub road_edits: Vec<RoadEdit>,
+    /// Input of the local players after the first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other_players: Vec<PlayerTickInput>,
+}
+
+/// Input of a local player after the first, for a single tick.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PlayerTickInput {
+    pub drive: DriveInput,
+    /// Pressed actions, sorted.
+    pub actions: Vec<Action>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InputRecording {
+    /// Simulation timestep the recording was made with.
+    pub timestep: f32,
+    pub ticks: Vec<TickInput>,
+}
+
+impl Default for InputRecording {
+    fn default() -> Self {
+        InputRecording {
+            timestep: TIMESTEP,
+            ticks: Vec::new(),
+        }
+    }
+}
+
+impl InputRecording {
+    pub fn from_json(json: &str) -> Result<InputRecording, serde_json::Error> {
+        return serde_json::from_str(json);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayMode {
+    /// Input comes from the devices and isn't recorded.
+    Live,
+    /// Input comes from the devices and is appended to the recording.
+    Recording,
+    /// Input comes from the recording, until it runs out.
+    Playback,
+}
+
+pub struct Replay {
+    pub mode: ReplayMode,
+    pub recording: InputRecording,
+    /// Next tick to play back.
+    pub cursor: usize,
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Replay {
+            mode: ReplayMode::Live,
+            recording: InputRecording::default(),
+            cursor: 0,
+        }
+    }
+}
+
+impl Replay {
+    /// Records the session from the next tick.
+    pub fn recording() -> Self {
+        return Replay {
+            mode: ReplayMode::Recording,
+            ..default()
+        };
+    }
+
+    pub fn playback(recording: InputRecording) -> Self {
+        if (recording.timestep - TIMESTEP).abs() > f32::EPSILON {
+            warn!(
+                "Replay was recorded with a {}s timestep, the simulation uses {}s. It will diverge.",
+                recording.timestep, TIMESTEP
+            );
+        }
+
+        return Replay {
+            mode: ReplayMode::Playback,
+            recording,
+            cursor: 0,
+        };
+    }
+
+    /// Input of the next tick, from the recording or from `live_input`.
+    pub fn next_tick(&mut self, live_input: TickInput) -> TickInput {
+        if self.mode == ReplayMode::Playback {
+            if let Some(tick) = self.recording.ticks.get(self.cursor) {
+                self.cursor += 1;
+                return tick.clone();
+            }
+            info!("Replay finished after {} ticks", self.cursor);
+            self.mode = ReplayMode::Recording;
+        }
+
+        if self.mode == ReplayMode::Live {
+            return live_input;
+        }
+
+        self.recording.ticks.push(live_input.clone());
+        self.cursor = self.recording.ticks.len();
+        return live_input;
+    }
+}
+
+/// What the simulation reads its input from during a tick.
+///
+/// Actions are only updated once per tick, so a key press triggers
+/// the same number of times whatever the frame rate.
+#[derive(Default)]
+pub struct SimulationInput {
+    /// Number of ticks simulated so far.
+    pub tick: u64,
+    pub drive: DriveInput,
+    pub actions: ActionState,
+    pub road_edits: Vec<RoadEdit>,
+    /// Driving input and actions of the local players after the first.
+    pub other_players: Vec<(DriveInput, ActionState)>,
+}
+
+impl SimulationInput {
+    /// Driving input of a local player.
+    pub fn drive_of(&self, player: usize) -> DriveInput {
+        if player == 0 {
+            return self.drive;
+        }
+        return match self.other_players.get(player - 1) {
+            Some((drive, _)) => *drive,
+            _ => DriveInput::default(),
+        };
+    }
+
+    /// Actions of a local player.
+    pub fn actions_of(&self, player: usize) -> Option<&ActionState> {
+        if player == 0 {
+            return Some(&self.actions);
+        }
+        return self.other_players.get(player - 1).map(|(_, actions)| actions);
+    }
+}
+
+/// Samples the device input for this tick, or reads it from the replay.
+pub fn simulation_input_system(
+    drive_input: Res<DriveInput>,
+    action_state: Res<ActionState>,
+    mut pending_road_edits: ResMut<PendingRoadEdits>,
+    local_players: Option<Res<LocalPlayerInputs>>,
+    mut replay: ResMut<Replay>,
+    mut input: ResMut<SimulationInput>,
+) {
+    // Camera and debug actions (tuning, saving replays) don't affect the simulation
+    let simulated_actions = |action_state: &ActionState| -> Vec<Action> {
+        return action_state
+            .pressed_actions()
+            .into_iter()
+            .filter(|action| action.context().is_simulated())
+            .collect();
+    };
+    let other_players = match &local_players {
+        Some(local_players) => local_players.players
+            .iter()
+            .map(|player| PlayerTickInput {
+                drive: player.drive,
+                actions: simulated_actions(&player.actions),
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    let live_input = TickInput {
+        drive: *drive_input,
+        actions: simulated_actions(&action_state),
+        road_edits: pending_road_edits.edits.drain(..).collect(),
+        other_players,
+    };
+
+    let tick_input = replay.next_tick(live_input);
+
+    input.drive = tick_input.drive;
+    input.actions.set_pressed(&tick_input.actions);
+    input.road_edits = tick_input.road_edits;
+    input.other_players.resize_with(tick_input.other_players.len(), || (DriveInput::default(), ActionState::default()));
+    for ((drive, actions), player) in input.other_players.iter_mut().zip(tick_input.other_players.iter()) {
+        *drive = player.drive;
+        actions.set_pressed(&player.actions);
+    }
+    input.tick += 1;
+}
+
+/// Plays back the replay given with `--replay <file>`, or records the
+/// session with `--record`.
+#[allow(unused_mut, unused_variables)]
+pub fn load_replay_from_args(mut replay: ResMut<Replay>) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let args: Vec<String> = std::env::args().collect();
+        if args.iter().any(|arg| arg == "--record") {
+            info!("Recording the session, F6 saves it to {}", REPLAY_PATH);
+            *replay = Replay::recording();
+        }
+
+        let path = match args.iter().position(|arg| arg == "--replay") {
+            Some(index) => match args.get(index + 1) {
+                Some(path) => path,
+                _ => {
+                    error!("--replay expects a file");
+                    return;
+                }
+            },
+            _ => {
+                return;
+            }
+        };
+
+        let recording = match read_to_string(path).map_err(|error| error.to_string())
+            .and_then(|serialized| InputRecording::from_json(&serialized).map_err(|error| error.to_string())) {
+            Ok(recording) => recording,
+            Err(error) => {
+                error!("Could not load replay {}: {}", path, error);
+                return;
+            }
+        };
+
+        info!("Playing back {} ({} ticks)", path, recording.ticks.len());
+        *replay = Replay::playback(recording);
+    }
+}
+
+/// Sent when the session is saved, for feedback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayEvent {
+    /// Number of ticks saved.
+    Saved(usize),
+    /// The session isn't being recorded, there is nothing to save.
+    NotRecording,
+}
+
+/// Saves the session so far (F6).
+pub fn save_replay_system(
+    action_state: Res<ActionState>,
+    replay: Res<Replay>,
+    mut replay_events: EventWriter<ReplayEvent>,
+) {
+    if !action_state.just_released(Action::SaveReplay) {
+        return;
+    }
+
+    if replay.mode == ReplayMode::Live {
+        warn!("The session isn't recorded, start with --record to save replays");
+        replay_events.send(ReplayEvent::NotRecording);
+        return;
+    }
+
+    let serialized = serde_json::to_string(&replay.recording).unwrap();
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        web_sys::console::log_1(&serialized.into());
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        write(REPLAY_PATH, serialized).unwrap();
+        info!("Saved {} ticks to {}", replay.recording.ticks.len(), REPLAY_PATH);
+    }
+    replay_events.send(ReplayEvent::Saved(replay.recording.ticks.len()));
+}
@@ -0,0 +1,424 @@
+//! Road following controller settings, per vehicle class.
+//!
+//! Classes are read from `assets/road_follow.json` and can be tuned live
+//! with the tuning panel (F2), then saved back (F4).
+
+use std::collections::HashMap;
+
+// This is only for native builds
+#[allow(unused_imports)]
+use std::fs::{read_to_string, write};
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::{input_map::{Action, ActionState}, road_systems::RoadNetworkConfig};
+
+// This is only for wasm builds
+#[allow(unused_imports)]
+use crate::windowmailer;
+
+pub const ROAD_FOLLOW_PATH: &str = "assets/road_follow.json";
+pub const DEFAULT_ROAD_FOLLOW_CLASS: &str = "car";
+
+// The code here is not used in native builds
+#[allow(dead_code)]
+const ROAD_FOLLOW_CHANNEL: &str = "ROAD_FOLLOW_DATA";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PidGains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    /// Maximum magnitude of the accumulated error.
+    pub integral_limit: f32,
+    /// Maximum magnitude of the correction.
+    pub output_limit: f32,
+}
+
+impl Default for PidGains {
+    fn default() -> Self {
+        PidGains {
+            kp: 1.0,
+            ki: 0.0,
+            kd: 0.0,
+            integral_limit: 10.0,
+            output_limit: 1000.0,
+        }
+    }
+}
+
+impl PidGains {
+    pub fn scaled(&self, factor: f32) -> PidGains {
+        return PidGains {
+            kp: self.kp * factor,
+            ki: self.ki * factor,
+            kd: self.kd * factor,
+            ..*self
+        };
+    }
+}
+
+/// Accumulated state of a vector PID controller.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PidState {
+    pub integral: Vec3,
+}
+
+impl PidState {
+    /// `error_rate` is the measured rate of change of the error.
+    /// Measuring it (from velocities) instead of differentiating the error
+    /// avoids spikes when the closest segment changes.
+    pub fn update(&mut self, gains: &PidGains, error: Vec3, error_rate: Vec3, dt: f32) -> Vec3 {
+        let without_integral = error * gains.kp + error_rate * gains.kd;
+        let output = without_integral + self.integral * gains.ki;
+
+        // Anti-windup: stop accumulating while saturated,
+        // unless the new error helps unwinding the integral.
+        let saturated = output.length() > gains.output_limit;
+        if !saturated || error.dot(self.integral) < 0.0 {
+            self.integral = (self.integral + error * dt).clamp_length_max(gains.integral_limit);
+        }
+
+        return (without_integral + self.integral * gains.ki).clamp_length_max(gains.output_limit);
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = Vec3::ZERO;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RoadFollowSettings {
+    /// Torque aligning the vehicle forward and up vectors with the road.
+    pub alignment: PidGains,
+    /// Force pulling the vehicle towards the center of the road.
+    pub centering: PidGains,
+    /// Fraction of the misalignment corrected at once.
+    pub sub_target_fraction: f32,
+    /// Vehicles further than this from the road are left alone.
+    pub max_distance: f32,
+    /// Height above the road the vehicle is pulled to, when it doesn't hover by itself.
+    pub road_offset: f32,
+}
+
+impl Default for RoadFollowSettings {
+    fn default() -> Self {
+        RoadFollowSettings {
+            alignment: PidGains {
+                kp: 40.0,
+                ki: 0.0,
+                kd: 3.0,
+                integral_limit: 2.0,
+                output_limit: 200.0,
+            },
+            centering: PidGains {
+                kp: 4.0,
+                ki: 0.5,
+                kd: 2.0,
+                integral_limit: 5.0,
+                output_limit: 200.0,
+            },
+            sub_target_fraction: 0.8,
+            max_distance: 30.0,
+            road_offset: 5.5,
+        }
+    }
+}
+
+/// Names and values of the tunable parameters, in panel order.
+const PARAMETER_NAMES: [&str; 13] = [
+    "alignment.kp",
+    "alignment.ki",
+    "alignment.kd",
+    "alignment.integralLimit",
+    "alignment.outputLimit",
+    "centering.kp",
+    "centering.ki",
+    "centering.kd",
+    "centering.integralLimit",
+    "centering.outputLimit",
+    "subTargetFraction",
+    "maxDistance",
+    "roadOffset",
+];
+
+impl RoadFollowSettings {
+    pub fn parameter_mut(&mut self, index: usize) -> &mut f32 {
+        match index {
+            0 => &mut self.alignment.kp,
+            1 => &mut self.alignment.ki,
+            2 => &mut self.alignment.kd,
+            3 => &mut self.alignment.integral_limit,
+            4 => &mut self.alignment.output_limit,
+            5 => &mut self.centering.kp,
+            6 => &mut self.centering.ki,
+            7 => &mut self.centering.kd,
+            8 => &mut self.centering.integral_limit,
+            9 => &mut self.centering.output_limit,
+            10 => &mut self.sub_target_fraction,
+            11 => &mut self.max_distance,
+            _ => &mut self.road_offset,
+        }
+    }
+}
+
+/// Controller settings by vehicle class name.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoadFollowClasses {
+    pub classes: HashMap<String, RoadFollowSettings>,
+}
+
+impl Default for RoadFollowClasses {
+    fn default() -> Self {
+        let car = RoadFollowSettings::default();
+        // Trailers are pulled harder, they have no thrust of their own.
+        let trailer = RoadFollowSettings {
+            alignment: car.alignment.scaled(1.5),
+            centering: car.centering.scaled(1.5),
+            ..car.clone()
+        };
+
+        return RoadFollowClasses {
+            classes: HashMap::from([
+                (String::from(DEFAULT_ROAD_FOLLOW_CLASS), car),
+                (String::from("trailer"), trailer),
+            ]),
+        };
+    }
+}
+
+impl RoadFollowClasses {
+    pub fn from_json(json: &str) -> Result<RoadFollowClasses, serde_json::Error> {
+        return serde_json::from_str(json);
+    }
+
+    /// Settings of a class, falling back to the default class.
+    pub fn get(&self, class: &str) -> RoadFollowSettings {
+        return self.classes
+            .get(class)
+            .or_else(|| self.classes.get(DEFAULT_ROAD_FOLLOW_CLASS))
+            .cloned()
+            .unwrap_or_default();
+    }
+
+    pub fn class_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.classes.keys().cloned().collect();
+        names.sort();
+        return names;
+    }
+}
+
+/// Entities with this component are pulled towards the road.
+#[derive(Component, Debug, Clone, Default)]
+pub struct RoadFollower {
+    pub class: String,
+    pub forward: PidState,
+    pub up: PidState,
+    pub centering: PidState,
+}
+
+impl RoadFollower {
+    pub fn new(class: &str) -> Self {
+        return RoadFollower {
+            class: class.to_string(),
+            ..default()
+        };
+    }
+
+    pub fn reset(&mut self) {
+        self.forward.reset();
+        self.up.reset();
+        self.centering.reset();
+    }
+}
+
+fn parse_road_follow_classes(serialized: &str) -> RoadFollowClasses {
+    return match RoadFollowClasses::from_json(serialized) {
+        Ok(classes) => classes,
+        Err(error) => {
+            warn!("Invalid road follow settings, using defaults: {}", error);
+            RoadFollowClasses::default()
+        }
+    };
+}
+
+#[allow(unused_mut, unused_variables)]
+pub fn load_road_follow_classes(config: Res<RoadNetworkConfig>, mut classes: ResMut<RoadFollowClasses>) {
+    let path = match &config.road_follow_path {
+        Some(path) => path.clone(),
+        _ => {
+            return;
+        }
+    };
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        windowmailer::fetch_to_channel(path, String::from(ROAD_FOLLOW_CHANNEL));
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if let Ok(serialized) = read_to_string(path) {
+            *classes = parse_road_follow_classes(&serialized);
+        }
+    }
+}
+
+#[allow(unused_mut, unused_variables)]
+// Waits for settings fetched by wasm builds
+pub fn road_follow_load_check(mut classes: ResMut<RoadFollowClasses>) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        if windowmailer::message_count(String::from(ROAD_FOLLOW_CHANNEL)) == 0 {
+            return;
+        }
+        let serialized: String = windowmailer::read_message(String::from(ROAD_FOLLOW_CHANNEL));
+        *classes = parse_road_follow_classes(&serialized);
+    }
+}
+
+/// State of the live tuning panel.
+#[derive(Default)]
+pub struct RoadFollowTuning {
+    pub visible: bool,
+    pub class_index: usize,
+    pub parameter_index: usize,
+    pub panel: Option<Entity>,
+}
+
+pub fn setup_road_follow_tuning_panel(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut tuning: ResMut<RoadFollowTuning>,
+) {
+    let panel = commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSansMono.ttf"),
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(10.0),
+                    right: Val::Px(10.0),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(Visibility { is_visible: false })
+        .id();
+
+    tuning.panel = Some(panel);
+}
+
+/// Adjusts a parameter by 10%, or by a small step when it is zero.
+fn adjust_parameter(value: f32, increase: bool) -> f32 {
+    const FACTOR: f32 = 1.1;
+    const STEP: f32 = 0.1;
+
+    if value.abs() < STEP / 2.0 {
+        return if increase { STEP } else { 0.0 };
+    }
+    return if increase { value * FACTOR } else { value / FACTOR };
+}
+
+/// Sent by the tuning panel, for feedback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoadFollowEvent {
+    TuningSaved,
+}
+
+/// F2: show/hide, F3: next class, PageUp/PageDown: select, +/-: adjust, F4: save.
+#[allow(unused_variables)]
+pub fn road_follow_tuning_system(
+    action_state: Res<ActionState>,
+    config: Res<RoadNetworkConfig>,
+    mut tuning: ResMut<RoadFollowTuning>,
+    mut classes: ResMut<RoadFollowClasses>,
+    mut texts: Query<(&mut Text, &mut Visibility)>,
+    mut road_follow_events: EventWriter<RoadFollowEvent>,
+) {
+    if action_state.just_released(Action::ToggleTuningPanel) {
+        tuning.visible = !tuning.visible;
+    }
+
+    let panel = match tuning.panel {
+        Some(panel) => panel,
+        _ => {
+            return;
+        }
+    };
+    let (mut text, mut visibility) = match texts.get_mut(panel) {
+        Ok(components) => components,
+        _ => {
+            return;
+        }
+    };
+    visibility.is_visible = tuning.visible;
+
+    if !tuning.visible {
+        return;
+    }
+
+    let class_names = classes.class_names();
+    if class_names.is_empty() {
+        return;
+    }
+
+    if action_state.just_released(Action::TuningNextClass) {
+        tuning.class_index += 1;
+    }
+    tuning.class_index %= class_names.len();
+
+    if action_state.just_released(Action::TuningNextParameter) {
+        tuning.parameter_index = (tuning.parameter_index + 1) % PARAMETER_NAMES.len();
+    }
+    if action_state.just_released(Action::TuningPreviousParameter) {
+        tuning.parameter_index = (tuning.parameter_index + PARAMETER_NAMES.len() - 1) % PARAMETER_NAMES.len();
+    }
+
+    let class_name = &class_names[tuning.class_index];
+    let parameter_index = tuning.parameter_index;
+    if let Some(settings) = classes.classes.get_mut(class_name) {
+        let parameter = settings.parameter_mut(parameter_index);
+        if action_state.just_released(Action::TuningIncrease) {
+            *parameter = adjust_parameter(*parameter, true);
+        }
+        if action_state.just_released(Action::TuningDecrease) {
+            *parameter = adjust_parameter(*parameter, false);
+        }
+    }
+
+    if action_state.just_released(Action::SaveTuning) {
+        let serialized = serde_json::to_string_pretty(&*classes).unwrap();
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            web_sys::console::log_1(&serialized.into());
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = config.road_follow_path.clone().unwrap_or_else(|| String::from(ROAD_FOLLOW_PATH));
+            write(&path, serialized).unwrap();
+            info!("Saved {}", path);
+        }
+        road_follow_events.send(RoadFollowEvent::TuningSaved);
+    }
+
+    let mut settings = classes.get(class_name);
+    let mut content = format!("Road follow tuning: {}\n", class_name);
+    for (index, name) in PARAMETER_NAMES.iter().enumerate() {
+        let marker = if index == parameter_index { ">" } else { " " };
+        content.push_str(&format!("{} {:<24} {:>9.3}\n", marker, name, *settings.parameter_mut(index)));
+    }
+
+    text.sections[0].value = content;
+}
@@ -0,0 +1,234 @@
+//! Gizmo on the node selected in the road editor: arrows move it along
+//! the world axes, rings turn its heading and bank.
+//!
+//! Dragging a handle makes `RoadEdit`s, like dragging the node itself.
+
+use std::f32::consts::{FRAC_PI_2, TAU};
+
+use bevy::prelude::*;
+
+use crate::{editor::RoadEditor, game::Game};
+
+/// Length of the arrows, in world units.
+pub const GIZMO_SIZE: f32 = 8.0;
+/// Radius of the rings.
+pub const GIZMO_RING_RADIUS: f32 = 6.0;
+/// Handles closer than this to the cursor ray are picked.
+pub const GIZMO_PICK_DISTANCE: f32 = 1.0;
+/// Rotation per pixel of horizontal mouse movement, in radians.
+pub const GIZMO_ROTATE_SPEED: f32 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoHandle {
+    TranslateX,
+    TranslateY,
+    TranslateZ,
+    /// Ring around the node up vector.
+    Heading,
+    /// Ring around the road direction.
+    Bank,
+}
+
+impl GizmoHandle {
+    pub const ALL: [GizmoHandle; 5] = [
+        GizmoHandle::TranslateX,
+        GizmoHandle::TranslateY,
+        GizmoHandle::TranslateZ,
+        GizmoHandle::Heading,
+        GizmoHandle::Bank,
+    ];
+
+    pub fn is_rotation(&self) -> bool {
+        return matches!(self, GizmoHandle::Heading | GizmoHandle::Bank);
+    }
+
+    /// Direction of an arrow, or axis of a ring, for a node oriented by `rotation`.
+    pub fn axis(&self, rotation: Quat) -> Vec3 {
+        return match self {
+            GizmoHandle::TranslateX => Vec3::X,
+            GizmoHandle::TranslateY => Vec3::Y,
+            GizmoHandle::TranslateZ => Vec3::Z,
+            GizmoHandle::Heading => rotation * Vec3::Y,
+            GizmoHandle::Bank => rotation * Vec3::NEG_Z,
+        };
+    }
+
+    fn color(&self) -> Color {
+        return match self {
+            GizmoHandle::TranslateX => Color::RED,
+            GizmoHandle::TranslateY => Color::GREEN,
+            GizmoHandle::TranslateZ => Color::BLUE,
+            GizmoHandle::Heading => Color::YELLOW,
+            GizmoHandle::Bank => Color::CYAN,
+        };
+    }
+
+    /// Points along the handle, to pick it.
+    fn points(&self, node: Vec3, rotation: Quat) -> Vec<Vec3> {
+        const SAMPLES: usize = 32;
+        let axis = self.axis(rotation);
+
+        if !self.is_rotation() {
+            // Leave the middle to the node itself
+            return (SAMPLES / 4..=SAMPLES)
+                .map(|i| node + axis * GIZMO_SIZE * i as f32 / SAMPLES as f32)
+                .collect();
+        }
+
+        let radius = axis.any_orthonormal_vector() * GIZMO_RING_RADIUS;
+        return (0..SAMPLES)
+            .map(|i| node + Quat::from_axis_angle(axis, TAU * i as f32 / SAMPLES as f32) * radius)
+            .collect();
+    }
+}
+
+/// Distance from `point` to the ray.
+fn ray_point_distance(origin: Vec3, direction: Vec3, point: Vec3) -> f32 {
+    let t = (point - origin).dot(direction).max(0.0);
+    return point.distance(origin + direction * t);
+}
+
+/// Handle of the gizmo on `node` under the cursor ray, if any.
+///
+/// Arrows pointing along the ray can't be dragged and are skipped.
+/// Without a `rotation` (a node without segments), only the arrows are shown.
+pub fn pick_handle(node: Vec3, rotation: Option<Quat>, origin: Vec3, direction: Vec3) -> Option<GizmoHandle> {
+    let direction = direction.normalize();
+
+    // Rings seen edge-on cross the node, which drags the node itself
+    if ray_point_distance(origin, direction, node) <= GIZMO_PICK_DISTANCE * 2.0 {
+        return None;
+    }
+
+    return GizmoHandle::ALL
+        .iter()
+        .filter(|handle| match rotation {
+            Some(_) => true,
+            _ => !handle.is_rotation(),
+        })
+        .filter(|handle| handle.is_rotation() || handle.axis(Quat::IDENTITY).dot(direction).abs() < 0.95)
+        .map(|handle| {
+            let distance = handle
+                .points(node, rotation.unwrap_or(Quat::IDENTITY))
+                .iter()
+                .map(|point| ray_point_distance(origin, direction, *point))
+                .fold(f32::MAX, f32::min);
+            return (*handle, distance);
+        })
+        .filter(|(_, distance)| *distance <= GIZMO_PICK_DISTANCE)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(handle, _)| handle);
+}
+
+/// Position along the line through `axis_origin` closest to the ray,
+/// None when they are parallel.
+pub fn closest_on_axis(axis_origin: Vec3, axis: Vec3, origin: Vec3, direction: Vec3) -> Option<f32> {
+    let (axis, direction) = (axis.normalize(), direction.normalize());
+    let offset = axis_origin - origin;
+    let alignment = axis.dot(direction);
+    let denominator = 1.0 - alignment * alignment;
+    if denominator < 1e-4 {
+        return None;
+    }
+    return Some((alignment * direction.dot(offset) - axis.dot(offset)) / denominator);
+}
+
+/// A gizmo handle being dragged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GizmoDrag {
+    pub handle: GizmoHandle,
+    /// Arrows: where the arrow was grabbed, along the axis from the node.
+    /// Rings: cursor x of the previous frame.
+    pub last: f32,
+}
+
+/// Meshes of the gizmo handles.
+pub struct RoadGizmo {
+    handles: Vec<(GizmoHandle, Entity, Handle<StandardMaterial>)>,
+}
+
+pub fn setup_road_gizmo(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let arrow = meshes.add(Mesh::from(shape::Box::new(0.3, 0.3, GIZMO_SIZE)));
+    let ring = meshes.add(Mesh::from(shape::Torus {
+        radius: GIZMO_RING_RADIUS,
+        ring_radius: 0.15,
+        ..default()
+    }));
+
+    let handles = GizmoHandle::ALL
+        .iter()
+        .map(|handle| {
+            let material = materials.add(StandardMaterial {
+                base_color: handle.color(),
+                unlit: true,
+                ..default()
+            });
+            let entity = commands.spawn_bundle(PbrBundle {
+                mesh: if handle.is_rotation() { ring.clone() } else { arrow.clone() },
+                material: material.clone(),
+                visibility: Visibility { is_visible: false },
+                ..default()
+            }).id();
+            return (*handle, entity, material);
+        })
+        .collect();
+
+    commands.insert_resource(RoadGizmo { handles });
+}
+
+/// Places the gizmo on the selected node, and highlights the active handle.
+pub fn road_gizmo_system(
+    editor: Res<RoadEditor>,
+    game: Res<Game>,
+    gizmo: Res<RoadGizmo>,
+    mut handles: Query<(&mut Transform, &mut Visibility)>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let selected = match editor.selected {
+        Some(node) if editor.active => Some((node, game.road_network.node_rotation(node))),
+        _ => None,
+    };
+    let active_handle = editor.gizmo_drag.map(|drag| drag.handle).or(editor.hovered_handle);
+
+    for (handle, entity, material) in gizmo.handles.iter() {
+        let (mut transform, mut visibility) = match handles.get_mut(*entity) {
+            Ok(handle_entity) => handle_entity,
+            _ => {
+                continue;
+            }
+        };
+
+        let (node, rotation) = match selected {
+            Some((node, Some(rotation))) => (node, rotation),
+            Some((node, _)) if !handle.is_rotation() => (node, Quat::IDENTITY),
+            _ => {
+                visibility.is_visible = false;
+                continue;
+            }
+        };
+        visibility.is_visible = true;
+
+        let axis = handle.axis(rotation);
+        *transform = if handle.is_rotation() {
+            // Tori lie in the XZ plane
+            let ring_rotation = match handle {
+                GizmoHandle::Bank => rotation * Quat::from_rotation_x(FRAC_PI_2),
+                _ => rotation,
+            };
+            Transform::from_translation(node).with_rotation(ring_rotation)
+        } else {
+            Transform::from_translation(node + axis * GIZMO_SIZE / 2.0).looking_at(node + axis * GIZMO_SIZE, axis.any_orthogonal_vector())
+        };
+
+        let color = if active_handle == Some(*handle) { Color::WHITE } else { handle.color() };
+        if materials.get(material).map(|material| material.base_color) != Some(color) {
+            if let Some(material) = materials.get_mut(material) {
+                material.base_color = color;
+            }
+        }
+    }
+}
@@ -0,0 +1,556 @@
+//! Build road networks from external curve data.
+//!
+//! Supported sources:
+//!  - CSV or JSON lists of control points
+//!  - SVG paths (top-down layout) combined with a height profile
+//!  - GPX tracks
+//!
+//! Every importer produces a `RoadNetwork` that can be serialized and
+//! loaded like `assets/road_network.json`.
+
+use bevy::prelude::*;
+
+use crate::road_network_builder::{RoadNetwork, Segment};
+
+/// Points closer than this are merged, zero length segments break the mesh builder.
+const MIN_POINT_DISTANCE: f32 = 0.01;
+
+/// Mean earth radius in meters, used to project GPX coordinates.
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+/// How `up` vectors are computed for imported segments.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum UpMode {
+    /// Road is never tilted sideways. Up follows the slope only.
+    #[default]
+    Level,
+    /// Road is tilted towards the inside of curves.
+    /// `factor` converts curvature (radians per meter) to a bank angle (radians).
+    Banked { factor: f32, max_angle: f32 },
+}
+
+/// Height of the road at a given distance along the path.
+/// Keys are `(distance, height)` pairs, interpolated linearly.
+#[derive(Debug, Clone, Default)]
+pub struct HeightProfile {
+    pub keys: Vec<(f32, f32)>,
+}
+
+impl HeightProfile {
+    pub fn flat() -> Self {
+        HeightProfile { keys: Vec::new() }
+    }
+
+    pub fn height_at(&self, distance: f32) -> f32 {
+        let (first, last) = match (self.keys.first(), self.keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => {
+                return 0.0;
+            }
+        };
+
+        if distance <= first.0 {
+            return first.1;
+        }
+        if distance >= last.0 {
+            return last.1;
+        }
+
+        for pair in self.keys.windows(2) {
+            let (d0, h0) = pair[0];
+            let (d1, h1) = pair[1];
+            if distance >= d0 && distance <= d1 {
+                if d1 - d0 <= f32::EPSILON {
+                    return h1;
+                }
+                let t = (distance - d0) / (d1 - d0);
+                return h0 + (h1 - h0) * t;
+            }
+        }
+
+        return last.1;
+    }
+}
+
+/// Builds a road network from an ordered list of control points.
+pub fn road_network_from_points(points: &[Vec3], up_mode: UpMode) -> RoadNetwork {
+    let points = dedup_points(points);
+    let ups = compute_up_vectors(&points, up_mode);
+
+    let road_segments: Vec<Segment> = points
+        .windows(2)
+        .zip(ups)
+        .map(|(pair, up)| Segment { a: pair[0], b: pair[1], up, target_speed: None })
+        .collect();
+
+    let mut road_network = RoadNetwork::default();
+    road_network.last_position = points.last().copied();
+    road_network.road_segments = road_segments;
+    return road_network;
+}
+
+/// Computes one up vector per segment (`points.len() - 1` vectors).
+pub fn compute_up_vectors(points: &[Vec3], up_mode: UpMode) -> Vec<Vec3> {
+    let segment_count = points.len().saturating_sub(1);
+    let mut ups: Vec<Vec3> = Vec::with_capacity(segment_count);
+
+    for i in 0..segment_count {
+        let direction = (points[i + 1] - points[i]).normalize();
+
+        // World up, made perpendicular to the segment so slopes tilt the road forward.
+        let mut up = (Vec3::Y - direction * direction.dot(Vec3::Y)).normalize_or_zero();
+        if up == Vec3::ZERO {
+            // Vertical segment, any perpendicular vector will do.
+            up = direction.any_orthonormal_vector();
+        }
+
+        if let UpMode::Banked { factor, max_angle } = up_mode {
+            let bank = (factor * signed_curvature(points, i)).clamp(-max_angle, max_angle);
+            // Positive curvature is a left turn, rotating around the direction
+            // with a negative angle leans the road to the left.
+            up = Quat::from_axis_angle(direction, -bank) * up;
+        }
+
+        ups.push(up);
+    }
+
+    return ups;
+}
+
+/// Horizontal curvature around segment `index`, in radians per meter.
+/// Positive for left turns.
+fn signed_curvature(points: &[Vec3], index: usize) -> f32 {
+    let flat = |v: Vec3| Vec3::new(v.x, 0.0, v.z);
+    let last = points.len() - 1;
+
+    // Average the turn at both ends of the segment.
+    let mut total_angle = 0.0;
+    let mut total_length = 0.0;
+
+    for corner in [index, index + 1] {
+        if corner == 0 || corner == last {
+            continue;
+        }
+        let incoming = flat(points[corner] - points[corner - 1]);
+        let outgoing = flat(points[corner + 1] - points[corner]);
+        if incoming.length_squared() < f32::EPSILON || outgoing.length_squared() < f32::EPSILON {
+            continue;
+        }
+        let angle = incoming.cross(outgoing).y.atan2(incoming.dot(outgoing));
+        total_angle += angle;
+        total_length += (incoming.length() + outgoing.length()) / 2.0;
+    }
+
+    if total_length <= f32::EPSILON {
+        return 0.0;
+    }
+
+    return total_angle / total_length;
+}
+
+fn dedup_points(points: &[Vec3]) -> Vec<Vec3> {
+    let mut result: Vec<Vec3> = Vec::with_capacity(points.len());
+    for point in points {
+        match result.last() {
+            Some(last) if last.distance(*point) < MIN_POINT_DISTANCE => {}
+            _ => result.push(*point),
+        }
+    }
+    return result;
+}
+
+/// Parses control points from CSV. One `x,y,z` point per line.
+/// Empty lines, `#` comments and a non numeric header line are skipped.
+pub fn parse_csv_points(text: &str) -> Result<Vec<Vec3>, String> {
+    let mut points: Vec<Vec3> = Vec::new();
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let values: Vec<&str> = line.split([',', ';', '\t']).map(str::trim).collect();
+        let parsed: Result<Vec<f32>, _> = values.iter().map(|v| v.parse::<f32>()).collect();
+
+        match parsed {
+            Ok(values) if values.len() == 3 => points.push(Vec3::new(values[0], values[1], values[2])),
+            Ok(values) => {
+                return Err(format!("line {}: expected 3 values, found {}", line_index + 1, values.len()));
+            }
+            Err(_) if points.is_empty() && line_index == 0 => {
+                // Header line
+            }
+            Err(error) => {
+                return Err(format!("line {}: {}", line_index + 1, error));
+            }
+        }
+    }
+
+    return Ok(points);
+}
+
+/// Parses control points from JSON.
+/// Accepts `[[x, y, z], ...]` or `[{"x": .., "y": .., "z": ..}, ...]`.
+pub fn parse_json_points(text: &str) -> Result<Vec<Vec3>, String> {
+    let value: serde_json::Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let array = value.as_array().ok_or("expected a JSON array of points")?;
+
+    let mut points: Vec<Vec3> = Vec::with_capacity(array.len());
+
+    for (index, item) in array.iter().enumerate() {
+        let component = |v: Option<&serde_json::Value>| v.and_then(|v| v.as_f64()).map(|v| v as f32);
+
+        let point = match item {
+            serde_json::Value::Array(values) if values.len() == 3 => {
+                (component(values.first()), component(values.get(1)), component(values.get(2)))
+            }
+            serde_json::Value::Object(map) => {
+                (component(map.get("x")), component(map.get("y")), component(map.get("z")))
+            }
+            _ => (None, None, None),
+        };
+
+        match point {
+            (Some(x), Some(y), Some(z)) => points.push(Vec3::new(x, y, z)),
+            _ => {
+                return Err(format!("point {}: expected [x, y, z] or {{\"x\", \"y\", \"z\"}}", index));
+            }
+        }
+    }
+
+    return Ok(points);
+}
+
+/// Parses an SVG path `d` attribute and samples it into a polyline.
+///
+/// Supports M, L, H, V, C, S, Q, T and Z commands (absolute and relative).
+/// Arcs (A) are replaced by a straight line to their end point.
+pub fn parse_svg_path(d: &str, samples_per_curve: usize) -> Result<Vec<Vec2>, String> {
+    let tokens = tokenize_svg_path(d)?;
+    let samples = samples_per_curve.max(1);
+
+    let mut points: Vec<Vec2> = Vec::new();
+    let mut current = Vec2::ZERO;
+    let mut subpath_start = Vec2::ZERO;
+    // Reflected control point for S and T commands
+    let mut last_control: Option<Vec2> = None;
+    let mut command: Option<char> = None;
+    let mut index = 0;
+
+    let number = |index: &mut usize| -> Result<f32, String> {
+        match tokens.get(*index) {
+            Some(SvgToken::Number(n)) => {
+                *index += 1;
+                Ok(*n)
+            }
+            _ => Err(format!("expected a number at token {}", index)),
+        }
+    };
+
+    while index < tokens.len() {
+        if let SvgToken::Command(c) = tokens[index] {
+            command = Some(c);
+            index += 1;
+        }
+
+        let c = match command {
+            Some(c) => c,
+            None => {
+                return Err(String::from("path data must start with a command"));
+            }
+        };
+        let relative = c.is_ascii_lowercase();
+        let origin = if relative { current } else { Vec2::ZERO };
+
+        match c.to_ascii_uppercase() {
+            'M' => {
+                current = origin + Vec2::new(number(&mut index)?, number(&mut index)?);
+                subpath_start = current;
+                points.push(current);
+                // Extra coordinates after a moveto are implicit linetos
+                command = Some(if relative { 'l' } else { 'L' });
+                last_control = None;
+            }
+            'L' => {
+                current = origin + Vec2::new(number(&mut index)?, number(&mut index)?);
+                points.push(current);
+                last_control = None;
+            }
+            'H' => {
+                let x = number(&mut index)?;
+                current = Vec2::new(if relative { current.x + x } else { x }, current.y);
+                points.push(current);
+                last_control = None;
+            }
+            'V' => {
+                let y = number(&mut index)?;
+                current = Vec2::new(current.x, if relative { current.y + y } else { y });
+                points.push(current);
+                last_control = None;
+            }
+            'C' | 'S' => {
+                let c1 = if c.eq_ignore_ascii_case(&'C') {
+                    origin + Vec2::new(number(&mut index)?, number(&mut index)?)
+                } else {
+                    match last_control {
+                        Some(control) => current * 2.0 - control,
+                        None => current,
+                    }
+                };
+                let c2 = origin + Vec2::new(number(&mut index)?, number(&mut index)?);
+                let end = origin + Vec2::new(number(&mut index)?, number(&mut index)?);
+                for step in 1..=samples {
+                    let t = step as f32 / samples as f32;
+                    points.push(cubic_bezier(current, c1, c2, end, t));
+                }
+                current = end;
+                last_control = Some(c2);
+            }
+            'Q' | 'T' => {
+                let control = if c.eq_ignore_ascii_case(&'Q') {
+                    origin + Vec2::new(number(&mut index)?, number(&mut index)?)
+                } else {
+                    match last_control {
+                        Some(control) => current * 2.0 - control,
+                        None => current,
+                    }
+                };
+                let end = origin + Vec2::new(number(&mut index)?, number(&mut index)?);
+                for step in 1..=samples {
+                    let t = step as f32 / samples as f32;
+                    points.push(quadratic_bezier(current, control, end, t));
+                }
+                current = end;
+                last_control = Some(control);
+            }
+            'A' => {
+                // rx ry rotation large-arc sweep x y
+                for _ in 0..5 {
+                    number(&mut index)?;
+                }
+                current = origin + Vec2::new(number(&mut index)?, number(&mut index)?);
+                points.push(current);
+                last_control = None;
+            }
+            'Z' => {
+                current = subpath_start;
+                points.push(current);
+                last_control = None;
+                command = None;
+            }
+            other => {
+                return Err(format!("unsupported path command '{}'", other));
+            }
+        }
+    }
+
+    return Ok(points);
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SvgToken {
+    Command(char),
+    Number(f32),
+}
+
+fn tokenize_svg_path(d: &str) -> Result<Vec<SvgToken>, String> {
+    let mut tokens: Vec<SvgToken> = Vec::new();
+    let chars: Vec<char> = d.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() || c == ',' {
+            i += 1;
+        } else if c.is_ascii_alphabetic() && c != 'e' && c != 'E' {
+            tokens.push(SvgToken::Command(c));
+            i += 1;
+        } else if c == '-' || c == '+' || c == '.' || c.is_ascii_digit() {
+            let start = i;
+            let mut seen_dot = c == '.';
+            i += 1;
+            while i < chars.len() {
+                let n = chars[i];
+                if n.is_ascii_digit() {
+                    i += 1;
+                } else if n == '.' && !seen_dot {
+                    seen_dot = true;
+                    i += 1;
+                } else if (n == 'e' || n == 'E') && i + 1 < chars.len() {
+                    // Exponent, possibly signed
+                    i += 1;
+                    if chars[i] == '-' || chars[i] == '+' {
+                        i += 1;
+                    }
+                } else {
+                    break;
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text.parse::<f32>().map_err(|_| format!("invalid number '{}'", text))?;
+            tokens.push(SvgToken::Number(value));
+        } else {
+            return Err(format!("unexpected character '{}' in path data", c));
+        }
+    }
+
+    return Ok(tokens);
+}
+
+fn cubic_bezier(p0: Vec2, p1: Vec2, p2: Vec2, p3: Vec2, t: f32) -> Vec2 {
+    let u = 1.0 - t;
+    return p0 * (u * u * u) + p1 * (3.0 * u * u * t) + p2 * (3.0 * u * t * t) + p3 * (t * t * t);
+}
+
+fn quadratic_bezier(p0: Vec2, p1: Vec2, p2: Vec2, t: f32) -> Vec2 {
+    let u = 1.0 - t;
+    return p0 * (u * u) + p1 * (2.0 * u * t) + p2 * (t * t);
+}
+
+/// Lifts a top-down layout into 3D.
+/// Layout `x` maps to world `x`, layout `y` to world `z` (SVG y points down,
+/// which matches +z when looking down from above with -z forward).
+/// Heights are read from the profile using the distance along the layout.
+pub fn apply_height_profile(layout: &[Vec2], scale: f32, profile: &HeightProfile) -> Vec<Vec3> {
+    let mut points: Vec<Vec3> = Vec::with_capacity(layout.len());
+    let mut distance = 0.0;
+
+    for (index, point) in layout.iter().enumerate() {
+        if index > 0 {
+            distance += (*point - layout[index - 1]).length() * scale;
+        }
+        points.push(Vec3::new(point.x * scale, profile.height_at(distance), point.y * scale));
+    }
+
+    return points;
+}
+
+/// Parses track (`trkpt`) or route (`rtept`) points from a GPX document.
+///
+/// Coordinates are projected to meters around the first point:
+/// east is +x, north is -z and elevation (if any) is y, relative to the first point.
+pub fn parse_gpx(text: &str) -> Result<Vec<Vec3>, String> {
+    let mut coordinates: Vec<(f64, f64, f64)> = Vec::new();
+    let mut rest = text;
+
+    loop {
+        let start = match (rest.find("<trkpt"), rest.find("<rtept")) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) => a,
+            (None, Some(b)) => b,
+            (None, None) => break,
+        };
+        rest = &rest[start..];
+
+        let tag_end = rest.find('>').ok_or("unterminated point tag")?;
+        let tag = &rest[..tag_end];
+        let lat = xml_attribute(tag, "lat").ok_or("point without lat")?;
+        let lon = xml_attribute(tag, "lon").ok_or("point without lon")?;
+
+        // Points are either self closing or contain children such as <ele>
+        let self_closing = tag.ends_with('/');
+        let body_end = if self_closing {
+            tag_end
+        } else {
+            rest.find("</trkpt>").or_else(|| rest.find("</rtept>")).unwrap_or(tag_end)
+        };
+        let body = &rest[tag_end..body_end.max(tag_end)];
+        let elevation = match (body.find("<ele>"), body.find("</ele>")) {
+            (Some(a), Some(b)) if b > a => body[a + 5..b].trim().parse::<f64>().map_err(|e| e.to_string())?,
+            _ => 0.0,
+        };
+
+        coordinates.push((lat, lon, elevation));
+        rest = &rest[tag_end..];
+    }
+
+    let (lat0, lon0, ele0) = match coordinates.first() {
+        Some(first) => *first,
+        None => {
+            return Err(String::from("no track points found"));
+        }
+    };
+    let cos_lat0 = lat0.to_radians().cos();
+
+    let points = coordinates
+        .iter()
+        .map(|(lat, lon, ele)| {
+            let east = (lon - lon0).to_radians() * EARTH_RADIUS * cos_lat0;
+            let north = (lat - lat0).to_radians() * EARTH_RADIUS;
+            Vec3::new(east as f32, (ele - ele0) as f32, -north as f32)
+        })
+        .collect();
+
+    return Ok(points);
+}
+
+fn xml_attribute(tag: &str, name: &str) -> Option<f64> {
+    let pattern = format!("{}=", name);
+    let mut search = tag;
+
+    // Make sure we match a whole attribute name, not a suffix of another one.
+    loop {
+        let position = search.find(&pattern)?;
+        let preceded_by_space = position == 0 || search[..position].ends_with(char::is_whitespace);
+        let value = &search[position + pattern.len()..];
+        if !preceded_by_space {
+            search = value;
+            continue;
+        }
+        let quote = value.chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        let end = value[1..].find(quote)?;
+        return value[1..end + 1].parse::<f64>().ok();
+    }
+}
+
+/// Drops points closer than `spacing` to the previously kept point.
+/// Useful to filter GPS jitter before computing banking.
+pub fn resample_min_spacing(points: &[Vec3], spacing: f32) -> Vec<Vec3> {
+    let mut result: Vec<Vec3> = Vec::new();
+    for (index, point) in points.iter().enumerate() {
+        let is_last = index == points.len() - 1;
+        match result.last() {
+            Some(last) if last.distance(*point) < spacing && !is_last => {}
+            _ => result.push(*point),
+        }
+    }
+    return result;
+}
+
+/// Imports a CSV control point list.
+pub fn import_csv(text: &str, up_mode: UpMode) -> Result<RoadNetwork, String> {
+    let points = parse_csv_points(text)?;
+    return Ok(road_network_from_points(&points, up_mode));
+}
+
+/// Imports a JSON control point list.
+pub fn import_json(text: &str, up_mode: UpMode) -> Result<RoadNetwork, String> {
+    let points = parse_json_points(text)?;
+    return Ok(road_network_from_points(&points, up_mode));
+}
+
+/// Imports an SVG path for the top-down layout with a height profile.
+/// `scale` converts SVG units to meters.
+pub fn import_svg_path(
+    d: &str,
+    scale: f32,
+    profile: &HeightProfile,
+    up_mode: UpMode,
+) -> Result<RoadNetwork, String> {
+    const SAMPLES_PER_CURVE: usize = 16;
+    let layout = parse_svg_path(d, SAMPLES_PER_CURVE)?;
+    let points = apply_height_profile(&layout, scale, profile);
+    return Ok(road_network_from_points(&points, up_mode));
+}
+
+/// Imports a GPX track. Points closer than `min_spacing` meters are dropped.
+pub fn import_gpx(text: &str, min_spacing: f32, up_mode: UpMode) -> Result<RoadNetwork, String> {
+    let points = parse_gpx(text)?;
+    let points = resample_min_spacing(&points, min_spacing);
+    return Ok(road_network_from_points(&points, up_mode));
+}
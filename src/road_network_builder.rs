@@ -1,15 +1,21 @@
 use bevy::{
     prelude::*, render::{render_resource::PrimitiveTopology, mesh::Indices},
 };
+use bevy_rapier3d::prelude::*;
 
 use serde::{Serialize, Deserialize};
 
+use crate::road_profile::{bank_angle, banked_up, blend_sections, RoadProfile};
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Segment {
     pub a: Vec3,
     pub b: Vec3,
     /// Normal/Up vector of the segment. (usually, the up vector of a car driving on the road)
     pub up: Vec3,
+    /// Speed AI vehicles drive at on this segment, see `ai`. They use their own default when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_speed: Option<f32>,
 }
 
 #[derive(Default, Serialize, Deserialize, Clone, Debug)]
@@ -18,11 +24,423 @@ pub struct Macro {
 }
 
 
+/// Gate to drive through during a lap, see `race`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Checkpoint {
+    /// Distance along the road.
+    pub distance: f32,
+}
+
+/// Traffic lane along the road, see `traffic`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Lane {
+    /// Distance to the right of the centerline, looking along the road.
+    pub offset: f32,
+    /// Traffic drives from the end of the road to its start.
+    #[serde(default)]
+    pub reverse: bool,
+}
+
 #[derive(Default, Serialize, Deserialize, Clone, Debug)]
 pub struct RoadNetwork {
     pub last_position: Option<Vec3>,
     pub road_segments: Vec<Segment>,
     pub macros: Vec<Macro>,
+    /// Sorted by distance.
+    #[serde(default)]
+    pub checkpoints: Vec<Checkpoint>,
+    /// Traffic lanes. A road without lanes has one on its centerline.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lanes: Vec<Lane>,
+}
+
+/// A change to the road network made in the road editor.
+///
+/// Edits are part of the simulation input, so replays reproduce them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RoadEdit {
+    /// Continues the road to a new node, like inserting a segment with the trailer.
+    AddNode { position: Vec3, up: Vec3 },
+    /// Moves the node at `from`, and the ends of every segment on it.
+    MoveNode { from: Vec3, to: Vec3 },
+    /// Rotates the node at `node`, see `RoadNetwork::rotate_node`.
+    RotateNode { node: Vec3, rotation: Quat },
+    /// Banks and raises the road, see `RoadNetwork::apply_profile`.
+    ApplyProfile { profile: RoadProfile },
+}
+
+impl RoadEdit {
+    /// Whether every position, vector and profile value of the edit is a finite number.
+    pub fn is_finite(&self) -> bool {
+        return match self {
+            RoadEdit::AddNode { position, up } => position.is_finite() && up.is_finite(),
+            RoadEdit::MoveNode { from, to } => from.is_finite() && to.is_finite(),
+            RoadEdit::RotateNode { node, rotation } => node.is_finite() && rotation.is_finite(),
+            RoadEdit::ApplyProfile { profile } => profile
+                .bank
+                .iter()
+                .chain(profile.elevation.iter())
+                .all(|section| [section.from, section.to, section.value, section.ease].iter().all(|value| value.is_finite())),
+        };
+    }
+}
+
+/// Edits made with the mouse since the last simulation tick.
+#[derive(Default, Debug)]
+pub struct PendingRoadEdits {
+    pub edits: Vec<RoadEdit>,
+}
+
+impl RoadNetwork {
+    /// Continues the road from `last_position` to `position`.
+    /// The first node only sets `last_position`.
+    pub fn add_node(&mut self, position: Vec3, up: Vec3) {
+        let last_position = match self.last_position {
+            Some(last_position) => last_position,
+            _ => {
+                self.last_position = Some(position);
+                return;
+            }
+        };
+
+        self.last_position = Some(position);
+        self.road_segments.push(Segment { a: last_position, b: position, up, target_speed: None });
+    }
+
+    /// Moves every segment end at `from` to `to`.
+    pub fn move_node(&mut self, from: Vec3, to: Vec3) {
+        for segment in self.road_segments.iter_mut() {
+            if segment.a == from {
+                segment.a = to;
+            }
+            if segment.b == from {
+                segment.b = to;
+            }
+        }
+        if self.last_position == Some(from) {
+            self.last_position = Some(to);
+        }
+    }
+
+    pub fn apply_edit(&mut self, edit: &RoadEdit) {
+        match edit {
+            RoadEdit::AddNode { position, up } => self.add_node(*position, *up),
+            RoadEdit::MoveNode { from, to } => self.move_node(*from, *to),
+            RoadEdit::RotateNode { node, rotation } => self.rotate_node(*node, *rotation),
+            RoadEdit::ApplyProfile { profile } => self.apply_profile(profile),
+        }
+    }
+
+    /// Segment ends and the last position, without duplicates.
+    pub fn nodes(&self) -> Vec<Vec3> {
+        let mut nodes: Vec<Vec3> = Vec::new();
+        let ends = self.road_segments.iter().flat_map(|segment| [segment.a, segment.b]);
+        for node in ends.chain(self.last_position) {
+            if !nodes.contains(&node) {
+                nodes.push(node);
+            }
+        }
+        return nodes;
+    }
+
+    /// Closest node to `point` on the horizontal plane, within `max_distance`.
+    pub fn closest_node(&self, point: Vec3, max_distance: f32) -> Option<Vec3> {
+        let horizontal_distance = |node: &Vec3| Vec2::new(node.x - point.x, node.z - point.z).length();
+        return self.nodes()
+            .into_iter()
+            .filter(|node| horizontal_distance(node) <= max_distance)
+            .min_by(|a, b| horizontal_distance(a).total_cmp(&horizontal_distance(b)));
+    }
+
+    /// Segment giving the heading and bank of `node`: the one leaving it,
+    /// else the one arriving, like the road mesh. True when it leaves the node.
+    fn node_segment(&self, node: Vec3) -> Option<(usize, bool)> {
+        if let Some(index) = self.road_segments.iter().position(|segment| segment.a == node) {
+            return Some((index, true));
+        }
+        return self.road_segments
+            .iter()
+            .position(|segment| segment.b == node)
+            .map(|index| (index, false));
+    }
+
+    /// Orientation of the road at `node`: -Z along the road, Y up.
+    /// None for a node without segments.
+    pub fn node_rotation(&self, node: Vec3) -> Option<Quat> {
+        let (index, _) = self.node_segment(node)?;
+        let segment = &self.road_segments[index];
+        let heading = segment.b - segment.a;
+        if heading.cross(segment.up).length_squared() < f32::EPSILON {
+            return None;
+        }
+        return Some(Transform::identity().looking_at(heading, segment.up).rotation);
+    }
+
+    /// Rotates the road at `node`: the up vector of its segment (the bank),
+    /// and the heading, by swinging the other end of that segment around the node.
+    pub fn rotate_node(&mut self, node: Vec3, rotation: Quat) {
+        let (index, outgoing) = match self.node_segment(node) {
+            Some(node_segment) => node_segment,
+            _ => {
+                return;
+            }
+        };
+
+        let segment = &mut self.road_segments[index];
+        segment.up = (rotation * segment.up).normalize();
+        let far = if outgoing { segment.b } else { segment.a };
+        if far != node {
+            self.move_node(far, node + rotation * (far - node));
+        }
+    }
+
+    /// Applies the bank and elevation of `profile` to the segments.
+    /// Distances run along `road_segments`, in order.
+    pub fn apply_profile(&mut self, profile: &RoadProfile) {
+        // Distances along the road before the heights change
+        let mut distance: f32 = 0.0;
+        let mut node_distances: Vec<(f32, f32)> = Vec::new();
+        for segment in self.road_segments.iter() {
+            let end = distance + segment.a.distance(segment.b);
+            node_distances.push((distance, end));
+            distance = end;
+        }
+
+        let last_segment_end = self.road_segments.last().map(|segment| segment.b);
+        for (segment, (start, end)) in self.road_segments.iter_mut().zip(node_distances) {
+            let previous_direction = segment.b - segment.a;
+            segment.a.y = blend_sections(&profile.elevation, start, segment.a.y);
+            segment.b.y = blend_sections(&profile.elevation, end, segment.b.y);
+            let direction = segment.b - segment.a;
+
+            // The mesh banks each node with the segment leaving it
+            let banked = profile.bank.iter().any(|section| section.weight(start) > 0.0);
+            if (!banked && direction == previous_direction) || direction.length_squared() < f32::EPSILON {
+                continue;
+            }
+            let current_bank = if previous_direction.length_squared() < f32::EPSILON {
+                0.0
+            } else {
+                bank_angle(previous_direction, segment.up)
+            };
+            let bank = blend_sections(&profile.bank, start, current_bank);
+            segment.up = banked_up(direction, bank);
+        }
+
+        if self.last_position.is_some() && self.last_position == last_segment_end {
+            self.last_position = self.road_segments.last().map(|segment| segment.b);
+        }
+    }
+
+    /// Total length of the road segments.
+    pub fn length(&self) -> f32 {
+        return self.road_segments.iter().map(|segment| segment.a.distance(segment.b)).sum();
+    }
+
+    /// Distance along the road of the point closest to `point`, and how far that is.
+    /// Distances run along `road_segments`, in order.
+    pub fn distance_along(&self, point: Vec3) -> Option<(f32, f32)> {
+        let mut start: f32 = 0.0;
+        let mut closest: Option<(f32, f32)> = None;
+        for segment in self.road_segments.iter() {
+            let ab = segment.b - segment.a;
+            let t = ((point - segment.a).dot(ab) / ab.length_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+            let off = point.distance(segment.a + ab * t);
+            let closer = match closest {
+                Some((_, closest_off)) => off < closest_off,
+                _ => true,
+            };
+            if closer {
+                closest = Some((start + ab.length() * t, off));
+            }
+            start += ab.length();
+        }
+        return closest;
+    }
+
+    /// Position, direction and up vector of the road `distance` along it.
+    pub fn point_at(&self, distance: f32) -> Option<(Vec3, Vec3, Vec3)> {
+        let mut start: f32 = 0.0;
+        for (index, segment) in self.road_segments.iter().enumerate() {
+            let ab = segment.b - segment.a;
+            let length = ab.length();
+            let last = index + 1 == self.road_segments.len();
+            if (distance <= start + length || last) && length > f32::EPSILON {
+                let t = ((distance - start) / length).clamp(0.0, 1.0);
+                return Some((segment.a + ab * t, ab / length, segment.up));
+            }
+            start += length;
+        }
+        return None;
+    }
+
+    /// Target speed of the segment `distance` along the road, see `Segment::target_speed`.
+    pub fn target_speed_at(&self, distance: f32) -> Option<f32> {
+        let mut start: f32 = 0.0;
+        for (index, segment) in self.road_segments.iter().enumerate() {
+            start += segment.a.distance(segment.b);
+            if distance <= start || index + 1 == self.road_segments.len() {
+                return segment.target_speed;
+            }
+        }
+        return None;
+    }
+
+    /// Traffic lanes, or the centerline when there are none.
+    pub fn traffic_lanes(&self) -> Vec<Lane> {
+        if self.lanes.is_empty() {
+            return vec![Lane { offset: 0.0, reverse: false }];
+        }
+        return self.lanes.clone();
+    }
+
+    /// Whether the road ends where it starts, so driving along it goes around.
+    pub fn is_closed(&self) -> bool {
+        return match (self.road_segments.first(), self.road_segments.last()) {
+            (Some(first), Some(last)) => self.road_segments.len() > 2 && first.a.distance(last.b) < 1.0,
+            _ => false,
+        };
+    }
+
+    /// Adds a checkpoint at `distance`, or removes the one within `tolerance`.
+    /// Returns true if it was added.
+    pub fn toggle_checkpoint(&mut self, distance: f32, tolerance: f32) -> bool {
+        let existing = self.checkpoints
+            .iter()
+            .position(|checkpoint| (checkpoint.distance - distance).abs() <= tolerance);
+        if let Some(index) = existing {
+            self.checkpoints.remove(index);
+            return false;
+        }
+
+        self.checkpoints.push(Checkpoint { distance });
+        self.checkpoints.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        return true;
+    }
+
+    /// Identifies a track layout, to keep records per track.
+    /// Changes when the road or its checkpoints do.
+    pub fn track_id(&self) -> String {
+        // FNV-1a, stable across builds unlike `DefaultHasher`
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut add = |value: f32| {
+            for byte in value.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        for segment in self.road_segments.iter() {
+            for vector in [segment.a, segment.b, segment.up] {
+                vector.to_array().into_iter().for_each(&mut add);
+            }
+            add(segment.target_speed.unwrap_or(f32::NAN));
+        }
+        for checkpoint in self.checkpoints.iter() {
+            add(checkpoint.distance);
+        }
+        return format!("{:016x}", hash);
+    }
+
+    /// Distance from `point` to the closest road segment, if there is a road.
+    pub fn distance_to(&self, point: Vec3) -> Option<f32> {
+        return self.road_segments
+            .iter()
+            .map(|segment| {
+                let ab = segment.b - segment.a;
+                let t = (point - segment.a).dot(ab) / ab.length_squared().max(f32::EPSILON);
+                return point.distance(segment.a + ab * t.clamp(0.0, 1.0));
+            })
+            .min_by(|a, b| a.total_cmp(b));
+    }
+}
+
+/// Segment start distances, to find points along the road without walking every segment.
+/// Build one per tick and share it between the vehicles using it.
+pub struct RoadPath<'a> {
+    road_network: &'a RoadNetwork,
+    starts: Vec<f32>,
+    pub length: f32,
+    pub closed: bool,
+}
+
+impl<'a> RoadPath<'a> {
+    pub fn new(road_network: &'a RoadNetwork) -> Self {
+        let mut starts = Vec::with_capacity(road_network.road_segments.len());
+        let mut length = 0.0;
+        for segment in road_network.road_segments.iter() {
+            starts.push(length);
+            length += segment.a.distance(segment.b);
+        }
+        return RoadPath { road_network, starts, length, closed: road_network.is_closed() };
+    }
+
+    /// Index of the segment `distance` along the road.
+    fn segment_index(&self, distance: f32) -> Option<usize> {
+        if self.starts.is_empty() {
+            return None;
+        }
+        return Some(self.starts.partition_point(|start| *start <= distance).max(1) - 1);
+    }
+
+    /// Position, direction and up vector, like `RoadNetwork::point_at`.
+    pub fn point_at(&self, distance: f32) -> Option<(Vec3, Vec3, Vec3)> {
+        let index = self.segment_index(distance)?;
+        let segment = &self.road_network.road_segments[index];
+        let ab = segment.b - segment.a;
+        let length = ab.length();
+        if length <= f32::EPSILON {
+            return self.road_network.point_at(distance);
+        }
+        let t = ((distance - self.starts[index]) / length).clamp(0.0, 1.0);
+        return Some((segment.a + ab * t, ab / length, segment.up));
+    }
+
+    /// Target speed of the segment `distance` along the road, like `RoadNetwork::target_speed_at`.
+    pub fn target_speed_at(&self, distance: f32) -> Option<f32> {
+        let index = self.segment_index(distance)?;
+        return self.road_network.road_segments[index].target_speed;
+    }
+
+    /// Distance along the road of the point closest to `point`, and how far that is,
+    /// like `RoadNetwork::distance_along`.
+    pub fn distance_along(&self, point: Vec3) -> Option<(f32, f32)> {
+        let mut closest: Option<(f32, f32)> = None;
+        for (segment, start) in self.road_network.road_segments.iter().zip(self.starts.iter()) {
+            let ab = segment.b - segment.a;
+            let t = ((point - segment.a).dot(ab) / ab.length_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+            let off = point.distance_squared(segment.a + ab * t);
+            let closer = match closest {
+                Some((_, closest_off)) => off < closest_off,
+                _ => true,
+            };
+            if closer {
+                closest = Some((start + ab.length() * t, off));
+            }
+        }
+        return closest.map(|(distance, off)| (distance, off.sqrt()));
+    }
+
+    /// Distance along the road, wrapped around closed roads.
+    pub fn wrap(&self, distance: f32) -> f32 {
+        if self.closed && self.length > 0.0 {
+            return distance.rem_euclid(self.length);
+        }
+        return distance;
+    }
+
+    /// Distance along the road of `progress` along `lane`.
+    pub fn lane_distance(&self, lane: &Lane, progress: f32) -> f32 {
+        return if lane.reverse { self.length - progress } else { progress };
+    }
+
+    /// Where a car `progress` along `lane` is, and the way it faces.
+    pub fn lane_transform(&self, lane: &Lane, progress: f32, height: f32) -> Option<Transform> {
+        let (point, direction, up) = self.point_at(self.lane_distance(lane, progress))?;
+        let right = direction.cross(up).normalize_or_zero();
+        let position = point + right * lane.offset + up * height;
+        let heading = if lane.reverse { -direction } else { direction };
+        return Some(Transform::from_translation(position).looking_at(position + heading, up));
+    }
 }
 
 /// Compute a triangle's normal
@@ -105,6 +523,15 @@ pub fn build_road_network(
         indices.push(3 + i * 4);
     }
 
+    // Hover thrusters need something to cast rays against.
+    let collider: Option<Collider> = if indices.is_empty() {
+        None
+    } else {
+        let vertices: Vec<Vec3> = position_attributes.iter().map(|p| Vec3::from(*p)).collect();
+        let triangles: Vec<[u32; 3]> = indices.chunks(3).map(|t| [t[0], t[1], t[2]]).collect();
+        Some(Collider::trimesh(vertices, triangles))
+    };
+
     let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
     mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, position_attributes);
     mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normal_attributes);
@@ -117,9 +544,15 @@ pub fn build_road_network(
         ..Default::default()
     });
 
-    return commands.spawn_bundle(PbrBundle {
+    let mut entity_commands = commands.spawn_bundle(PbrBundle {
         mesh: meshes.add(mesh),
         material: material,
         ..default()
-    }).id();
+    });
+
+    if let Some(collider) = collider {
+        entity_commands.insert(collider);
+    }
+
+    return entity_commands.id();
 }
@@ -0,0 +1,90 @@
+//! Bank and elevation of a road as curves over the distance along it.
+//!
+//! A profile is a list of sections, like "bank 30° from 100m to 180m, easing
+//! in and out over 20m". Applying it rewrites the `up` vectors and heights of
+//! the existing segments: a section sets its value in its middle, blends with
+//! what was there over its ease lengths, and leaves the rest of the road alone.
+//!
+//! Profiles are read from `assets/road_profile.json` and applied in the game
+//! with B, see `EditorConfig::road_profile_path`.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const ROAD_PROFILE_PATH: &str = "assets/road_profile.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileSection {
+    /// Distance along the road where the section starts.
+    pub from: f32,
+    /// Distance along the road where the section ends.
+    pub to: f32,
+    pub value: f32,
+    /// Length over which the section blends in after `from` and out before `to`.
+    #[serde(default)]
+    pub ease: f32,
+}
+
+impl ProfileSection {
+    /// How much the section applies at `distance`, from 0 to 1.
+    pub fn weight(&self, distance: f32) -> f32 {
+        if distance < self.from || distance > self.to {
+            return 0.0;
+        }
+
+        let ease = self.ease.min((self.to - self.from) / 2.0);
+        if ease <= 0.0 {
+            return 1.0;
+        }
+        let t = ((distance - self.from) / ease).min((self.to - distance) / ease).min(1.0);
+        // Smoothstep, so the road doesn't kink where a section starts
+        return t * t * (3.0 - 2.0 * t);
+    }
+}
+
+/// Blends `current` towards each section in turn: later sections win where they overlap.
+pub fn blend_sections(sections: &[ProfileSection], distance: f32, current: f32) -> f32 {
+    return sections
+        .iter()
+        .fold(current, |value, section| value + (section.value - value) * section.weight(distance));
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RoadProfile {
+    /// Bank angle in degrees. Positive angles roll the road to the right,
+    /// like a vehicle: the right edge goes down.
+    pub bank: Vec<ProfileSection>,
+    /// Height of the road.
+    pub elevation: Vec<ProfileSection>,
+}
+
+impl RoadProfile {
+    pub fn from_json(json: &str) -> Result<RoadProfile, serde_json::Error> {
+        return serde_json::from_str(json);
+    }
+}
+
+/// Up vector of a road going along `direction` without banking.
+pub fn level_up(direction: Vec3) -> Vec3 {
+    let right = direction.cross(Vec3::Y);
+    if right.length_squared() < f32::EPSILON {
+        // Vertical road, any up will do
+        return direction.any_orthonormal_vector();
+    }
+    return right.cross(direction).normalize();
+}
+
+/// Bank angle of `up` on a road going along `direction`, in degrees.
+pub fn bank_angle(direction: Vec3, up: Vec3) -> f32 {
+    let forward = direction.normalize();
+    let level = level_up(forward);
+    return level.cross(up).dot(forward).atan2(level.dot(up)).to_degrees();
+}
+
+/// Up vector of a road going along `direction`, banked by `angle` degrees.
+pub fn banked_up(direction: Vec3, angle: f32) -> Vec3 {
+    let forward = direction.normalize();
+    return Quat::from_axis_angle(forward, angle.to_radians()) * level_up(forward);
+}
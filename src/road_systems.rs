@@ -1,28 +1,143 @@
-use bevy::{
-    input::{keyboard::KeyCode, Input},
-    prelude::*,
-};
+// This is only for native builds
+#[allow(unused_imports)]
+use std::fs::read_to_string;
+
+use bevy::prelude::*;
 
 
-use crate::{game::Game, road_network_builder::*};
+use crate::{game::Game, hover::HoverVehicle, input_map::Action, replay::SimulationInput, road_follow::*, road_network_builder::*};
 use crate::road_network_builder::Segment;
+use crate::simulation::{add_simulation_system, SimulationStage, TIMESTEP};
+use crate::trailer::{
+    closest_free_trailer, road_train, Coupling, HitchEvent, HitchEventKind, Hitches, Trailer, TRAILER_ATTACH_DISTANCE,
+};
 use bevy_rapier3d::prelude::*;
 
-const TRAILER_ATTACH_DISTANCE: f32 = 10.0;
-
-// There are probably conceptual errors in there, but it works.
-// This is a mechanism similar to a PID.
-// P: adjustement of correction based on current position difference
-// R: adjustement of correction based on rate of change
-// D: adjustement of correction based on current variation of error
-fn apply_control(
-    delta: Vec3,
-    rate_of_change: Vec3,// -> velocity.angvel
-    p: f32,
-    r: f32, // (old i)
-) -> Vec3 {
-    let correction: Vec3 = delta * p + rate_of_change * r;
-    return correction;
+// This is only for wasm builds
+#[allow(unused_imports)]
+use crate::windowmailer;
+
+pub const ROAD_NETWORK_PATH: &str = "assets/road_network.json";
+
+// The code here is not used in native builds
+#[allow(dead_code)]
+const ROAD_NETWORK_DATA_CHANNEL: &str = "ROAD_NETWORK_DATA";
+
+#[derive(Clone, Debug)]
+pub struct RoadNetworkConfig {
+    /// Road network loaded at startup, if any.
+    pub road_network_path: Option<String>,
+    /// Road following settings. Defaults are used when missing.
+    pub road_follow_path: Option<String>,
+    /// Show the road follow tuning panel (F2). Requires Bevy's UI.
+    pub tuning_panel: bool,
+}
+
+impl Default for RoadNetworkConfig {
+    fn default() -> Self {
+        RoadNetworkConfig {
+            road_network_path: Some(String::from(ROAD_NETWORK_PATH)),
+            road_follow_path: Some(String::from(ROAD_FOLLOW_PATH)),
+            tuning_panel: true,
+        }
+    }
+}
+
+/// Loads and builds the road network, and pulls `RoadFollower`s towards it.
+/// Requires `SimulationPlugin`.
+#[derive(Default)]
+pub struct RoadNetworkPlugin {
+    pub config: RoadNetworkConfig,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum RoadNetworkLoadingState {
+    Loading,
+    Loaded,
+}
+
+impl Plugin for RoadNetworkPlugin {
+    fn build(&self, app: &mut App) {
+        #[cfg(not(target_arch = "wasm32"))]
+        let default_road_state = RoadNetworkLoadingState::Loaded;
+        #[cfg(target_arch = "wasm32")]
+        let default_road_state = match self.config.road_network_path {
+            Some(_) => RoadNetworkLoadingState::Loading,
+            _ => RoadNetworkLoadingState::Loaded,
+        };
+
+        app.insert_resource(self.config.clone())
+            .init_resource::<Game>()
+            .init_resource::<RoadFollowClasses>()
+            .add_state(default_road_state)
+            .add_startup_system(load_road_network)
+            .add_system_set(
+                SystemSet::on_update(RoadNetworkLoadingState::Loading)
+                    .with_system(road_network_load_check)
+            )
+            .add_startup_system(load_road_follow_classes)
+            .add_system(road_follow_load_check);
+
+        add_simulation_system(app, SimulationStage::RoadForces, road_physics_system);
+
+        if self.config.tuning_panel {
+            app.init_resource::<RoadFollowTuning>()
+                .add_event::<RoadFollowEvent>()
+                .add_startup_system(setup_road_follow_tuning_panel)
+                .add_system(road_follow_tuning_system
+                            .after(road_follow_load_check));
+        }
+    }
+}
+
+fn load_road_network(
+    _commands: Commands,
+    config: Res<RoadNetworkConfig>,
+    mut _game: ResMut<Game>,
+    _meshes: ResMut<Assets<Mesh>>,
+    _materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let path = match &config.road_network_path {
+        Some(path) => path.clone(),
+        _ => {
+            return;
+        }
+    };
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        windowmailer::fetch_to_channel(path, String::from(ROAD_NETWORK_DATA_CHANNEL));
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let serialized_road_data: String = read_to_string(path).unwrap();
+        _game.road_network = serde_json::from_str(&serialized_road_data).unwrap();
+        refresh_road_network(_game, _meshes, _materials, _commands);
+    }
+}
+
+
+#[allow(unused_mut, unused_variables)]
+// Waits for roads to load
+fn road_network_load_check(
+    commands: Commands,
+    mut game: ResMut<Game>,
+    _meshes: ResMut<Assets<Mesh>>,
+    _materials: ResMut<Assets<StandardMaterial>>,
+    mut road_network_loading_state: ResMut<State<RoadNetworkLoadingState>>
+) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        if windowmailer::message_count(String::from(ROAD_NETWORK_DATA_CHANNEL)) == 0 {
+            return;
+        }
+        let serialized_road_data: String = windowmailer::read_message(String::from(ROAD_NETWORK_DATA_CHANNEL));
+        game.road_network = serde_json::from_str(&serialized_road_data).unwrap();
+
+        refresh_road_network(game, _meshes, _materials, commands);
+
+        road_network_loading_state.set(RoadNetworkLoadingState::Loaded).unwrap();
+    }
 }
 
 pub fn refresh_road_network(
@@ -43,43 +158,43 @@ pub fn refresh_road_network(
 
 }
 
+/// Sent when the road network is changed from `road_network_creation_system`, for feedback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoadNetworkEvent {
+    SegmentInserted,
+    Dumped,
+    Cleared,
+    /// Index of the new macro.
+    MacroRecorded(usize),
+    /// Index of the macro and number of segments added.
+    MacroPlayed(usize, usize),
+    /// The road profile was read, its edit is queued.
+    ProfileApplied,
+    /// The road profile couldn't be read.
+    ProfileFailed,
+}
+
 pub fn road_network_creation_system(
-    mut transforms: Query<&mut Transform>,
+    transforms: Query<&Transform>,
     mut ext_forces: Query<&mut ExternalForce>,
-    keyboard_input: Res<Input<KeyCode>>,
+    hitches: Query<&Hitches>,
+    joints: Query<(Entity, &ImpulseJoint), With<Trailer>>,
+    trailers: Query<Entity, With<Trailer>>,
+    input: Res<SimulationInput>,
+    mut hitch_events: EventWriter<HitchEvent>,
+    mut road_network_events: EventWriter<RoadNetworkEvent>,
     mut game: ResMut<Game>,
     meshes: ResMut<Assets<Mesh>>,
     materials: ResMut<Assets<StandardMaterial>>,
     mut commands: Commands,
 ) {
-    let vehicle_entity = match game.player_car {
+    let vehicle_entity = match game.player_car() {
         Some(vehicle_entity) => vehicle_entity,
         _ => {
             return;
         }
     };
 
-    let trailer_entity = match game.trailer {
-        Some(trailer_entity) => trailer_entity,
-        _ => {
-            return;
-        }
-    };
-
-    let mut ext_force = match ext_forces.get_mut(trailer_entity) {
-        Ok(ext_force) => ext_force,
-        _ => {
-            return;
-        }
-    };
-
-    let trailer_transform = match transforms.get(trailer_entity) {
-        Ok(trailer_transform) => trailer_transform,
-        _ => {
-            return;
-        }
-    };
-
     let vehicle_transform = match transforms.get(vehicle_entity) {
         Ok(vehicle_transform) => vehicle_transform,
         _ => {
@@ -87,63 +202,88 @@ pub fn road_network_creation_system(
         }
     };
 
-    // E: Insert road segment. (E because it is close to WASD)
-    if keyboard_input.just_released(KeyCode::E) {
-        let translation = trailer_transform.translation;
-        let current_point = translation.clone();
+    let train = road_train(vehicle_entity, &joints);
 
-        let last_position = match game.road_network.last_position {
-            Some(position) => position,
-            _ => {
-                game.road_network.last_position = Some(current_point.clone());
-                return;
-            }
+    // Couple the closest free trailer behind the road train (T)
+    let toggle = input.actions.just_released(Action::ToggleTrailer);
+    if toggle {
+        let tail = *train.last().unwrap_or(&vehicle_entity);
+        let tail_rear_hitch = match (hitches.get(tail), transforms.get(tail)) {
+            (Ok(Hitches { rear: Some(rear), .. }), Ok(tail_transform)) => Some((*rear, tail_transform.mul_vec3(*rear))),
+            _ => None,
         };
 
-        game.road_network.last_position = Some(current_point.clone());
-
-        let segment = Segment { a: last_position.clone(), b: current_point, up: trailer_transform.up() };
-
-        game.road_network.road_segments.push(segment);
-        refresh_road_network(game, meshes, materials, commands);
+        if let Some((local_rear_hitch, rear_hitch)) = tail_rear_hitch {
+            let candidate = closest_free_trailer(rear_hitch, &transforms, &joints, &trailers);
+            if let Some((trailer, _)) = candidate {
+                let front_hitch = match hitches.get(trailer) {
+                    Ok(Hitches { front: Some(front), .. }) => Some(*front),
+                    _ => None,
+                };
+                let trailer_transform = transforms.get(trailer).unwrap();
+
+                // Start coupling if close enough, see `coupling_system`
+                if let Some(front_hitch) = front_hitch {
+                    if trailer_transform.mul_vec3(front_hitch).distance(rear_hitch) <= TRAILER_ATTACH_DISTANCE {
+                        commands.entity(trailer).insert(Coupling::new(tail, local_rear_hitch));
+                        hitch_events.send(HitchEvent { trailer, kind: HitchEventKind::CouplingStarted });
+                        return;
+                    }
+                }
+            }
+        }
+    }
 
+    // Unhitch its last trailer (Y), or with T when no free trailer is in range
+    if toggle || input.actions.just_released(Action::UnhitchTrailer) {
+        if let Some(last) = train.last() {
+            commands.entity(*last).remove::<ImpulseJoint>();
+            hitch_events.send(HitchEvent { trailer: *last, kind: HitchEventKind::Unhitched });
+        }
         return;
     }
 
-    // T: Attach/Detach Trailer
-    if keyboard_input.just_released(KeyCode::T) {
-        let trailer = match game.trailer {
-            Some(trailer) => trailer,
+    // The last trailer of the road train builds the road.
+    // Without a road train, the closest trailer does.
+    let builder = match train.last() {
+        Some(trailer) => *trailer,
+        _ => match closest_free_trailer(vehicle_transform.translation, &transforms, &joints, &trailers) {
+            Some((trailer, _)) => trailer,
             _ => {
                 return;
             }
-        };
+        },
+    };
 
-        let joint = match game.trailer_joint {
-            Some(joint) => joint,
-            _ => {
-                // Link trailer if close enough
-                if vehicle_transform.translation.distance(trailer_transform.translation) > TRAILER_ATTACH_DISTANCE {
-                    return;
-                }
-                let joint_builder: RevoluteJointBuilder =  RevoluteJointBuilder::new(Vec3::Y)
-                    .local_anchor1(Vec3::new(0.0, 0.0, 3.0))
-                    .local_anchor2(Vec3::new(0.0, 0.0, -5.0));
+    let mut ext_force = match ext_forces.get_mut(builder) {
+        Ok(ext_force) => ext_force,
+        _ => {
+            return;
+        }
+    };
 
-                let joint = ImpulseJoint::new(game.player_car.unwrap(), joint_builder);
-                game.trailer_joint = Some(joint);
-                commands.entity(trailer).insert(joint);
+    let trailer_transform = match transforms.get(builder) {
+        Ok(trailer_transform) => trailer_transform,
+        _ => {
+            return;
+        }
+    };
 
-                return;
-            }
-        };
+    // Insert road segment. (E by default, because it is close to WASD)
+    if input.actions.just_released(Action::InsertSegment) {
+        // The first node only marks where the road starts
+        let first_node = game.road_network.last_position.is_none();
+        game.road_network.add_node(trailer_transform.translation, trailer_transform.up());
+        if !first_node {
+            road_network_events.send(RoadNetworkEvent::SegmentInserted);
+            refresh_road_network(game, meshes, materials, commands);
+        }
 
-        commands.entity(trailer).remove::<ImpulseJoint>();
-        game.trailer_joint = None;
+        return;
     }
 
-    // O: Output/Dump road network
-    if keyboard_input.just_released(KeyCode::O) {
+    // Output/Dump road network (O)
+    if input.actions.just_released(Action::DumpRoadNetwork) {
         let serialized = serde_json::to_string(&game.road_network).unwrap();
 
         #[cfg(target_arch = "wasm32")]
@@ -154,29 +294,33 @@ pub fn road_network_creation_system(
         {
             println!("serialized = {}", serialized);
         }
+        road_network_events.send(RoadNetworkEvent::Dumped);
     }
 
-    // X: Delete everything and go back to 0,0
-    if keyboard_input.just_released(KeyCode::X) {
+    // Delete everything and go back to 0,0 (X)
+    if input.actions.just_released(Action::ClearRoadNetwork) {
         game.road_network.road_segments.clear();
+        game.road_network.checkpoints.clear();
         game.road_network.last_position = Some(Vec3::ZERO);
 
         // trailer_transform.translation = Vec3::ZERO;
         ext_force.force = Vec3::ZERO;
         ext_force.torque = Vec3::ZERO;
+        road_network_events.send(RoadNetworkEvent::Cleared);
         refresh_road_network(game, meshes, materials, commands);
     }
 
-    // R: Record current state as macro
-    else if keyboard_input.just_released(KeyCode::R) {
+    // Record current state as macro (R)
+    else if input.actions.just_released(Action::RecordMacro) {
         let mut m: Macro =  Macro::default();
         m.road_segments = game.road_network.road_segments.clone();
         game.road_network.macros.push(m);
+        road_network_events.send(RoadNetworkEvent::MacroRecorded(game.road_network.macros.len() - 1));
     }
 
-    // P: Play macro
-    else if keyboard_input.just_released(KeyCode::P) {
-        if game.road_network.macros.len() <= 0 {
+    // Play macro (P)
+    else if input.actions.just_released(Action::PlayMacro) {
+        if game.road_network.macros.is_empty() {
             return;
         }
         let segments = game.road_network.macros.last().unwrap().road_segments.clone();
@@ -186,12 +330,9 @@ pub fn road_network_creation_system(
             let a = r * segment.a + t;
             let b = r * segment.b + t;
             let up = r * segment.up;
-            game.road_network.road_segments.push(Segment {
-                a: a,
-                b: b,
-                up: up
-            });
+            game.road_network.road_segments.push(Segment { a, b, up, target_speed: segment.target_speed });
         }
+        road_network_events.send(RoadNetworkEvent::MacroPlayed(game.road_network.macros.len() - 1, segments.len()));
 
         // move trailer to last point
         // let last_segment = game.road_network.road_segments.last().unwrap();
@@ -203,6 +344,23 @@ pub fn road_network_creation_system(
     }
 }
 
+/// Applies the road editor changes of the tick.
+pub fn road_edit_system(
+    input: Res<SimulationInput>,
+    mut game: ResMut<Game>,
+    meshes: ResMut<Assets<Mesh>>,
+    materials: ResMut<Assets<StandardMaterial>>,
+    commands: Commands,
+) {
+    if input.road_edits.is_empty() {
+        return;
+    }
+    for edit in &input.road_edits {
+        game.road_network.apply_edit(edit);
+    }
+    refresh_road_network(game, meshes, materials, commands);
+}
+
 /// Finds the closest point on a segment to a point.
 /// Returns None if the projection lands outside of the segment.
 fn find_closest_point_on_segment_capped(segment_a: Vec3, segment_b: Vec3, p: Vec3) -> Option<Vec3> {
@@ -220,35 +378,33 @@ fn find_closest_point_on_segment_capped(segment_a: Vec3, segment_b: Vec3, p: Vec
     }
 }
 
-struct EntityAndWeight {
-    entity: Entity,
-    weight: f32,
-}
-
-fn bring_entities_closer_to_road(
-    entities_and_weights: Vec<EntityAndWeight>,
-    mut transforms: Query<&mut Transform>,
-    game: ResMut<Game>,
-    mut ext_forces: Query<&mut ExternalForce>,
-    mut velocities: Query<&mut Velocity>,
+type RoadFollowerQuery<'w, 's> = Query<'w, 's, (
+    &'static Transform,
+    &'static Velocity,
+    &'static mut ExternalForce,
+    &'static mut RoadFollower,
+    Option<&'static HoverVehicle>,
+    Option<&'static ImpulseJoint>,
+)>;
+
+/// Pulls every `RoadFollower` towards the closest road segment.
+///
+/// Each follower uses the controller settings of its class,
+/// see `RoadFollowClasses` and the tuning panel (F2).
+///
+/// Hitched trailers follow the road in the direction they are towed,
+/// so a road train reversing along the road doesn't jackknife.
+pub fn road_physics_system(
+    game: Res<Game>,
+    classes: Res<RoadFollowClasses>,
+    mut followers: RoadFollowerQuery,
 ) {
-    for entity_and_weight in entities_and_weights {
-        let entity = entity_and_weight.entity;
-        let weight = entity_and_weight.weight;
-
-        let mut ext_force = match ext_forces.get_mut(entity) {
-            Ok(ext_force) => ext_force,
-            _ => {
-                return;
-            }
-        };
-
-        let transform = match transforms.get_mut(entity) {
-            Ok(vehicle_transform) => vehicle_transform,
-            _ => {
-                return;
-            }
-        };
+    for (transform, velocity, mut ext_force, mut follower, hover_vehicle, joint) in followers.iter_mut() {
+        let settings = classes.get(&follower.class);
+        // Hover vehicles hold their height with thrusters,
+        // the road only pulls them sideways.
+        let hovers = hover_vehicle.is_some();
+        let towed = joint.is_some();
         let position = transform.translation;
 
         struct ClosestPointInfo {
@@ -257,45 +413,30 @@ fn bring_entities_closer_to_road(
         }
 
         let mut closest_segment: Option<ClosestPointInfo> = None;
-        let mut closest_segment_index: Option<usize> = None;
         let mut closest_point: Option<Vec3> = None;
         let mut closest_dist: Option<f32> = None;
 
         // In this game, vehicles float above roads
-        let offset: Vec3 = Vec3::Y * 5.5;
+        let offset: Vec3 = Vec3::Y * settings.road_offset;
 
         // find close segments to vehicle (dumb, not efficient)
-        for (index, segment_data) in game.road_network.road_segments.iter().enumerate() {
+        for segment_data in game.road_network.road_segments.iter() {
             let p1: Vec3 = segment_data.a + offset;
             let p2: Vec3 = segment_data.b + offset;
-            let closest_point_to_segment: Option<Vec3> = find_closest_point_on_segment_capped(p1, p2, transform.translation);
-
+            let closest_point_to_segment: Option<Vec3> = find_closest_point_on_segment_capped(p1, p2, position);
 
             match closest_point_to_segment {
                 Some(closest_point_to_segment) => {
                     let dist: f32 = (closest_point_to_segment - position).length();
 
-                    match closest_dist {
-                        Some(closest_dist) => {
-                            if closest_segment_index.is_none() || closest_point.is_none() {
-                                panic!("If dist is set, then we also expect closest_point and closest_segment_index.");
-                            }
-
-                            if dist < closest_dist {
-                                // We become the new closest dist
-                            } else {
-                               // Continue, so that we don't set the closest dist.
-                                continue;
-                            }
-                        },
-                        _ => {}
+                    if let Some(closest_dist) = closest_dist {
+                        if dist >= closest_dist {
+                            continue;
+                        }
                     }
 
-                    // 0: This first point becomes the new closest dist
-                    // 1..n: This point is the closest now. Update!
                     closest_dist = Some(dist);
                     closest_point = Some(closest_point_to_segment);
-                    closest_segment_index = Some(index);
                     closest_segment = Some(ClosestPointInfo {
                         segment: segment_data.b - segment_data.a,
                         up: segment_data.up
@@ -305,114 +446,52 @@ fn bring_entities_closer_to_road(
             }
         }
 
-        let velocity = match velocities.get_mut(entity) {
-            Ok(velocity) => velocity,
-            _ => return
+        let (closest_point, closest_segment) = match (closest_point, closest_segment) {
+            (Some(closest_point), Some(closest_segment)) => (closest_point, closest_segment),
+            _ => {
+                follower.reset();
+                continue;
+            }
         };
 
-        match closest_point {
-            Some(closest_point) => {
-
-                // Too far: outside of road force field.
-                if closest_dist.unwrap() > 30.0 {
-                    return;
-                }
+        // Too far: outside of road force field.
+        if closest_dist.unwrap() > settings.max_distance {
+            follower.reset();
+            continue;
+        }
 
-                if let Some(closest_segment) = closest_segment {
-                    let p: f32 = 40.0 * weight;
-                    let r: f32 = -3.0 * weight;
-                    const SUB_TARGET_FRACTION: f32 = 0.8;
-
-                    // Make vehicle more aligned with road
-                    let delta_forward = -closest_segment.segment
-                        .normalize()
-                        .cross(transform.forward())
-                        * SUB_TARGET_FRACTION;
-
-                    let delta_up = -closest_segment.up
-                        .normalize()
-                        .cross(transform.up())
-                        * SUB_TARGET_FRACTION;
-
-                    ext_force.torque += apply_control(
-                        delta_forward,
-                        velocity.angvel,
-                        p,
-                        r,
-                    );
-
-                    ext_force.torque += apply_control(
-                        delta_up,
-                        velocity.angvel,
-                        p,
-                        r,
-                    );
-
-                    {
-                        let p: f32 = 4.0 * weight;
-                        let r: f32 = -2.0 * weight;
-
-                        let delta_position: Vec3 = closest_point - position;
-
-                        let centering_force = apply_control(
-                            delta_position,
-                            velocity.linvel,
-                            p,
-                            r,
-                        );
-
-                        // This force will only act on the plane perpendicular to the segment.
-                        ext_force.force += centering_force
-                            - centering_force.project_onto(closest_segment.segment);
-                    }
-                };
-            },
-            _ => {}
+        let mut road_direction = closest_segment.segment.normalize();
+        if towed && road_direction.dot(transform.forward()) < 0.0 {
+            road_direction = -road_direction;
         }
-    }
-}
 
+        // Make vehicle more aligned with road
+        let delta_forward = -road_direction
+            .cross(transform.forward())
+            * settings.sub_target_fraction;
 
-pub fn road_physics_system(
-    transforms: Query<&mut Transform>,
-    game: ResMut<Game>,
-    ext_forces: Query<&mut ExternalForce>,
-    velocities: Query<&mut Velocity>,
-) {
-    let vehicle_entity = match game.player_car {
-        Some(entity) => entity,
-        _ => {
-            return;
-        }
-    };
+        let delta_up = -closest_segment.up
+            .normalize()
+            .cross(transform.up())
+            * settings.sub_target_fraction;
 
-    let trailer_entity = match game.trailer {
-        Some(entity) => entity,
-        _ => {
-            return;
+        // The misalignment shrinks as the body rotates towards the road
+        ext_force.torque += follower.forward.update(&settings.alignment, delta_forward, -velocity.angvel, TIMESTEP);
+        ext_force.torque += follower.up.update(&settings.alignment, delta_up, -velocity.angvel, TIMESTEP);
+
+        let delta_position: Vec3 = closest_point - position;
+        let centering_force = follower.centering.update(&settings.centering, delta_position, -velocity.linvel, TIMESTEP);
+
+        // This force will only act on the plane perpendicular to the segment.
+        let mut centering_force = centering_force
+            - centering_force.project_onto(closest_segment.segment);
+
+        if hovers {
+            centering_force -= centering_force.project_onto(closest_segment.up);
         }
-    };
 
-    bring_entities_closer_to_road(
-        vec!(
-            EntityAndWeight {
-                entity: vehicle_entity,
-                weight: 1.0,
-            },
-            EntityAndWeight {
-                entity: trailer_entity,
-                weight: 1.5,
-            }
-        ),
-        transforms,
-        game,
-        ext_forces,
-        velocities
-    );
-
-    // trailer should tend to continue on it's current direction
-
-    // - Add a force to compensate rotation
-    // - Add a joint and a mass at the rear of the trailer?
-    // - 3??
+        ext_force.force += centering_force;
+    }
+
+    // Trailers keeping in line with their tow: see `trailer_stability_system`
 }
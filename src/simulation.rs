@@ -0,0 +1,179 @@
+//! Fixed timestep simulation.
+//!
+//! Everything that pushes bodies around runs in `FixedUpdateStage`, a nested
+//! schedule that ticks at `TIMESTEP` whatever the frame rate, followed by the
+//! physics step. Systems run single threaded, in a fixed order, and read
+//! their input from `SimulationInput`, so a recorded session replays the same
+//! way on every machine running the same build.
+//!
+//! Other plugins add their systems to the `SimulationStage`s with
+//! `add_simulation_system`.
+
+use bevy::{ecs::schedule::IntoSystemDescriptor, input::InputSystem, prelude::*, time::FixedTimestep};
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    drive_input::{drive_input_system, DriveInput, DriveInputSettings},
+    game::Game,
+    input_map::{action_state_system, input_map_load_check, load_input_map, ActionState, InputDevices, InputMap},
+    road_network_builder::PendingRoadEdits,
+    replay::{load_replay_from_args, save_replay_system, simulation_input_system, Replay, ReplayEvent, SimulationInput},
+};
+
+/// Duration of a simulation tick, in seconds.
+pub const TIMESTEP: f32 = 1.0 / 60.0;
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+pub struct FixedUpdateStage;
+
+/// Stages of a tick, in order. Commands are applied between stages.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+pub enum SimulationStage {
+    /// Reads the input of the tick and clears forces.
+    Input,
+    /// Spawning and switching vehicles.
+    Vehicles,
+    /// Road editing.
+    Editing,
+    /// Lap timing.
+    Gameplay,
+    /// Vehicle thrusters and driving.
+    VehicleForces,
+    /// Road following.
+    RoadForces,
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    /// Tick every `TIMESTEP` of real time.
+    /// When false, tick once per update (tests, tools).
+    pub fixed_timestep: bool,
+    /// Read the keyboard and gamepads. Requires Bevy's `InputPlugin`.
+    pub device_input: bool,
+    /// Bindings file. Default controls are used when it is missing.
+    pub input_map_path: String,
+    /// Play back `--replay <file>`, record with `--record` and save replays (F6).
+    pub replays: bool,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            fixed_timestep: true,
+            device_input: true,
+            input_map_path: String::from(crate::input_map::INPUT_MAP_PATH),
+            replays: true,
+        }
+    }
+}
+
+/// Physics, input and the fixed timestep schedule.
+/// Add it before the other plugins of this crate.
+#[derive(Default)]
+pub struct SimulationPlugin {
+    pub config: SimulationConfig,
+}
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        let mut schedule = simulation_schedule();
+        if self.config.fixed_timestep {
+            schedule.set_run_criteria(FixedTimestep::step(TIMESTEP as f64));
+        }
+
+        app.insert_resource(self.config.clone())
+            .insert_resource(RapierConfiguration {
+                timestep_mode: TimestepMode::Fixed {
+                    dt: TIMESTEP,
+                    substeps: 1,
+                },
+                ..default()
+            })
+            .init_resource::<Game>()
+            .init_resource::<InputMap>()
+            .init_resource::<ActionState>()
+            .init_resource::<InputDevices>()
+            .init_resource::<DriveInput>()
+            .init_resource::<DriveInputSettings>()
+            .init_resource::<PendingRoadEdits>()
+            .init_resource::<SimulationInput>()
+            .init_resource::<Replay>()
+            .add_plugin(RapierPhysicsPlugin::<NoUserData>::default().with_default_system_setup(false))
+            .add_stage_after(CoreStage::Update, FixedUpdateStage, schedule)
+            // Like the default setup, catch despawns at the end of the frame
+            .add_stage_before(
+                CoreStage::Last,
+                PhysicsStages::DetectDespawn,
+                SystemStage::parallel()
+                    .with_system_set(RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::DetectDespawn)),
+            );
+
+        // Actions are up to date for every system of the frame
+        if self.config.device_input {
+            app.add_startup_system(load_input_map)
+                .add_system_to_stage(CoreStage::PreUpdate, input_map_load_check)
+                .add_system_to_stage(CoreStage::PreUpdate, action_state_system
+                                     .after(InputSystem)
+                                     .after(input_map_load_check))
+                .add_system_to_stage(CoreStage::PreUpdate, drive_input_system
+                                     .after(action_state_system));
+        }
+
+        if self.config.replays {
+            app.add_event::<ReplayEvent>()
+                .add_startup_system(load_replay_from_args)
+                .add_system(save_replay_system);
+        }
+    }
+}
+
+pub fn reset_forces_system(
+    mut ext_forces: Query<&mut ExternalForce>,
+) {
+    // Forces are accumulated by each system every tick
+    for mut ext_force in ext_forces.iter_mut() {
+        ext_force.force = Vec3::ZERO;
+        ext_force.torque = Vec3::ZERO;
+    }
+}
+
+/// One simulation tick: game systems, then the physics step.
+/// Runs once every time the schedule runs, add a run criteria to pace it.
+pub fn simulation_schedule() -> Schedule {
+    let mut schedule = Schedule::default();
+    for label in [
+        SimulationStage::Input,
+        SimulationStage::Vehicles,
+        SimulationStage::Editing,
+        SimulationStage::Gameplay,
+        SimulationStage::VehicleForces,
+        SimulationStage::RoadForces,
+    ] {
+        schedule.add_stage(label, SystemStage::single_threaded());
+    }
+
+    schedule.add_system_to_stage(SimulationStage::Input, simulation_input_system);
+    schedule.add_system_to_stage(SimulationStage::Input, reset_forces_system.after(simulation_input_system));
+
+    for physics_stage in [PhysicsStages::SyncBackend, PhysicsStages::StepSimulation, PhysicsStages::Writeback] {
+        schedule.add_stage(
+            physics_stage.clone(),
+            SystemStage::parallel()
+                .with_system_set(RapierPhysicsPlugin::<NoUserData>::get_systems(physics_stage)),
+        );
+    }
+
+    return schedule;
+}
+
+/// Adds a system to a stage of the simulation tick.
+pub fn add_simulation_system<Params>(
+    app: &mut App,
+    stage: SimulationStage,
+    system: impl IntoSystemDescriptor<Params>,
+) {
+    app.stage(FixedUpdateStage, |schedule: &mut Schedule| {
+        schedule.add_system_to_stage(stage, system);
+        return schedule;
+    });
+}
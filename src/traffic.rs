@@ -0,0 +1,360 @@
+//! Background traffic on the lanes of the road.
+//!
+//! Traffic cars enter each lane at its start (the end of the road for
+//! reverse lanes) and leave at its other end, or keep going around closed
+//! roads. They don't hover or follow the road with forces like the player's
+//! car: each one is a distance along its lane and a speed, moved with the
+//! Intelligent Driver Model behind the car ahead, which can also be the
+//! player or an AI car. Bodies are kinematic, so vehicles still bump into
+//! them, and cars leaving the road are parked and reused for the next ones.
+//!
+//! Traffic is off by default, `--traffic [count]` turns it on.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    game::Game,
+    replay::SimulationInput,
+    road_network_builder::RoadPath,
+    simulation::{add_simulation_system, SimulationStage, TIMESTEP},
+    vehicle::{Vehicle, VehicleManifest, VehicleRoster},
+};
+
+/// Where pooled cars wait, out of sight and out of the way.
+const PARKING: Vec3 = Vec3::new(0.0, -10000.0, 0.0);
+/// Cars on the road with `--traffic` and no count.
+pub const DEFAULT_TRAFFIC_VEHICLES: usize = 200;
+
+#[derive(Clone, Debug)]
+pub struct TrafficConfig {
+    /// Vehicle the traffic cars are spawned with.
+    pub vehicle: String,
+    /// Cars on the road at once. 0, the default, disables traffic.
+    pub max_vehicles: usize,
+    /// Seconds between cars entering a lane.
+    pub spawn_interval: f32,
+    /// Speed on segments without a `target_speed`, in m/s.
+    pub desired_speed: f32,
+    /// Each car drives up to this fraction faster or slower than the desired speed.
+    pub speed_variation: f32,
+    /// Seconds to the car ahead.
+    pub time_headway: f32,
+    /// Bumper to bumper distance kept when stopped.
+    pub min_gap: f32,
+    pub max_acceleration: f32,
+    /// Braking the cars are comfortable with. They brake harder when they must.
+    pub comfortable_deceleration: f32,
+    /// Other vehicles closer than half of this to the middle of a lane are in it.
+    pub lane_width: f32,
+}
+
+impl Default for TrafficConfig {
+    fn default() -> Self {
+        TrafficConfig {
+            vehicle: String::from("car_0002"),
+            max_vehicles: 0,
+            spawn_interval: 3.0,
+            desired_speed: 20.0,
+            speed_variation: 0.15,
+            time_headway: 1.5,
+            min_gap: 4.0,
+            max_acceleration: 1.5,
+            comfortable_deceleration: 3.0,
+            lane_width: 4.0,
+        }
+    }
+}
+
+impl TrafficConfig {
+    /// Traffic given with `--traffic [count]`, none otherwise.
+    pub fn from_args() -> Self {
+        #[allow(unused_mut)]
+        let mut config = TrafficConfig::default();
+
+        // This is only for native builds
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let args: Vec<String> = std::env::args().collect();
+            if let Some(index) = args.iter().position(|arg| arg == "--traffic") {
+                config.max_vehicles = match args.get(index + 1).filter(|arg| !arg.starts_with("--")) {
+                    Some(value) => match value.parse::<usize>() {
+                        Ok(value) => value,
+                        _ => {
+                            error!("--traffic expects a number of cars");
+                            DEFAULT_TRAFFIC_VEHICLES
+                        }
+                    },
+                    _ => DEFAULT_TRAFFIC_VEHICLES,
+                };
+            }
+        }
+
+        return config;
+    }
+}
+
+/// Requires `SimulationPlugin` and `VehiclePlugin`.
+#[derive(Default)]
+pub struct TrafficPlugin {
+    pub config: TrafficConfig,
+}
+
+impl Plugin for TrafficPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.config.clone())
+            .init_resource::<Game>()
+            .init_resource::<Traffic>();
+
+        add_simulation_system(app, SimulationStage::Vehicles, traffic_system);
+    }
+}
+
+/// Attached to traffic cars, on the road or parked.
+#[derive(Component)]
+pub struct TrafficVehicle;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrafficCar {
+    pub entity: Entity,
+    /// Index in `RoadNetwork::traffic_lanes`.
+    pub lane: usize,
+    /// Distance driven along the lane, from where it starts.
+    pub progress: f32,
+    pub speed: f32,
+    /// Multiplies the desired speed, so cars don't all drive alike.
+    pub speed_factor: f32,
+}
+
+/// Traffic cars on the road, and the parked ones.
+#[derive(Default, Debug)]
+pub struct Traffic {
+    pub cars: Vec<TrafficCar>,
+    /// Parked entities, reused before spawning new ones.
+    pub pool: Vec<Entity>,
+    /// Cars that entered the road so far, seeds their speed factor.
+    pub entered: u64,
+    /// Tick a car last entered each lane on.
+    last_entry: Vec<Option<u64>>,
+}
+
+/// Acceleration of a car with the Intelligent Driver Model.
+/// `leader` is the bumper to bumper gap to the car ahead and its speed.
+pub fn idm_acceleration(config: &TrafficConfig, speed: f32, desired_speed: f32, leader: Option<(f32, f32)>) -> f32 {
+    let free_road = 1.0 - (speed / desired_speed.max(0.1)).powi(4);
+    let interaction = match leader {
+        Some((gap, leader_speed)) => {
+            let braking = 2.0 * (config.max_acceleration * config.comfortable_deceleration).sqrt();
+            let desired_gap = config.min_gap
+                + (speed * config.time_headway + speed * (speed - leader_speed) / braking).max(0.0);
+            (desired_gap / gap.max(0.1)).powi(2)
+        },
+        _ => 0.0,
+    };
+    return config.max_acceleration * (free_road - interaction);
+}
+
+/// Speed factor of the `index`th car, from 1 - `variation` to 1 + `variation`.
+fn speed_factor(index: u64, variation: f32) -> f32 {
+    // Deterministic, so replays get the same traffic
+    let unit = (index.wrapping_add(1).wrapping_mul(0x9E3779B97F4A7C15) >> 40) as f32 / (1u64 << 24) as f32;
+    return 1.0 + variation * (unit * 2.0 - 1.0);
+}
+
+/// Spawns a traffic car, or takes one from the pool.
+fn enter_car(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    traffic: &mut Traffic,
+    name: &str,
+    manifest: &VehicleManifest,
+    transform: Transform,
+) -> Entity {
+    if let Some(entity) = traffic.pool.pop() {
+        commands.entity(entity).insert(transform);
+        return entity;
+    }
+
+    return commands
+        .spawn_bundle(SceneBundle {
+            scene: asset_server.load(&manifest.scene_path(name)),
+            transform,
+            ..default()
+        })
+        .insert(RigidBody::KinematicPositionBased)
+        .insert(Collider::cuboid(manifest.collider.x, manifest.collider.y, manifest.collider.z))
+        .insert(Velocity::default())
+        .insert(TrafficVehicle)
+        .id();
+}
+
+type OtherVehicleQuery<'w, 's> = Query<'w, 's,
+    (&'static Transform, Option<&'static Velocity>),
+    (With<Vehicle>, Without<TrafficVehicle>),
+>;
+
+/// Moves the traffic cars along their lanes, and lets cars enter and leave the road.
+#[allow(clippy::too_many_arguments)]
+pub fn traffic_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<TrafficConfig>,
+    input: Res<SimulationInput>,
+    game: Res<Game>,
+    roster: Res<VehicleRoster>,
+    mut traffic: ResMut<Traffic>,
+    mut cars: Query<(&mut Transform, &mut Velocity), With<TrafficVehicle>>,
+    vehicles: OtherVehicleQuery,
+) {
+    if config.max_vehicles == 0 && traffic.cars.is_empty() {
+        return;
+    }
+    let manifest = match roster.manifests.get(&config.vehicle) {
+        Some(manifest) => manifest,
+        _ => {
+            return;
+        }
+    };
+    let road_network = &game.road_network;
+    let path = RoadPath::new(road_network);
+    let lanes = road_network.traffic_lanes();
+    let closed = path.closed;
+    let vehicle_length = manifest.collider.z * 2.0;
+    let height = manifest.hover.hover_height;
+
+    // Cars leaving the road, or lanes that went away, go back to the pool
+    let mut leaving: Vec<Entity> = Vec::new();
+    traffic.cars.retain(|car| {
+        let on_road = car.lane < lanes.len() && path.length > 0.0 && (closed || car.progress <= path.length);
+        if !on_road {
+            leaving.push(car.entity);
+        }
+        return on_road;
+    });
+    for entity in leaving {
+        if let Ok((mut transform, mut velocity)) = cars.get_mut(entity) {
+            *transform = Transform::from_translation(PARKING);
+            *velocity = Velocity::default();
+        }
+        traffic.pool.push(entity);
+    }
+    if path.length <= 0.0 {
+        return;
+    }
+
+    // Everything in each lane: progress, speed, and the traffic car if it is one
+    let mut lane_entries: Vec<Vec<(f32, f32, Option<usize>)>> = vec![Vec::new(); lanes.len()];
+    for (index, car) in traffic.cars.iter().enumerate() {
+        lane_entries[car.lane].push((car.progress, car.speed, Some(index)));
+    }
+    for (transform, velocity) in vehicles.iter() {
+        let position = transform.translation;
+        let (along, point, direction, up) = match path.distance_along(position) {
+            Some((along, off)) if off <= config.lane_width * lanes.len() as f32 => match path.point_at(along) {
+                Some((point, direction, up)) => (along, point, direction, up),
+                _ => {
+                    continue;
+                }
+            },
+            _ => {
+                continue;
+            }
+        };
+        let lateral = (position - point).dot(direction.cross(up).normalize_or_zero());
+        let speed_along_road = velocity.map(|velocity| velocity.linvel.dot(direction)).unwrap_or(0.0);
+
+        for (lane_index, lane) in lanes.iter().enumerate() {
+            if (lateral - lane.offset).abs() > config.lane_width / 2.0 {
+                continue;
+            }
+            let (progress, speed) = if lane.reverse {
+                (path.length - along, -speed_along_road)
+            } else {
+                (along, speed_along_road)
+            };
+            lane_entries[lane_index].push((progress, speed, None));
+        }
+    }
+
+    // Car following, behind whatever is next in the lane
+    let mut accelerations: Vec<(usize, f32)> = Vec::with_capacity(traffic.cars.len());
+    for entries in lane_entries.iter_mut() {
+        entries.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        for (position, (progress, speed, car_index)) in entries.iter().enumerate() {
+            let car_index = match car_index {
+                Some(car_index) => *car_index,
+                _ => {
+                    continue;
+                }
+            };
+            let leader = match (entries.get(position + 1), closed && entries.len() > 1) {
+                (Some((leader_progress, leader_speed, _)), _) => Some((*leader_progress, *leader_speed)),
+                // Around closed roads, the first car is ahead of the last one
+                (_, true) => Some((entries[0].0 + path.length, entries[0].1)),
+                _ => None,
+            };
+            let car = &traffic.cars[car_index];
+            let distance = path.lane_distance(&lanes[car.lane], progress.rem_euclid(path.length));
+            let desired_speed = path.target_speed_at(distance).unwrap_or(config.desired_speed) * car.speed_factor;
+            let gap = leader.map(|(leader_progress, leader_speed)| (leader_progress - progress - vehicle_length, leader_speed));
+            accelerations.push((car_index, idm_acceleration(&config, *speed, desired_speed, gap)));
+        }
+    }
+    for (car_index, acceleration) in accelerations {
+        let car = &mut traffic.cars[car_index];
+        car.speed = (car.speed + acceleration * TIMESTEP).max(0.0);
+        car.progress += car.speed * TIMESTEP;
+        if closed {
+            car.progress = car.progress.rem_euclid(path.length);
+        }
+    }
+
+    // New cars enter at the start of each lane, with room to drive
+    traffic.last_entry.resize(lanes.len(), None);
+    let interval = (config.spawn_interval / TIMESTEP).round() as u64;
+    for (lane_index, lane) in lanes.iter().enumerate() {
+        if traffic.cars.len() >= config.max_vehicles {
+            break;
+        }
+        let due = match traffic.last_entry[lane_index] {
+            Some(tick) => input.tick >= tick + interval,
+            _ => true,
+        };
+        let speed_factor = speed_factor(traffic.entered, config.speed_variation);
+        let speed = path.target_speed_at(path.lane_distance(lane, 0.0)).unwrap_or(config.desired_speed) * speed_factor;
+        let room = config.min_gap + vehicle_length + speed * config.time_headway;
+        let clear = lane_entries[lane_index]
+            .iter()
+            .all(|(progress, _, _)| *progress > room && (!closed || *progress < path.length - vehicle_length - config.min_gap));
+        if !due || !clear {
+            continue;
+        }
+        let transform = match path.lane_transform(lane, 0.0, height) {
+            Some(transform) => transform,
+            _ => {
+                continue;
+            }
+        };
+
+        let entity = enter_car(&mut commands, &asset_server, &mut traffic, &config.vehicle, manifest, transform);
+        traffic.cars.push(TrafficCar { entity, lane: lane_index, progress: 0.0, speed, speed_factor });
+        traffic.entered += 1;
+        traffic.last_entry[lane_index] = Some(input.tick);
+    }
+
+    for car in traffic.cars.iter() {
+        let lane = &lanes[car.lane];
+        let (mut transform, mut velocity) = match cars.get_mut(car.entity) {
+            Ok(components) => components,
+            // Spawned this tick
+            _ => {
+                continue;
+            }
+        };
+        if let Some(lane_transform) = path.lane_transform(lane, car.progress, height) {
+            *transform = lane_transform;
+            velocity.linvel = transform.forward() * car.speed;
+        }
+    }
+}
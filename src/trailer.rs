@@ -0,0 +1,511 @@
+//! Trailers and road trains.
+//!
+//! A trailer hitches its front hitch to the rear hitch of a vehicle or of
+//! another trailer with a joint (an `ImpulseJoint` on the trailer, whose
+//! parent is the towing body). The road train of a vehicle is the chain of
+//! trailers hitched behind it.
+//!
+//! Hitching goes through a coupling phase: the trailer is pulled until its
+//! front hitch meets the rear hitch of the tow, then the joint locks. Joints
+//! with a `hitchBreakImpulse` break when pulled too hard.
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{game::Game, simulation::TIMESTEP, vehicle::VehicleManifest};
+
+/// Hitches can be coupled when they are closer than this.
+pub const TRAILER_ATTACH_DISTANCE: f32 = 10.0;
+/// The joint locks when the coupling hitches are closer than this.
+pub const HITCH_LOCK_DISTANCE: f32 = 0.6;
+/// Coupling gives up after this many ticks.
+pub const COUPLING_TICKS: u32 = 180;
+
+// Coupling spring, per unit of mass of the trailer
+const COUPLING_STIFFNESS: f32 = 16.0;
+const COUPLING_DAMPING: f32 = 8.0;
+const COUPLING_ALIGN_STIFFNESS: f32 = 12.0;
+const COUPLING_ALIGN_DAMPING: f32 = 6.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum HitchKind {
+    /// Turns around the up axis of the tow.
+    #[default]
+    Revolute,
+    /// Turns freely, the trailer can also pitch and roll.
+    Ball,
+}
+
+/// Hitch anchors, in the local space of the body.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct Hitches {
+    /// Where this body attaches to the one towing it.
+    pub front: Option<Vec3>,
+    /// Where the next trailer attaches.
+    pub rear: Option<Vec3>,
+    /// Joint used at the front hitch.
+    pub kind: HitchKind,
+    /// The front hitch breaks over this joint impulse, if any.
+    pub break_impulse: Option<f32>,
+}
+
+impl Hitches {
+    pub fn from_manifest(manifest: &VehicleManifest) -> Self {
+        return Hitches {
+            front: manifest.front_hitch,
+            rear: manifest.rear_hitch,
+            kind: manifest.hitch_kind,
+            break_impulse: manifest.hitch_break_impulse,
+        };
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Trailer;
+
+/// Keeps a towed trailer in line with what tows it.
+/// Set with `trailerStability` in the trailer manifest.
+#[derive(Serialize, Deserialize, Component, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TrailerStability {
+    /// Damps the yaw rate relative to the tow, per unit of mass.
+    pub yaw_damping: f32,
+    /// Hitch angle, in degrees, over which the anti-jackknife torque kicks in.
+    pub jackknife_angle: f32,
+    /// Anti-jackknife torque per radian over `jackknife_angle`, per unit of mass.
+    pub anti_jackknife: f32,
+    /// Where the rear mass and drag apply, in local space.
+    pub rear_point: Vec3,
+    /// Point mass added at `rear_point`.
+    pub rear_mass: f32,
+    /// Damps the sideways velocity of `rear_point`, per unit of mass.
+    pub rear_drag: f32,
+}
+
+impl Default for TrailerStability {
+    fn default() -> Self {
+        TrailerStability {
+            yaw_damping: 15.0,
+            jackknife_angle: 45.0,
+            anti_jackknife: 20.0,
+            rear_point: Vec3::new(0.0, 0.0, 3.0),
+            rear_mass: 0.0,
+            rear_drag: 0.0,
+        }
+    }
+}
+
+impl TrailerStability {
+    /// Rear mass, to add to the body.
+    pub fn additional_mass_properties(&self) -> Option<AdditionalMassProperties> {
+        if self.rear_mass <= 0.0 {
+            return None;
+        }
+        return Some(AdditionalMassProperties::MassProperties(MassProperties {
+            local_center_of_mass: self.rear_point,
+            mass: self.rear_mass,
+            ..default()
+        }));
+    }
+}
+
+/// A trailer being pulled towards the rear hitch of `tow`.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Coupling {
+    pub tow: Entity,
+    /// Rear hitch of the tow, in its local space.
+    pub tow_rear_hitch: Vec3,
+    pub ticks: u32,
+}
+
+impl Coupling {
+    pub fn new(tow: Entity, tow_rear_hitch: Vec3) -> Self {
+        return Coupling {
+            tow,
+            tow_rear_hitch,
+            ticks: 0,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HitchEventKind {
+    CouplingStarted,
+    Hitched,
+    Unhitched,
+    CouplingFailed,
+    Broke,
+}
+
+/// Sent when a trailer hitch changes, for feedback.
+#[derive(Debug, Clone, Copy)]
+pub struct HitchEvent {
+    pub trailer: Entity,
+    pub kind: HitchEventKind,
+}
+
+/// Joint between the rear hitch of `tow` and the front hitch of a trailer.
+pub fn hitch_joint(tow: Entity, tow_rear_hitch: Vec3, trailer_front_hitch: Vec3, kind: HitchKind) -> ImpulseJoint {
+    return match kind {
+        HitchKind::Revolute => {
+            let joint_builder = RevoluteJointBuilder::new(Vec3::Y)
+                .local_anchor1(tow_rear_hitch)
+                .local_anchor2(trailer_front_hitch);
+            ImpulseJoint::new(tow, joint_builder)
+        },
+        HitchKind::Ball => {
+            let joint_builder = SphericalJointBuilder::new()
+                .local_anchor1(tow_rear_hitch)
+                .local_anchor2(trailer_front_hitch);
+            ImpulseJoint::new(tow, joint_builder)
+        },
+    };
+}
+
+/// Trailers hitched behind `head`, from the closest to the last.
+pub fn road_train(head: Entity, joints: &Query<(Entity, &ImpulseJoint), With<Trailer>>) -> Vec<Entity> {
+    let mut train: Vec<Entity> = Vec::new();
+    let mut tow = head;
+
+    loop {
+        let next = joints
+            .iter()
+            .find(|(_, joint)| joint.parent == tow)
+            .map(|(entity, _)| entity);
+
+        match next {
+            // Guard against a joint loop
+            Some(trailer) if !train.contains(&trailer) && trailer != head => {
+                train.push(trailer);
+                tow = trailer;
+            },
+            _ => {
+                return train;
+            }
+        }
+    }
+}
+
+/// Closest trailer which isn't hitched, with its distance to `point`.
+pub fn closest_free_trailer(
+    point: Vec3,
+    transforms: &Query<&Transform>,
+    joints: &Query<(Entity, &ImpulseJoint), With<Trailer>>,
+    trailers: &Query<Entity, With<Trailer>>,
+) -> Option<(Entity, f32)> {
+    let mut closest: Option<(Entity, f32)> = None;
+    for trailer in trailers.iter() {
+        if joints.contains(trailer) {
+            continue;
+        }
+        let distance = match transforms.get(trailer) {
+            Ok(transform) => transform.translation.distance(point),
+            _ => {
+                continue;
+            }
+        };
+        if closest.is_none() || distance < closest.unwrap().1 {
+            closest = Some((trailer, distance));
+        }
+    }
+    return closest;
+}
+
+/// Pulls coupling trailers until their front hitch meets the tow,
+/// then locks the joint.
+pub fn coupling_system(
+    mut commands: Commands,
+    tows: Query<(&Transform, &Velocity), Without<Coupling>>,
+    mut couplings: Query<(
+        Entity,
+        &mut Coupling,
+        &Hitches,
+        &Transform,
+        &Velocity,
+        &ReadMassProperties,
+        &mut ExternalForce,
+    )>,
+    mut hitch_events: EventWriter<HitchEvent>,
+) {
+    for (trailer, mut coupling, hitches, transform, velocity, mass_properties, mut ext_force) in couplings.iter_mut() {
+        let (tow_transform, tow_velocity, front_hitch) = match (tows.get(coupling.tow), hitches.front) {
+            (Ok((tow_transform, tow_velocity)), Some(front_hitch)) => (tow_transform, tow_velocity, front_hitch),
+            _ => {
+                // Tow is gone
+                commands.entity(trailer).remove::<Coupling>();
+                hitch_events.send(HitchEvent { trailer, kind: HitchEventKind::CouplingFailed });
+                continue;
+            }
+        };
+
+        let tow_hitch = tow_transform.mul_vec3(coupling.tow_rear_hitch);
+        let hitch = transform.mul_vec3(front_hitch);
+        let offset = tow_hitch - hitch;
+
+        if offset.length() < HITCH_LOCK_DISTANCE {
+            commands.entity(trailer)
+                .remove::<Coupling>()
+                .insert(hitch_joint(coupling.tow, coupling.tow_rear_hitch, front_hitch, hitches.kind));
+            hitch_events.send(HitchEvent { trailer, kind: HitchEventKind::Hitched });
+            continue;
+        }
+
+        coupling.ticks += 1;
+        if coupling.ticks > COUPLING_TICKS {
+            commands.entity(trailer).remove::<Coupling>();
+            hitch_events.send(HitchEvent { trailer, kind: HitchEventKind::CouplingFailed });
+            continue;
+        }
+
+        // Velocity of each hitch point
+        let hitch_velocity = velocity.linvel + velocity.angvel.cross(hitch - transform.translation);
+        let tow_hitch_velocity = tow_velocity.linvel
+            + tow_velocity.angvel.cross(tow_hitch - tow_transform.translation);
+
+        // Spring between the hitches. Applied at the center of mass, the
+        // hitch is far enough from it to make the spring unstable otherwise.
+        let mass = mass_properties.0.mass;
+        let force = (offset * COUPLING_STIFFNESS - (hitch_velocity - tow_hitch_velocity) * COUPLING_DAMPING) * mass;
+        ext_force.force += force;
+
+        // Face the same way as the tow
+        let alignment = transform.forward().cross(tow_transform.forward());
+        let angular_velocity = velocity.angvel - tow_velocity.angvel;
+        ext_force.torque += (alignment * COUPLING_ALIGN_STIFFNESS - angular_velocity * COUPLING_ALIGN_DAMPING) * mass;
+    }
+}
+
+/// Applies `TrailerStability` to hitched trailers.
+pub fn trailer_stability_system(
+    bodies: Query<(&Transform, &Velocity)>,
+    mut trailers: Query<(Entity, &ImpulseJoint, &TrailerStability, &ReadMassProperties, &mut ExternalForce)>,
+) {
+    for (trailer, joint, stability, mass_properties, mut ext_force) in trailers.iter_mut() {
+        let ((transform, velocity), (tow_transform, tow_velocity)) = match (bodies.get(trailer), bodies.get(joint.parent)) {
+            (Ok(trailer_body), Ok(tow_body)) => (trailer_body, tow_body),
+            _ => {
+                continue;
+            }
+        };
+
+        let mass = mass_properties.0.mass;
+        let up = transform.up();
+        let center_of_mass = transform.mul_vec3(mass_properties.0.local_center_of_mass);
+
+        // Yaw rate relative to the tow
+        let yaw_rate = (velocity.angvel - tow_velocity.angvel).dot(up);
+        ext_force.torque -= up * yaw_rate * stability.yaw_damping * mass;
+
+        // Signed hitch angle, positive when the trailer has to turn left to line up
+        let forward = transform.forward();
+        let tow_forward = tow_transform.forward();
+        let hitch_angle = forward.cross(tow_forward).dot(up).atan2(forward.dot(tow_forward));
+        let excess = hitch_angle.abs() - stability.jackknife_angle.to_radians();
+        if excess > 0.0 {
+            ext_force.torque += up * hitch_angle.signum() * excess * stability.anti_jackknife * mass;
+        }
+
+        // Drag at the rear, like the feathers of an arrow
+        if stability.rear_drag > 0.0 {
+            let rear_point = transform.mul_vec3(stability.rear_point);
+            let rear_velocity = velocity.linvel + velocity.angvel.cross(rear_point - center_of_mass);
+            let sideways_velocity = rear_velocity - rear_velocity.project_onto(forward) - rear_velocity.project_onto(up);
+            let drag = -sideways_velocity * stability.rear_drag * mass;
+            ext_force.force += drag;
+            ext_force.torque += (rear_point - center_of_mass).cross(drag);
+        }
+    }
+}
+
+type HitchJointQuery<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static Hitches, &'static RapierImpulseJointHandle),
+    (With<Trailer>, With<ImpulseJoint>),
+>;
+
+/// Breaks hitches pulled harder than their `break_impulse` on the last step.
+pub fn hitch_break_system(
+    mut commands: Commands,
+    context: Res<RapierContext>,
+    joints: HitchJointQuery,
+    mut hitch_events: EventWriter<HitchEvent>,
+) {
+    for (trailer, hitches, handle) in joints.iter() {
+        let break_impulse = match hitches.break_impulse {
+            Some(break_impulse) => break_impulse,
+            _ => {
+                continue;
+            }
+        };
+        let joint = match context.impulse_joints.get(handle.0) {
+            Some(joint) => joint,
+            _ => {
+                continue;
+            }
+        };
+
+        // Only the pull on the hitch point, not what holds the angles
+        let impulse = joint.impulses.fixed_rows::<3>(0).norm();
+        if impulse > break_impulse {
+            info!("Hitch broke ({:.1} N over {:.0} ms)", impulse / TIMESTEP, TIMESTEP * 1000.0);
+            commands.entity(trailer).remove::<ImpulseJoint>();
+            hitch_events.send(HitchEvent { trailer, kind: HitchEventKind::Broke });
+        }
+    }
+}
+
+pub fn log_hitch_events_system(
+    mut hitch_events: EventReader<HitchEvent>,
+) {
+    for event in hitch_events.iter() {
+        match event.kind {
+            HitchEventKind::CouplingStarted => info!("Coupling trailer"),
+            HitchEventKind::Hitched => info!("Trailer hitched"),
+            HitchEventKind::Unhitched => info!("Trailer unhitched"),
+            HitchEventKind::CouplingFailed => info!("Coupling failed"),
+            HitchEventKind::Broke => info!("Hitch broke"),
+        }
+    }
+}
+
+/// Ring showing the attach range around the rear hitch of the road train,
+/// and a marker on the front hitch of the closest free trailer.
+pub struct HitchIndicator {
+    range: Entity,
+    marker: Entity,
+    material: Handle<StandardMaterial>,
+}
+
+pub fn setup_hitch_indicator(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let material = materials.add(StandardMaterial {
+        base_color: Color::rgba(1.0, 1.0, 1.0, 0.5),
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..default()
+    });
+
+    let range = commands.spawn_bundle(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::Torus {
+            radius: TRAILER_ATTACH_DISTANCE,
+            ring_radius: 0.05,
+            subdivisions_segments: 64,
+            subdivisions_sides: 8,
+        })),
+        material: material.clone(),
+        visibility: Visibility { is_visible: false },
+        ..default()
+    }).id();
+
+    let marker = commands.spawn_bundle(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::UVSphere {
+            radius: 0.3,
+            ..default()
+        })),
+        material: material.clone(),
+        visibility: Visibility { is_visible: false },
+        ..default()
+    }).id();
+
+    commands.insert_resource(HitchIndicator {
+        range,
+        marker,
+        material,
+    });
+}
+
+type IndicatorTransforms<'w, 's> = ParamSet<
+    'w,
+    's,
+    (Query<'w, 's, &'static Transform>, Query<'w, 's, (&'static mut Transform, &'static mut Visibility)>),
+>;
+
+#[allow(clippy::too_many_arguments)]
+pub fn hitch_indicator_system(
+    mut transforms: IndicatorTransforms,
+    hitches: Query<&Hitches>,
+    joints: Query<(Entity, &ImpulseJoint), With<Trailer>>,
+    trailers: Query<Entity, With<Trailer>>,
+    couplings: Query<&Coupling>,
+    indicator: Option<Res<HitchIndicator>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    game: Res<Game>,
+) {
+    let indicator = match indicator {
+        Some(indicator) => indicator,
+        _ => {
+            return;
+        }
+    };
+
+    // Where the indicator goes, and its color
+    let mut placement: Option<(Vec3, Quat, Vec3, Color)> = None;
+
+    if let Some(vehicle) = game.player_car() {
+        let train = road_train(vehicle, &joints);
+        let tail = *train.last().unwrap_or(&vehicle);
+        let query = transforms.p0();
+
+        if let (Ok(Hitches { rear: Some(rear), .. }), Ok(tail_transform)) = (hitches.get(tail), query.get(tail)) {
+            let rear_hitch = tail_transform.mul_vec3(*rear);
+            let candidate = closest_free_trailer(rear_hitch, &query, &joints, &trailers);
+
+            if let Some((trailer, distance)) = candidate {
+                let front_hitch = match (hitches.get(trailer), query.get(trailer)) {
+                    (Ok(Hitches { front: Some(front), .. }), Ok(trailer_transform)) => Some(trailer_transform.mul_vec3(*front)),
+                    _ => None,
+                };
+
+                if let Some(front_hitch) = front_hitch {
+                    // Only when a trailer is around
+                    if distance < TRAILER_ATTACH_DISTANCE * 2.0 {
+                        let in_range = front_hitch.distance(rear_hitch) <= TRAILER_ATTACH_DISTANCE;
+                        let color = if couplings.contains(trailer) {
+                            Color::rgba(1.0, 0.8, 0.0, 0.8)
+                        } else if in_range {
+                            Color::rgba(0.0, 1.0, 0.3, 0.6)
+                        } else {
+                            Color::rgba(1.0, 1.0, 1.0, 0.3)
+                        };
+                        placement = Some((rear_hitch, tail_transform.rotation, front_hitch, color));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut query = transforms.p1();
+    match placement {
+        Some((rear_hitch, rotation, front_hitch, color)) => {
+            if let Ok((mut transform, mut visibility)) = query.get_mut(indicator.range) {
+                transform.translation = rear_hitch;
+                transform.rotation = rotation;
+                visibility.is_visible = true;
+            }
+            if let Ok((mut transform, mut visibility)) = query.get_mut(indicator.marker) {
+                transform.translation = front_hitch;
+                visibility.is_visible = true;
+            }
+            // Avoid modifying the material every frame
+            let current_color = materials.get(&indicator.material).map(|material| material.base_color);
+            if current_color != Some(color) {
+                if let Some(material) = materials.get_mut(&indicator.material) {
+                    material.base_color = color;
+                }
+            }
+        },
+        _ => {
+            for entity in [indicator.range, indicator.marker] {
+                if let Ok((_, mut visibility)) = query.get_mut(entity) {
+                    visibility.is_visible = false;
+                }
+            }
+        }
+    }
+}
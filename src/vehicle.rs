@@ -0,0 +1,550 @@
+//! Vehicle definitions, read from `assets/<name>/manifest.json`.
+
+use std::collections::HashMap;
+
+// This is only for native builds
+#[allow(unused_imports)]
+use std::{fs::{read_dir, read_to_string}, path::Path};
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    game::Game,
+    hover::{hover_drive_system, hover_thruster_system, HoverSettings, HoverVehicle},
+    input_map::Action,
+    replay::SimulationInput,
+    road_follow::{RoadFollower, DEFAULT_ROAD_FOLLOW_CLASS},
+    simulation::{add_simulation_system, SimulationStage},
+    trailer::{hitch_joint, Coupling, HitchEvent, HitchEventKind, HitchKind, Hitches, Trailer, TrailerStability},
+};
+
+// This is only for wasm builds
+#[allow(unused_imports)]
+use crate::windowmailer;
+
+/// Vehicle spawned for the player when the game starts.
+pub const DEFAULT_PLAYER_VEHICLE: &str = "car_0001";
+
+// The code here is not used in native builds
+#[allow(dead_code)]
+const VEHICLE_MANIFEST_CHANNEL_PREFIX: &str = "VEHICLE_MANIFEST_";
+
+// The code here is not used in native builds
+#[allow(dead_code)]
+const WEB_MANIFEST_NAMES: [&str; 3] = ["car_0001", "car_0002", "road_trailer_0001"];
+
+#[derive(Clone, Debug)]
+pub struct VehicleConfig {
+    /// Vehicle spawned for the player, if any.
+    pub player_vehicle: Option<String>,
+    pub player_transform: Transform,
+    /// Manifests to load. `None` loads every manifest of the assets folder.
+    pub manifest_names: Option<Vec<String>>,
+    /// Cycle through the roster with `Action::SwitchVehicle` (V).
+    pub vehicle_switching: bool,
+}
+
+impl Default for VehicleConfig {
+    fn default() -> Self {
+        VehicleConfig {
+            player_vehicle: Some(String::from(DEFAULT_PLAYER_VEHICLE)),
+            player_transform: Transform::from_xyz(2.0, 0.0, -5.0),
+            manifest_names: None,
+            vehicle_switching: true,
+        }
+    }
+}
+
+/// Vehicle manifests, the player's vehicle and hover driving.
+/// Requires `SimulationPlugin`.
+#[derive(Default)]
+pub struct VehiclePlugin {
+    pub config: VehicleConfig,
+}
+
+impl Plugin for VehiclePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.config.clone())
+            .init_resource::<Game>()
+            .init_resource::<VehicleRoster>()
+            .add_event::<HitchEvent>()
+            .add_startup_system(load_vehicle_roster)
+            .add_system(vehicle_manifest_load_check);
+
+        add_simulation_system(app, SimulationStage::Vehicles, spawn_player_vehicle_system);
+        if self.config.vehicle_switching {
+            add_simulation_system(app, SimulationStage::Vehicles, vehicle_switch_system.after(spawn_player_vehicle_system));
+        }
+        add_simulation_system(app, SimulationStage::VehicleForces, hover_thruster_system);
+        add_simulation_system(app, SimulationStage::VehicleForces, hover_drive_system.after(hover_thruster_system));
+    }
+}
+
+/// Content of a `manifest.json` file.
+///
+/// Field names are camelCase in the file. Everything except the original
+/// fields has a default, so a bare manifest still describes a drivable car.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VehicleManifest {
+    #[serde(default)]
+    pub has_custom_shader: bool,
+    #[serde(default)]
+    pub is_static_object: bool,
+    #[serde(default = "default_true")]
+    pub has_collisions: bool,
+    #[serde(default)]
+    pub is_vehicle: bool,
+
+    /// Scene file, relative to the vehicle folder.
+    #[serde(default = "default_model")]
+    pub model: String,
+    /// Half extents of the cuboid collider.
+    #[serde(default = "default_collider")]
+    pub collider: Vec3,
+    /// Collider density, the mass of the body follows from it and the collider.
+    #[serde(default = "default_density")]
+    pub density: f32,
+    #[serde(default = "default_linear_damping")]
+    pub linear_damping: f32,
+    #[serde(default = "default_angular_damping")]
+    pub angular_damping: f32,
+
+    /// Force applied when accelerating.
+    #[serde(default = "default_forward_thrust")]
+    pub forward_thrust: f32,
+    /// Force applied when braking/reversing.
+    #[serde(default = "default_backward_thrust")]
+    pub backward_thrust: f32,
+    /// Torque around the up axis (steering).
+    #[serde(default = "default_turn_torque")]
+    pub turn_torque: f32,
+    /// Torque around the forward axis.
+    #[serde(default = "default_turn_torque")]
+    pub roll_torque: f32,
+    /// Torque around the right axis.
+    #[serde(default = "default_pitch_torque")]
+    pub pitch_torque: f32,
+
+    /// Suspension and handling of vehicles.
+    #[serde(default)]
+    pub hover: HoverSettings,
+    /// Road following controller settings, see `RoadFollowClasses`.
+    #[serde(default = "default_road_follow_class")]
+    pub road_follow_class: String,
+
+    /// Trailers can be hitched to vehicles and other trailers.
+    #[serde(default)]
+    pub is_trailer: bool,
+    /// Where this trailer attaches to what tows it, in local space.
+    #[serde(default)]
+    pub front_hitch: Option<Vec3>,
+    /// Where trailers attach to this object, in local space.
+    /// Objects without one can't tow.
+    #[serde(default)]
+    pub rear_hitch: Option<Vec3>,
+    /// Joint at the front hitch: "revolute" only turns around the up axis,
+    /// "ball" also lets the trailer pitch and roll.
+    #[serde(default)]
+    pub hitch_kind: HitchKind,
+    /// The front hitch breaks when its joint impulse goes over this,
+    /// in newton seconds per tick. Unbreakable when missing.
+    #[serde(default)]
+    pub hitch_break_impulse: Option<f32>,
+    /// Stability assist of a towed trailer, none when missing.
+    #[serde(default)]
+    pub trailer_stability: Option<TrailerStability>,
+}
+
+fn default_true() -> bool { true }
+fn default_density() -> f32 { 1.0 }
+fn default_model() -> String { String::from("model.glb") }
+fn default_collider() -> Vec3 { Vec3::new(1.0, 1.0, 3.0) }
+fn default_linear_damping() -> f32 { 0.8 }
+fn default_angular_damping() -> f32 { 0.4 }
+fn default_forward_thrust() -> f32 { 160.0 }
+fn default_backward_thrust() -> f32 { 80.0 }
+fn default_turn_torque() -> f32 { 22.0 }
+fn default_pitch_torque() -> f32 { 25.0 }
+fn default_road_follow_class() -> String { String::from(DEFAULT_ROAD_FOLLOW_CLASS) }
+
+impl VehicleManifest {
+    pub fn from_json(json: &str) -> Result<VehicleManifest, serde_json::Error> {
+        return serde_json::from_str(json);
+    }
+
+    pub fn manifest_path(name: &str) -> String {
+        return format!("assets/{}/manifest.json", name);
+    }
+
+    /// Path of the scene, as expected by the asset server.
+    pub fn scene_path(&self, name: &str) -> String {
+        return format!("{}/{}#Scene0", name, self.model);
+    }
+}
+
+/// Attached to every spawned vehicle.
+#[derive(Component, Debug, Clone)]
+pub struct Vehicle {
+    pub name: String,
+    pub manifest: VehicleManifest,
+}
+
+/// Every manifest found in the assets folder, by name (the asset folder name).
+///
+/// Objects with `isVehicle` make up the roster the player can cycle through.
+#[derive(Default)]
+pub struct VehicleRoster {
+    pub manifests: HashMap<String, VehicleManifest>,
+    /// Manifests requested but not received yet (wasm builds).
+    pub pending: Vec<String>,
+}
+
+impl VehicleRoster {
+    /// Drivable vehicles, sorted by name.
+    pub fn vehicle_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.manifests
+            .iter()
+            .filter(|(_, manifest)| manifest.is_vehicle)
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        return names;
+    }
+
+    /// Vehicle following `current` in the roster, wrapping around.
+    pub fn next_vehicle(&self, current: &str) -> Option<String> {
+        let names = self.vehicle_names();
+        let next_index = match names.iter().position(|name| name == current) {
+            Some(index) => (index + 1) % names.len(),
+            _ => 0,
+        };
+        return names.get(next_index).cloned();
+    }
+}
+
+/// Folders of `assets/` that contain a `manifest.json`.
+pub fn discover_manifest_names() -> Vec<String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        // Browsers can't list directories, keep this in sync with the assets folder.
+        return WEB_MANIFEST_NAMES.iter().map(|name| name.to_string()).collect();
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let entries = match read_dir("assets") {
+            Ok(entries) => entries,
+            Err(error) => {
+                error!("Could not list the assets folder: {}", error);
+                return Vec::new();
+            }
+        };
+        let mut names: Vec<String> = Vec::new();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if Path::new(&VehicleManifest::manifest_path(&name)).is_file() {
+                names.push(name);
+            }
+        }
+        names.sort();
+        return names;
+    }
+}
+
+/// Starts loading the manifests of the given objects.
+///
+/// Native builds read the files right away, wasm builds fetch them and
+/// `vehicle_manifest_load_check` picks them up when they arrive.
+pub fn load_vehicle_manifests(names: &[String], roster: &mut VehicleRoster) {
+    for name in names {
+        #[cfg(target_arch = "wasm32")]
+        {
+            windowmailer::fetch_to_channel(
+                VehicleManifest::manifest_path(name),
+                format!("{}{}", VEHICLE_MANIFEST_CHANNEL_PREFIX, name)
+            );
+            roster.pending.push(name.clone());
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = VehicleManifest::manifest_path(name);
+            let serialized_manifest = match read_to_string(&path) {
+                Ok(serialized_manifest) => serialized_manifest,
+                Err(error) => {
+                    error!("Could not read {}: {}", path, error);
+                    continue;
+                }
+            };
+            match VehicleManifest::from_json(&serialized_manifest) {
+                Ok(manifest) => {
+                    roster.manifests.insert(name.clone(), manifest);
+                },
+                Err(error) => {
+                    error!("Could not load {}: {}", path, error);
+                }
+            }
+        }
+    }
+}
+
+pub fn load_vehicle_roster(config: Res<VehicleConfig>, mut roster: ResMut<VehicleRoster>) {
+    let names = match &config.manifest_names {
+        Some(names) => names.clone(),
+        _ => discover_manifest_names(),
+    };
+    load_vehicle_manifests(&names, &mut roster);
+}
+
+#[allow(unused_mut, unused_variables)]
+// Waits for manifests fetched by wasm builds
+pub fn vehicle_manifest_load_check(mut roster: ResMut<VehicleRoster>) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let pending = roster.pending.clone();
+        for name in pending {
+            let channel = format!("{}{}", VEHICLE_MANIFEST_CHANNEL_PREFIX, name);
+            if windowmailer::message_count(channel.clone()) == 0 {
+                continue;
+            }
+            let serialized_manifest: String = windowmailer::read_message(channel);
+            match VehicleManifest::from_json(&serialized_manifest) {
+                Ok(manifest) => {
+                    roster.manifests.insert(name.clone(), manifest);
+                },
+                Err(error) => {
+                    error!("Could not load {}: {}", VehicleManifest::manifest_path(&name), error);
+                }
+            }
+            roster.pending.retain(|pending_name| *pending_name != name);
+        }
+    }
+}
+
+/// Spawns a vehicle body described by its manifest.
+pub fn spawn_vehicle(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    name: &str,
+    manifest: &VehicleManifest,
+    transform: Transform,
+) -> Entity {
+    let scene = asset_server.load(&manifest.scene_path(name));
+
+    let mass_properties = ColliderMassProperties::Density(manifest.density);
+
+    let rigid_body = if manifest.is_static_object {
+        RigidBody::Fixed
+    } else {
+        RigidBody::Dynamic
+    };
+
+    let mut entity_commands = commands.spawn_bundle(SceneBundle {
+        scene,
+        transform,
+        ..Default::default()
+    });
+
+    entity_commands
+        .insert(rigid_body)
+        .insert(Collider::cuboid(manifest.collider.x, manifest.collider.y, manifest.collider.z))
+        .insert(mass_properties)
+        .insert(Friction::coefficient(0.0))
+        .insert(Damping {
+            linear_damping: manifest.linear_damping,
+            angular_damping: manifest.angular_damping,
+        })
+        .insert(Velocity {
+            linvel: Vec3::new(0.0, 0.0, 0.0),
+            angvel: Vec3::new(0.0, 0.0, 0.0),
+        })
+        .insert(ExternalForce {
+            force: Vec3::new(0.0, 0.0, 0.0),
+            torque: Vec3::new(0.0, 0.0, 0.0),
+        })
+        .insert(Vehicle {
+            name: name.to_string(),
+            manifest: manifest.clone(),
+        })
+        .insert(Hitches::from_manifest(manifest));
+
+    if manifest.is_vehicle {
+        entity_commands
+            .insert(HoverVehicle::from_manifest(manifest))
+            .insert(ReadMassProperties::default());
+    }
+
+    if manifest.is_trailer {
+        entity_commands
+            .insert(Trailer)
+            .insert(ReadMassProperties::default());
+
+        if let Some(stability) = manifest.trailer_stability {
+            entity_commands.insert(stability);
+            if let Some(mass_properties) = stability.additional_mass_properties() {
+                entity_commands.insert(mass_properties);
+            }
+        }
+    }
+
+    if manifest.is_vehicle || manifest.is_trailer {
+        entity_commands.insert(RoadFollower::new(&manifest.road_follow_class));
+    }
+
+    if !manifest.has_collisions {
+        // Keep the collider for mass computation, but don't collide.
+        entity_commands.insert(Sensor);
+    }
+
+    return entity_commands.id();
+}
+
+/// Distance between the starting places of local players.
+pub const PLAYER_SPACING: f32 = 8.0;
+
+/// Spawns the players' vehicles once their manifest is available.
+/// Local players after the first start side by side, to the right of the first.
+pub fn spawn_player_vehicle_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<VehicleConfig>,
+    mut game: ResMut<Game>,
+    roster: Res<VehicleRoster>,
+) {
+    let name = match &config.player_vehicle {
+        Some(name) => name,
+        _ => {
+            return;
+        }
+    };
+
+    let manifest = match roster.manifests.get(name) {
+        Some(manifest) => manifest,
+        _ => {
+            return;
+        }
+    };
+
+    for (index, player) in game.players.iter_mut().enumerate() {
+        if player.car.is_some() {
+            continue;
+        }
+        let mut transform = config.player_transform;
+        transform.translation += transform.right() * PLAYER_SPACING * index as f32;
+        player.car = Some(spawn_vehicle(&mut commands, &asset_server, name, manifest, transform));
+    }
+}
+
+/// Switch a player to the next vehicle of the roster (V).
+///
+/// The new vehicle takes the place and velocity of the current one,
+/// the road train and a trailer being coupled are hitched to it
+/// and the player's camera follows it.
+pub fn vehicle_switch_system(
+    mut commands: Commands,
+    input: Res<SimulationInput>,
+    asset_server: Res<AssetServer>,
+    mut game: ResMut<Game>,
+    roster: Res<VehicleRoster>,
+    vehicles: Query<(&Vehicle, &Transform, &Velocity)>,
+    joints: Query<(Entity, &ImpulseJoint, &Hitches), With<Trailer>>,
+    mut couplings: Query<(Entity, &mut Coupling)>,
+    mut hitch_events: EventWriter<HitchEvent>,
+) {
+    for (index, player) in game.players.iter_mut().enumerate() {
+        let switch = input.actions_of(index).map(|actions| actions.just_released(Action::SwitchVehicle));
+        if switch != Some(true) {
+            continue;
+        }
+        let new_car = switch_vehicle(
+            &mut commands,
+            &asset_server,
+            &roster,
+            &vehicles,
+            &joints,
+            &mut couplings,
+            &mut hitch_events,
+            player.car,
+        );
+        if let Some(new_car) = new_car {
+            player.car = Some(new_car);
+        }
+    }
+}
+
+fn switch_vehicle(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    roster: &VehicleRoster,
+    vehicles: &Query<(&Vehicle, &Transform, &Velocity)>,
+    joints: &Query<(Entity, &ImpulseJoint, &Hitches), With<Trailer>>,
+    couplings: &mut Query<(Entity, &mut Coupling)>,
+    hitch_events: &mut EventWriter<HitchEvent>,
+    old_car: Option<Entity>,
+) -> Option<Entity> {
+    let old_car = match old_car {
+        Some(entity) => entity,
+        _ => {
+            return None;
+        }
+    };
+    let (vehicle, transform, velocity) = match vehicles.get(old_car) {
+        Ok(components) => components,
+        _ => {
+            return None;
+        }
+    };
+
+    let next_name = match roster.next_vehicle(&vehicle.name) {
+        Some(name) if name != vehicle.name => name,
+        _ => {
+            return None;
+        }
+    };
+
+    let manifest = match roster.manifests.get(&next_name) {
+        Some(manifest) => manifest,
+        _ => {
+            return None;
+        }
+    };
+
+    let new_car = spawn_vehicle(commands, asset_server, &next_name, manifest, *transform);
+    commands.entity(new_car).insert(*velocity);
+
+    // Hand over the road train to the new vehicle, at its own hitch
+    for (trailer, joint, hitches) in joints.iter() {
+        if joint.parent != old_car {
+            continue;
+        }
+        match (manifest.rear_hitch, hitches.front) {
+            (Some(rear_hitch), Some(front_hitch)) => {
+                commands.entity(trailer).insert(hitch_joint(new_car, rear_hitch, front_hitch, hitches.kind));
+            },
+            _ => {
+                commands.entity(trailer).remove::<ImpulseJoint>();
+            }
+        }
+    }
+
+    // A trailer being coupled is pulled towards the new rear hitch instead
+    for (trailer, mut coupling) in couplings.iter_mut() {
+        if coupling.tow != old_car {
+            continue;
+        }
+        match manifest.rear_hitch {
+            Some(rear_hitch) => {
+                coupling.tow = new_car;
+                coupling.tow_rear_hitch = rear_hitch;
+            },
+            _ => {
+                commands.entity(trailer).remove::<Coupling>();
+                hitch_events.send(HitchEvent { trailer, kind: HitchEventKind::CouplingFailed });
+            }
+        }
+    }
+
+    commands.entity(old_car).despawn_recursive();
+    return Some(new_car);
+}
@@ -0,0 +1,453 @@
+//! Minimal WebSocket connections over TCP (RFC 6455), used by `net`.
+//!
+//! Connections never block once the TCP connection is made: `receive` reads
+//! whatever arrived, and `send` writes what the socket takes and keeps the rest
+//! for the next call. Only text messages are supported. Browsers use their own
+//! WebSocket instead, see `net::NetConnection`.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Larger messages close the connection.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Larger HTTP upgrade requests or answers close the connection.
+const MAX_HANDSHAKE_SIZE: usize = 8 * 1024;
+
+/// Largest frame header: opcode, length and mask.
+const MAX_FRAME_HEADER_SIZE: usize = 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebSocketState {
+    /// Waiting for the HTTP upgrade. Sent messages are queued.
+    Handshaking,
+    Open,
+    Closed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Client,
+    Server,
+}
+
+pub struct WebSocket {
+    stream: TcpStream,
+    role: Role,
+    state: WebSocketState,
+    /// Key sent by the client, to check the server's answer.
+    key: String,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    /// Messages sent before the handshake is over.
+    queued: Vec<String>,
+    /// Payload of a fragmented message.
+    fragments: Vec<u8>,
+}
+
+impl WebSocket {
+    /// Connects to a `ws://host:port/path` url. The TCP connection is made
+    /// at once, the handshake completes during the following `receive` calls.
+    pub fn connect(url: &str) -> std::io::Result<Self> {
+        let address = match url.strip_prefix("ws://") {
+            Some(address) => address,
+            _ => {
+                return Err(std::io::Error::new(ErrorKind::InvalidInput, "only ws:// urls are supported"));
+            }
+        };
+        let (host, path) = match address.find('/') {
+            Some(index) => (&address[..index], &address[index..]),
+            _ => (address, "/"),
+        };
+
+        let stream = TcpStream::connect(host)?;
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+
+        let nonce: Vec<u8> = random_u64().to_le_bytes().iter().chain(random_u64().to_le_bytes().iter()).copied().collect();
+        let key = base64(&nonce);
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            path, host, key,
+        );
+
+        let mut socket = WebSocket::new(stream, Role::Client);
+        socket.key = key;
+        socket.output.extend_from_slice(request.as_bytes());
+        socket.flush();
+        return Ok(socket);
+    }
+
+    /// Serves a connection accepted by a `TcpListener`.
+    /// The handshake completes during the following `receive` calls.
+    pub fn accept(stream: TcpStream) -> std::io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        return Ok(WebSocket::new(stream, Role::Server));
+    }
+
+    fn new(stream: TcpStream, role: Role) -> Self {
+        return WebSocket {
+            stream,
+            role,
+            state: WebSocketState::Handshaking,
+            key: String::new(),
+            input: Vec::new(),
+            output: Vec::new(),
+            queued: Vec::new(),
+            fragments: Vec::new(),
+        };
+    }
+
+    pub fn state(&self) -> WebSocketState {
+        return self.state;
+    }
+
+    /// Sends a text message, or queues it until the handshake is over.
+    pub fn send(&mut self, message: &str) {
+        match self.state {
+            WebSocketState::Handshaking => {
+                self.queued.push(message.to_string());
+            },
+            WebSocketState::Open => {
+                self.write_frame(0x1, message.as_bytes());
+                self.flush();
+            },
+            WebSocketState::Closed => {}
+        }
+    }
+
+    /// Closes the connection, telling the other side when it is open.
+    pub fn close(&mut self) {
+        if self.state == WebSocketState::Open {
+            self.write_frame(0x8, &[]);
+            self.flush();
+        }
+        self.state = WebSocketState::Closed;
+    }
+
+    /// Text messages received since the last call.
+    pub fn receive(&mut self) -> Vec<String> {
+        let mut messages = Vec::new();
+        if self.state == WebSocketState::Closed {
+            return messages;
+        }
+
+        // Stop reading once more than a handshake or a frame arrived, they are rejected anyway
+        let input_limit = match self.state {
+            WebSocketState::Handshaking => MAX_HANDSHAKE_SIZE,
+            _ => MAX_MESSAGE_SIZE + MAX_FRAME_HEADER_SIZE,
+        };
+        let mut buffer = [0; 4096];
+        while self.input.len() <= input_limit {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.state = WebSocketState::Closed;
+                    break;
+                },
+                Ok(length) => {
+                    self.input.extend_from_slice(&buffer[..length]);
+                },
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                    break;
+                },
+                Err(error) if error.kind() == ErrorKind::Interrupted => {},
+                Err(_) => {
+                    self.state = WebSocketState::Closed;
+                    break;
+                }
+            }
+        }
+
+        if self.state == WebSocketState::Handshaking {
+            self.handshake();
+        }
+        while self.state == WebSocketState::Open {
+            match self.read_frame() {
+                Some(Some(message)) => {
+                    messages.push(message);
+                },
+                Some(None) => {},
+                None => {
+                    break;
+                }
+            }
+        }
+
+        self.flush();
+        return messages;
+    }
+
+    /// Reads the HTTP upgrade request or answer, once it is complete.
+    fn handshake(&mut self) {
+        let end = match self.input.windows(4).position(|window| window == b"\r\n\r\n") {
+            Some(end) => end,
+            _ => {
+                if self.input.len() > MAX_HANDSHAKE_SIZE {
+                    self.state = WebSocketState::Closed;
+                }
+                return;
+            }
+        };
+        let head = String::from_utf8_lossy(&self.input[..end]).to_string();
+        self.input.drain(..end + 4);
+
+        let header = |name: &str| -> Option<String> {
+            return head.lines().skip(1).find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                if key.trim().eq_ignore_ascii_case(name) {
+                    return Some(value.trim().to_string());
+                }
+                return None;
+            });
+        };
+
+        match self.role {
+            Role::Server => {
+                let key = match header("Sec-WebSocket-Key") {
+                    Some(key) => key,
+                    _ => {
+                        self.output.extend_from_slice(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+                        self.flush();
+                        self.state = WebSocketState::Closed;
+                        return;
+                    }
+                };
+                let response = format!(
+                    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                    accept_key(&key),
+                );
+                self.output.extend_from_slice(response.as_bytes());
+            },
+            Role::Client => {
+                let switching = head.lines().next().map(|status| status.contains(" 101")).unwrap_or(false);
+                if !switching || header("Sec-WebSocket-Accept") != Some(accept_key(&self.key)) {
+                    self.state = WebSocketState::Closed;
+                    return;
+                }
+            },
+        }
+
+        self.state = WebSocketState::Open;
+        for message in std::mem::take(&mut self.queued) {
+            self.write_frame(0x1, message.as_bytes());
+        }
+    }
+
+    /// Reads one frame: None when it isn't complete yet,
+    /// Some(None) for control frames and fragments.
+    fn read_frame(&mut self) -> Option<Option<String>> {
+        if self.input.len() < 2 {
+            return None;
+        }
+        let fin = self.input[0] & 0x80 != 0;
+        let opcode = self.input[0] & 0x0f;
+        let masked = self.input[1] & 0x80 != 0;
+
+        let (length, mut offset) = match self.input[1] & 0x7f {
+            126 => {
+                if self.input.len() < 4 {
+                    return None;
+                }
+                (u16::from_be_bytes([self.input[2], self.input[3]]) as usize, 4)
+            },
+            127 => {
+                if self.input.len() < 10 {
+                    return None;
+                }
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&self.input[2..10]);
+                (usize::try_from(u64::from_be_bytes(bytes)).unwrap_or(usize::MAX), 10)
+            },
+            length => (length as usize, 2),
+        };
+        // The length comes from the peer, don't let it overflow
+        let too_large = match length.checked_add(self.fragments.len()) {
+            Some(total) => length > MAX_MESSAGE_SIZE || total > MAX_MESSAGE_SIZE,
+            _ => true,
+        };
+        // Clients must mask their frames
+        let unmasked_client = self.role == Role::Server && !masked;
+        if too_large || unmasked_client {
+            self.state = WebSocketState::Closed;
+            return None;
+        }
+
+        let mut mask = [0; 4];
+        if masked {
+            if self.input.len() < offset + 4 {
+                return None;
+            }
+            mask.copy_from_slice(&self.input[offset..offset + 4]);
+            offset += 4;
+        }
+        if self.input.len() < offset + length {
+            return None;
+        }
+        let mut payload: Vec<u8> = self.input.drain(..offset + length).skip(offset).collect();
+        if masked {
+            for (index, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[index % 4];
+            }
+        }
+
+        match opcode {
+            // Text, binary and continuation frames
+            0x0..=0x2 => {
+                self.fragments.append(&mut payload);
+                if !fin {
+                    return Some(None);
+                }
+                let message = std::mem::take(&mut self.fragments);
+                return Some(String::from_utf8(message).ok());
+            },
+            0x8 => {
+                self.close();
+                return None;
+            },
+            0x9 => {
+                self.write_frame(0xa, &payload);
+                return Some(None);
+            },
+            _ => {
+                return Some(None);
+            }
+        }
+    }
+
+    /// Queues a single frame. Clients mask their frames, servers don't.
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) {
+        self.output.push(0x80 | opcode);
+        let mask_bit = match self.role {
+            Role::Client => 0x80,
+            Role::Server => 0x00,
+        };
+        match payload.len() {
+            length if length < 126 => {
+                self.output.push(mask_bit | length as u8);
+            },
+            length if length <= u16::MAX as usize => {
+                self.output.push(mask_bit | 126);
+                self.output.extend_from_slice(&(length as u16).to_be_bytes());
+            },
+            length => {
+                self.output.push(mask_bit | 127);
+                self.output.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+
+        match self.role {
+            Role::Client => {
+                let mask = (random_u64() as u32).to_be_bytes();
+                self.output.extend_from_slice(&mask);
+                self.output.extend(payload.iter().enumerate().map(|(index, byte)| byte ^ mask[index % 4]));
+            },
+            Role::Server => {
+                self.output.extend_from_slice(payload);
+            },
+        }
+    }
+
+    /// Writes as much of the output as the socket takes.
+    fn flush(&mut self) {
+        while !self.output.is_empty() {
+            match self.stream.write(&self.output) {
+                Ok(0) => {
+                    self.state = WebSocketState::Closed;
+                    return;
+                },
+                Ok(length) => {
+                    self.output.drain(..length);
+                },
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                    return;
+                },
+                Err(error) if error.kind() == ErrorKind::Interrupted => {},
+                Err(_) => {
+                    self.state = WebSocketState::Closed;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// `Sec-WebSocket-Accept` answer to a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    return base64(&sha1(format!("{}{}", key, GUID).as_bytes()));
+}
+
+/// Random enough for handshake nonces and frame masks.
+fn random_u64() -> u64 {
+    return RandomState::new().build_hasher().finish();
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let bits = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    return encoded;
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (index, word) in block.chunks(4).enumerate() {
+            words[index] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for index in 16..80 {
+            words[index] = (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(added);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (index, value) in state.iter().enumerate() {
+        digest[index * 4..index * 4 + 4].copy_from_slice(&value.to_be_bytes());
+    }
+    return digest;
+}
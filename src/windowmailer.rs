@@ -1,8 +1,17 @@
 /// Send messages through the window object.
 
-use wasm_bindgen::prelude::*;
+use std::str;
+
+use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    Response,
+    ReadableStream,
+    ReadableStreamDefaultReader
+};
 use js_sys::Map;
 use js_sys::Array;
+use js_sys::Uint8Array;
 
 const MAIL_VAR: &str = "WINDOW_MAILER_MAILBOX";
 
@@ -14,7 +23,8 @@ const MAIL_VAR: &str = "WINDOW_MAILER_MAILBOX";
 ///
 /// Example:
 ///
-/// ```
+/// ```no_run
+/// # use osd::windowmailer;
 /// windowmailer::send_message(
 ///     String::from("ROAD_NETWORK_LOADED"),
 ///     String::from("ARE_ROADS_LOADED")
@@ -88,3 +98,51 @@ pub fn read_message(_channel_name: String) -> String {
 
     return value.as_string().unwrap();
 }
+
+// The code here is not used in native builds
+#[allow(dead_code)]
+async fn response_to_string(message: JsValue) -> Result<String, JsValue>{
+    let response: Response = message.dyn_into()?;
+    let stream: ReadableStream = response.body().unwrap();
+    let reader: ReadableStreamDefaultReader = stream.get_reader().dyn_into()?;
+    let result_value: JsValue = JsFuture::from(reader.read()).await?;
+    let array: Uint8Array = js_sys::Reflect::get(&result_value, &JsValue::from("value"))?.dyn_into()?;
+
+    let rust_vec: Vec<u8> = array.to_vec();
+    let str_string: &str = str::from_utf8(&rust_vec).unwrap();
+    let string: String = str_string.to_string();
+
+    return Ok(string);
+}
+
+/// Fetches a file and sends its content as a message in the specified channel.
+///
+/// Example:
+///
+/// ```no_run
+/// # use osd::windowmailer;
+/// windowmailer::fetch_to_channel(
+///     String::from("assets/road_network.json"),
+///     String::from("ROAD_NETWORK_DATA")
+/// );
+/// ```
+// The code here is not used in native builds
+#[allow(dead_code)]
+pub fn fetch_to_channel(url: String, channel_name: String) {
+    let load_assets = async move {
+        let window = web_sys::window().unwrap();
+        let future = JsFuture::from(window.fetch_with_str(&url)).await;
+
+        match future {
+            Ok(future) => {
+                let string: String = response_to_string(future).await.unwrap();
+                send_message(channel_name, string);
+            }
+            _ => {
+                web_sys::console::log_1(&JsValue::from(format!("Error in fetch: {}", url)));
+            }
+        }
+    };
+
+    wasm_bindgen_futures::spawn_local(load_assets);
+}
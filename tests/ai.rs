@@ -0,0 +1,75 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use osd::{
+    ai::{ai_drive_input, AiSettings, Obstacle, ObstacleGrid},
+    harness::{circle, Harness},
+    road_import::{road_network_from_points, UpMode},
+    road_network_builder::RoadPath,
+};
+
+#[test]
+fn ai_steers_towards_the_road_ahead_and_holds_segment_speeds() {
+    let straight = road_network_from_points(&[Vec3::ZERO, Vec3::new(0.0, 0.0, -100.0), Vec3::new(0.0, 0.0, -400.0)], UpMode::Level);
+    assert!(!straight.is_closed());
+    assert!(road_network_from_points(&circle(50.0, 16), UpMode::Level).is_closed());
+
+    let settings = AiSettings::default();
+    let still = Velocity::default();
+
+    // Left of the road, facing along it: steer right, and speed up
+    let path = RoadPath::new(&straight);
+    let (input, _) = ai_drive_input(&settings, &path, &Transform::from_xyz(-5.0, 5.5, -10.0), &still, &[]);
+    assert!(input.steer < 0.0);
+    assert!(input.throttle > 0.0);
+
+    // A slow segment brakes a car going faster
+    let mut slow = straight.clone();
+    slow.road_segments[0].target_speed = Some(5.0);
+    assert_eq!(slow.target_speed_at(50.0), Some(5.0));
+    assert_eq!(slow.target_speed_at(150.0), None);
+    let fast = Velocity { linvel: Vec3::new(0.0, 0.0, -20.0), ..default() };
+    let (input, _) = ai_drive_input(&settings, &RoadPath::new(&slow), &Transform::from_xyz(0.0, 5.5, -10.0), &fast, &[]);
+    assert!(input.throttle < 0.0);
+    let (input, _) = ai_drive_input(&settings, &path, &Transform::from_xyz(0.0, 5.5, -10.0), &fast, &[]);
+    assert!(input.throttle > 0.0);
+
+    // A stopped car right ahead: brake, and swerve around it
+    let obstacle = Obstacle { entity: Entity::from_raw(7), position: Vec3::new(1.0, 5.5, -20.0), velocity: Vec3::ZERO };
+    let (input, avoiding) = ai_drive_input(&settings, &path, &Transform::from_xyz(0.0, 5.5, -10.0), &fast, &[obstacle]);
+    assert_eq!(avoiding, Some(obstacle.entity));
+    assert!(input.throttle < 0.0);
+    assert!(input.steer > 0.0);
+}
+
+#[test]
+fn obstacles_are_found_near_the_car() {
+    let obstacle = |id: u32, position: Vec3| Obstacle { entity: Entity::from_raw(id), position, velocity: Vec3::ZERO };
+    let grid = ObstacleGrid::new([obstacle(1, Vec3::new(0.0, 0.0, -30.0)), obstacle(2, Vec3::new(-5.0, 0.0, 35.0)), obstacle(3, Vec3::new(500.0, 0.0, 0.0))], 40.0);
+    let mut near: Vec<u32> = grid.near(Vec3::ZERO, 40.0).map(|obstacle| obstacle.entity.id()).collect();
+    near.sort();
+    assert_eq!(near, vec![1, 2]);
+}
+
+#[test]
+fn ai_cars_drive_around_the_road() {
+    let mut harness = Harness::new();
+    let road_network = road_network_from_points(&circle(150.0, 64), UpMode::Level);
+    let length = road_network.length();
+    harness.load_road_network(road_network);
+    let settings = AiSettings { target_speed: 20.0, ..default() };
+    let car = harness.spawn_ai_car("car_0002", Transform::from_xyz(0.0, 5.5, 0.0), settings.clone());
+    harness.step(60 * 20);
+
+    // Further than a lap at half the target speed, and on the road all along
+    let along = |harness: &Harness, car: Entity| harness.game().road_network.distance_along(harness.transform(car).translation).unwrap();
+    let mut driven = 0.0;
+    let mut last = along(&harness, car).0;
+    for _ in 0..60 {
+        harness.step(10);
+        let (distance, off) = along(&harness, car);
+        assert!(off < 10.0, "{} off the road", off);
+        driven += (distance - last).rem_euclid(length);
+        last = distance;
+    }
+    assert!(driven > 10.0 * 10.0, "drove {}", driven);
+}
@@ -0,0 +1,60 @@
+use bevy::prelude::*;
+use osd::camera::{smooth_damp, CameraCollision};
+
+/// Eases from the origin to `target` for `seconds`, returning every step.
+fn ease(target: Vec3, smooth_time: f32, dt: f32, seconds: f32) -> Vec<Vec3> {
+    let mut position = Vec3::ZERO;
+    let mut velocity = Vec3::ZERO;
+    let steps = (seconds / dt).round() as usize;
+    return (0..steps)
+        .map(|_| {
+            position = smooth_damp(position, target, &mut velocity, smooth_time, dt);
+            position
+        })
+        .collect();
+}
+
+#[test]
+fn smoothing_reaches_the_target_without_overshooting() {
+    let target = Vec3::new(10.0, 0.0, -5.0);
+    let steps = ease(target, 0.25, 1.0 / 60.0, 3.0);
+
+    for pair in steps.windows(2) {
+        // Always closer, never past the target
+        assert!(pair[1].distance(target) <= pair[0].distance(target) + 1e-5);
+    }
+    assert!(steps.last().unwrap().distance(target) < 0.01);
+}
+
+#[test]
+fn smoothing_barely_depends_on_the_frame_rate() {
+    let target = Vec3::new(0.0, 20.0, 0.0);
+    let at_30_fps = *ease(target, 0.25, 1.0 / 30.0, 0.5).last().unwrap();
+    let at_144_fps = *ease(target, 0.25, 1.0 / 144.0, 0.5).last().unwrap();
+    assert!(at_30_fps.distance(at_144_fps) < 0.5, "{} and {}", at_30_fps, at_144_fps);
+}
+
+#[test]
+fn collision_pulls_in_at_once_and_restores_smoothly() {
+    let mut collision = CameraCollision::default();
+    let dt = 1.0 / 60.0;
+
+    assert_eq!(collision.update(20.0, None, 0.6, dt), 20.0);
+
+    // A pillar 8 units away
+    assert_eq!(collision.update(20.0, Some(8.0), 0.6, dt), 8.0);
+
+    // Moves back without jumping
+    let mut distance = 8.0;
+    for _ in 0..30 {
+        let next = collision.update(20.0, None, 0.6, dt);
+        assert!(next >= distance && next - distance < 2.0, "jumped from {} to {}", distance, next);
+        distance = next;
+    }
+    assert!(distance < 20.0);
+
+    for _ in 0..300 {
+        distance = collision.update(20.0, None, 0.6, dt);
+    }
+    assert_eq!(distance, 20.0);
+}
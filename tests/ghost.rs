@@ -0,0 +1,74 @@
+use bevy::prelude::*;
+use osd::{
+    drive_input::DriveInput,
+    ghost::{ghost_path, GhostLap, GhostSample, GhostVehicle, Ghosts},
+    harness::{circle, Harness},
+    input_map::Action,
+    race::Race,
+    road_import::{road_network_from_points, UpMode},
+};
+
+#[test]
+fn ghost_files_sit_next_to_the_track() {
+    assert_eq!(ghost_path("assets/road_network.json"), "assets/road_network.ghost.json");
+
+    let ghost = GhostLap {
+        track_id: String::from("0123456789abcdef"),
+        vehicle: String::from("car_0001"),
+        time: 0.05,
+        timestep: 0.025,
+        samples: vec![
+            GhostSample { translation: Vec3::ZERO, rotation: Quat::IDENTITY },
+            GhostSample { translation: Vec3::new(0.0, 0.0, -1.0), rotation: Quat::IDENTITY },
+        ],
+    };
+    let loaded = GhostLap::from_json(&serde_json::to_string(&ghost).unwrap()).unwrap();
+    assert_eq!(loaded, ghost);
+
+    // Between samples, and parked on the line after the lap
+    assert!((ghost.sample_at(0.0125).unwrap().translation.z + 0.5).abs() < 1e-4);
+    assert_eq!(ghost.sample_at(10.0).unwrap().translation, Vec3::new(0.0, 0.0, -1.0));
+}
+
+#[test]
+fn best_laps_are_replayed_as_ghosts() {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&circle(150.0, 64), UpMode::Level));
+    let car = harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, 0.0));
+    harness.step(30);
+
+    harness.tap(Action::ToggleRaceMode);
+    harness.set_drive_input(DriveInput { throttle: 0.3, ..Default::default() });
+
+    let laps = |harness: &Harness| harness.app.world.resource::<Race>().laps;
+    harness.step_while(60 * 120, |harness| laps(harness) < 1);
+    assert_eq!(laps(&harness), 1);
+
+    // The first lap became the ghost, one sample per tick
+    let ghost = harness.app.world.resource::<Ghosts>().best.clone().unwrap();
+    let first_lap = harness.app.world.resource::<Race>().last_lap.unwrap();
+    assert_eq!(ghost.time, first_lap);
+    assert_eq!(ghost.vehicle, "car_0001");
+    assert_eq!(ghost.track_id, harness.game().road_network.track_id());
+    assert!((ghost.samples.len() as f32 * ghost.timestep - first_lap).abs() < 0.1);
+
+    // And drives along on the next lap
+    harness.step(60 * 5);
+    let mut ghost_cars = harness.app.world.query_filtered::<&Transform, With<GhostVehicle>>();
+    let ghost_transform = *ghost_cars.single(&harness.app.world);
+    let expected = ghost.sample_at(harness.app.world.resource::<Race>().lap_time(harness.tick()).unwrap()).unwrap();
+    assert!(ghost_transform.translation.distance(expected.translation) < 1.0);
+    assert!(ghost_transform.translation.distance(harness.transform(car).translation) > 1.0);
+
+    // The flying lap is faster and replaces it
+    harness.step_while(60 * 120, |harness| laps(harness) < 2);
+    let ghost = harness.app.world.resource::<Ghosts>().best.clone().unwrap();
+    assert_eq!(ghost.time, harness.app.world.resource::<Race>().last_lap.unwrap());
+    assert!(ghost.time < first_lap);
+
+    // No ghost outside race mode
+    harness.tap(Action::ToggleRaceMode);
+    harness.step(1);
+    let mut ghost_cars = harness.app.world.query_filtered::<Entity, With<GhostVehicle>>();
+    assert_eq!(ghost_cars.iter(&harness.app.world).count(), 0);
+}
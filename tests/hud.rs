@@ -0,0 +1,51 @@
+use bevy::prelude::*;
+use osd::{
+    hud::{binding_label, Toasts},
+    input_map::{Action, Binding, InputMap},
+    road_network_builder::RoadNetwork,
+};
+
+#[test]
+fn toasts_expire_and_keep_the_newest() {
+    let mut toasts = Toasts::new(1.0, 2);
+    toasts.push("first");
+    toasts.update(0.5);
+    toasts.push("second");
+    toasts.push("third");
+    assert_eq!(toasts.messages(), vec!["second", "third"]);
+
+    toasts.update(0.6);
+    assert_eq!(toasts.messages(), vec!["second", "third"]);
+    toasts.update(0.5);
+    assert!(toasts.messages().is_empty());
+}
+
+#[test]
+fn key_hints_follow_the_input_map() {
+    let mut input_map = InputMap::default();
+    assert_eq!(binding_label(&input_map, Action::InsertSegment), "E");
+
+    input_map.bindings.insert(
+        Action::InsertSegment,
+        vec![Binding::GamepadButton(GamepadButtonType::South), Binding::Key(KeyCode::Q)],
+    );
+    assert_eq!(binding_label(&input_map, Action::InsertSegment), "Q");
+
+    input_map.bindings.remove(&Action::InsertSegment);
+    assert_eq!(binding_label(&input_map, Action::InsertSegment), "-");
+}
+
+#[test]
+fn road_length_and_distance() {
+    let mut road_network = RoadNetwork::default();
+    assert_eq!(road_network.distance_to(Vec3::ZERO), None);
+
+    road_network.add_node(Vec3::ZERO, Vec3::Y);
+    road_network.add_node(Vec3::new(0.0, 0.0, -10.0), Vec3::Y);
+    road_network.add_node(Vec3::new(10.0, 0.0, -10.0), Vec3::Y);
+    assert!((road_network.length() - 20.0).abs() < 1e-4);
+
+    // Beside the first segment, and past the end of the road
+    assert!((road_network.distance_to(Vec3::new(-3.0, 0.0, -5.0)).unwrap() - 3.0).abs() < 1e-4);
+    assert!((road_network.distance_to(Vec3::new(14.0, 0.0, -10.0)).unwrap() - 4.0).abs() < 1e-4);
+}
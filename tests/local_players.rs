@@ -0,0 +1,55 @@
+use bevy::prelude::*;
+use osd::{
+    drive_input::DriveInput,
+    harness::Harness,
+    input_map::InputDevices,
+    local_players::{split_screen_region, LocalPlayersConfig},
+    replay::Replay,
+    road_import::{road_network_from_points, UpMode},
+};
+
+fn setup() -> (Harness, Entity, Entity) {
+    let mut harness = Harness::with_players(2);
+    harness.load_road_network(road_network_from_points(&[Vec3::ZERO, Vec3::new(0.0, 0.0, -400.0)], UpMode::Level));
+    let first = harness.spawn_player_car(0, "car_0001", Transform::from_xyz(-3.0, 5.5, -10.0));
+    let second = harness.spawn_player_car(1, "car_0001", Transform::from_xyz(3.0, 5.5, -10.0));
+    return (harness, first, second);
+}
+
+#[test]
+fn each_player_drives_their_own_car() {
+    let (mut harness, first, second) = setup();
+    assert_eq!(harness.game().players.len(), 2);
+    assert_eq!(harness.game().player_car(), Some(first));
+
+    harness.set_player_drive_input(1, DriveInput { throttle: 1.0, ..Default::default() });
+    harness.step(120);
+    assert!(harness.transform(second).translation.z < -20.0, "second car didn't move");
+    assert!(harness.transform(first).translation.z > -12.0, "first car moved");
+
+    // Replays carry the input of both players
+    let recording = harness.app.world.resource::<Replay>().recording.clone();
+    let recording = serde_json::from_str(&serde_json::to_string(&recording).unwrap()).unwrap();
+    let (mut replayed, replayed_first, replayed_second) = setup();
+    replayed.play(recording);
+    replayed.step(harness.tick() as usize);
+    assert_eq!(harness.transform(first), replayed.transform(replayed_first));
+    assert_eq!(harness.transform(second), replayed.transform(replayed_second));
+}
+
+#[test]
+fn players_get_their_devices_and_screen_regions() {
+    let single = LocalPlayersConfig::with_players(1);
+    assert_eq!(single.devices, vec![InputDevices::default()]);
+
+    let config = LocalPlayersConfig::with_players(3);
+    assert!(config.devices[0].keyboard);
+    assert_eq!(config.devices[1], InputDevices::gamepad(0));
+    assert_eq!(config.devices[2], InputDevices::gamepad(1));
+
+    // Two players side by side, three or four in a 2x2 grid
+    let window = UVec2::new(1280, 720);
+    assert_eq!(split_screen_region(1, 2, window), (UVec2::new(640, 0), UVec2::new(640, 720)));
+    assert_eq!(split_screen_region(2, 3, window), (UVec2::new(0, 360), UVec2::new(640, 360)));
+    assert_eq!(split_screen_region(3, 4, window), (UVec2::new(640, 360), UVec2::new(640, 360)));
+}
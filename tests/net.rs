@@ -0,0 +1,188 @@
+use bevy::prelude::*;
+use osd::{
+    drive_input::DriveInput,
+    harness::Harness,
+    net::{BodySnapshot, ClientMessage, NetServer, NetServerConfig, NetServerPlugin, ServerMessage, Snapshot, SnapshotBuffer},
+    road_import::{road_network_from_points, UpMode},
+    road_network_builder::RoadEdit,
+    websocket::{WebSocket, WebSocketState},
+};
+
+fn snapshot(tick: u64, x: f32) -> Snapshot {
+    return Snapshot {
+        tick,
+        car: Some(1),
+        bodies: vec![BodySnapshot {
+            id: 1,
+            vehicle: String::from("car_0001"),
+            translation: Vec3::new(x, 0.0, 0.0),
+            rotation: Quat::IDENTITY,
+        }],
+    };
+}
+
+#[test]
+fn snapshots_are_interpolated() {
+    let mut buffer = SnapshotBuffer::default();
+    buffer.push(snapshot(60, 0.0));
+    buffer.push(snapshot(66, 6.0));
+    // Late, dropped
+    buffer.push(snapshot(63, 100.0));
+    assert_eq!(buffer.snapshots.len(), 2);
+
+    let x_at = |time: f32| buffer.sample(time)[0].2.translation.x;
+    assert!((x_at(1.05) - 3.0).abs() < 0.01);
+    // Held at the ends, without extrapolating
+    assert_eq!(x_at(0.5), 0.0);
+    assert_eq!(x_at(2.0), 6.0);
+}
+
+/// Messages of the server, stepping it until `condition` holds for one of them.
+fn receive_until(harness: &mut Harness, client: &mut WebSocket, mut condition: impl FnMut(&ServerMessage) -> bool) -> ServerMessage {
+    for _ in 0..300 {
+        harness.step(1);
+        for message in client.receive() {
+            let message: ServerMessage = serde_json::from_str(&message).unwrap();
+            if condition(&message) {
+                return message;
+            }
+        }
+    }
+    panic!("no such message from the server");
+}
+
+fn send(client: &mut WebSocket, message: ClientMessage) {
+    client.send(&serde_json::to_string(&message).unwrap());
+}
+
+#[test]
+fn server_drives_client_cars_and_replicates_road_edits() {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&[Vec3::ZERO, Vec3::new(0.0, 0.0, -400.0)], UpMode::Level));
+    harness.app.add_plugin(NetServerPlugin {
+        config: NetServerConfig {
+            address: String::from("127.0.0.1:0"),
+            ..default()
+        },
+    });
+    let address = harness.app.world.resource::<NetServer>().local_addr().unwrap();
+
+    let mut client = WebSocket::connect(&format!("ws://{}", address)).unwrap();
+    send(&mut client, ClientMessage::Hello { vehicle: String::from("car_0001") });
+    let welcome = receive_until(&mut harness, &mut client, |message| matches!(message, ServerMessage::Welcome { .. }));
+    assert_eq!(client.state(), WebSocketState::Open);
+    let segments = match welcome {
+        ServerMessage::Welcome { road_network, .. } => road_network.road_segments.len(),
+        _ => 0,
+    };
+    assert_eq!(segments, harness.game().road_network.road_segments.len());
+
+    // The server spawns a car for the client, and tells it which one it is
+    let snapshot = receive_until(&mut harness, &mut client, |message| match message {
+        ServerMessage::Snapshot(snapshot) => snapshot.car.is_some(),
+        _ => false,
+    });
+    let car = harness.app.world.resource::<NetServer>().clients[0].car.unwrap();
+    match snapshot {
+        ServerMessage::Snapshot(snapshot) => {
+            assert_eq!(snapshot.car, Some(car.to_bits()));
+            assert!(snapshot.bodies.iter().any(|body| body.id == car.to_bits()));
+        },
+        _ => {}
+    }
+
+    let start = harness.transform(car).translation;
+    send(&mut client, ClientMessage::Input { drive: DriveInput { throttle: 1.0, ..Default::default() } });
+    harness.step(120);
+    assert!(harness.transform(car).translation.distance(start) > 10.0, "client car didn't move");
+
+    // Edits come back to every client, and change the road of the server
+    let edit = RoadEdit::AddNode { position: Vec3::new(0.0, 0.0, -500.0), up: Vec3::Y };
+    send(&mut client, ClientMessage::RoadEdit { edit: edit.clone() });
+    let echoed = receive_until(&mut harness, &mut client, |message| matches!(message, ServerMessage::RoadEdit { .. }));
+    match echoed {
+        ServerMessage::RoadEdit { edit: echoed } => assert_eq!(echoed, edit),
+        _ => {}
+    }
+    harness.step(1);
+    assert_eq!(harness.game().road_network.road_segments.len(), segments + 1);
+
+    // Leaving removes the car
+    client.close();
+    harness.step(2);
+    assert!(harness.app.world.resource::<NetServer>().clients.is_empty());
+    assert!(harness.app.world.get_entity(car).is_none());
+}
+
+/// Connects a client and steps the server until its car is spawned.
+fn join(harness: &mut Harness, address: std::net::SocketAddr) -> (WebSocket, Entity) {
+    let mut client = WebSocket::connect(&format!("ws://{}", address)).unwrap();
+    send(&mut client, ClientMessage::Hello { vehicle: String::from("car_0001") });
+    let snapshot = receive_until(harness, &mut client, |message| match message {
+        ServerMessage::Snapshot(snapshot) => snapshot.car.is_some(),
+        _ => false,
+    });
+    let car = match snapshot {
+        ServerMessage::Snapshot(snapshot) => Entity::from_bits(snapshot.car.unwrap()),
+        _ => unreachable!(),
+    };
+    return (client, car);
+}
+
+#[test]
+fn new_clients_start_in_a_free_place() {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&[Vec3::ZERO, Vec3::new(0.0, 0.0, -400.0)], UpMode::Level));
+    harness.app.add_plugin(NetServerPlugin {
+        config: NetServerConfig {
+            address: String::from("127.0.0.1:0"),
+            ..default()
+        },
+    });
+    let address = harness.app.world.resource::<NetServer>().local_addr().unwrap();
+
+    let (mut first, _) = join(&mut harness, address);
+    let (_second, second_car) = join(&mut harness, address);
+    first.close();
+    harness.step(2);
+
+    // The third client takes the place of the first, not the one of the second
+    let (_third, third_car) = join(&mut harness, address);
+    let slots: Vec<Option<usize>> = harness.app.world.resource::<NetServer>().clients.iter().map(|client| client.slot).collect();
+    assert_eq!(slots, vec![Some(1), Some(0)]);
+    let gap = harness.transform(second_car).translation.distance(harness.transform(third_car).translation);
+    assert!(gap > 4.0, "cars {} apart", gap);
+}
+
+#[test]
+fn client_input_and_edits_are_validated() {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&[Vec3::ZERO, Vec3::new(0.0, 0.0, -400.0)], UpMode::Level));
+    harness.app.add_plugin(NetServerPlugin {
+        config: NetServerConfig {
+            address: String::from("127.0.0.1:0"),
+            ..default()
+        },
+    });
+    let address = harness.app.world.resource::<NetServer>().local_addr().unwrap();
+    let (mut client, _) = join(&mut harness, address);
+    let drive = |harness: &Harness| harness.app.world.resource::<NetServer>().clients[0].drive;
+
+    // Out of range axes are clamped
+    send(&mut client, ClientMessage::Input { drive: DriveInput { throttle: 1e9, steer: -3.0, ..Default::default() } });
+    harness.step(2);
+    assert_eq!(drive(&harness), DriveInput { throttle: 1.0, steer: -1.0, ..Default::default() });
+
+    // Infinite ones are dropped, the last valid input stays
+    client.send(r#"{"Input":{"drive":{"throttle":1e39,"steer":0.0,"roll":0.0,"pitch":0.0}}}"#);
+    harness.step(2);
+    assert_eq!(drive(&harness).throttle, 1.0);
+
+    let segments = harness.game().road_network.road_segments.len();
+    let edit = ClientMessage::RoadEdit { edit: RoadEdit::AddNode { position: Vec3::new(12345.0, 0.0, 0.0), up: Vec3::Y } };
+    client.send(&serde_json::to_string(&edit).unwrap().replace("12345.0", "1e39"));
+    harness.step(2);
+    assert_eq!(harness.game().road_network.road_segments.len(), segments);
+    assert!(!RoadEdit::MoveNode { from: Vec3::ZERO, to: Vec3::NAN }.is_finite());
+    assert_eq!(DriveInput { pitch: f32::NAN, ..Default::default() }.validated(), None);
+}
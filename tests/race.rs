@@ -0,0 +1,115 @@
+use bevy::prelude::*;
+use osd::{
+    drive_input::DriveInput,
+    harness::{circle, Harness},
+    input_map::Action,
+    race::{format_delta, format_lap_time, BestLaps, Race},
+    road_import::{road_network_from_points, UpMode},
+    road_network_builder::Checkpoint,
+};
+
+#[test]
+fn lap_times_are_formatted() {
+    assert_eq!(format_lap_time(83.4567), "1:23.457");
+    assert_eq!(format_lap_time(9.05), "0:09.050");
+    assert_eq!(format_delta(-0.25), "-0.250");
+    assert_eq!(format_delta(1.5), "+1.500");
+}
+
+#[test]
+fn track_id_follows_the_road_and_checkpoints() {
+    let mut road_network = road_network_from_points(&circle(150.0, 64), UpMode::Level);
+    let track = road_network.track_id();
+    assert_eq!(road_network.clone().track_id(), track);
+
+    road_network.checkpoints.push(Checkpoint { distance: 10.0 });
+    assert_ne!(road_network.track_id(), track);
+    road_network.checkpoints.clear();
+    assert_eq!(road_network.track_id(), track);
+
+    road_network.road_segments[3].target_speed = Some(20.0);
+    assert_ne!(road_network.track_id(), track);
+}
+
+#[test]
+fn checkpoints_are_placed_where_the_car_is() {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&circle(150.0, 64), UpMode::Level));
+    let car = harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, 0.0));
+    harness.set_drive_input(DriveInput { throttle: 0.3, ..Default::default() });
+    harness.step(60 * 4);
+
+    let track = harness.game().road_network.track_id();
+    harness.tap(Action::PlaceCheckpoint);
+    assert_ne!(harness.game().road_network.track_id(), track);
+    let checkpoints = harness.game().road_network.checkpoints.clone();
+    assert_eq!(checkpoints.len(), 1);
+    let (distance, _) = harness.game().road_network.distance_along(harness.transform(car).translation).unwrap();
+    assert!((checkpoints[0].distance - distance).abs() < 5.0);
+
+    // Stored with the road network
+    let json = serde_json::to_string(&harness.game().road_network).unwrap();
+    assert!(json.contains("checkpoints"));
+
+    // Again on the same spot removes it
+    harness.set_drive_input(DriveInput::default());
+    harness.tap(Action::PlaceCheckpoint);
+    assert!(harness.game().road_network.checkpoints.is_empty());
+    assert_eq!(harness.game().road_network.track_id(), track);
+}
+
+#[test]
+fn laps_are_timed_through_the_checkpoints() {
+    let mut harness = Harness::new();
+    let mut road_network = road_network_from_points(&circle(150.0, 64), UpMode::Level);
+    let length = road_network.length();
+    road_network.checkpoints = vec![Checkpoint { distance: length / 3.0 }, Checkpoint { distance: length * 2.0 / 3.0 }];
+    harness.load_road_network(road_network);
+    harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, 0.0));
+    harness.step(30);
+
+    // Standing start on the line
+    harness.tap(Action::ToggleRaceMode);
+    assert!(harness.app.world.resource::<Race>().lap_start.is_some());
+    harness.set_drive_input(DriveInput { throttle: 0.3, ..Default::default() });
+
+    let laps = |harness: &Harness| harness.app.world.resource::<Race>().laps;
+    harness.step_while(60 * 120, |harness| laps(harness) < 2);
+    assert_eq!(laps(&harness), 2);
+
+    // The flying lap beats the standing start, and became the record
+    let race = harness.app.world.resource::<Race>();
+    let flying_lap = race.last_lap.unwrap();
+    let track = harness.game().road_network.track_id();
+    let record = harness.app.world.resource::<BestLaps>().tracks[&track].clone();
+    assert_eq!(record.time, flying_lap);
+    assert_eq!(record.splits.len(), 2);
+    assert!(record.splits[0] < record.splits[1] && record.splits[1] < flying_lap);
+    assert!(flying_lap > 20.0 && flying_lap < 60.0, "lap took {}", flying_lap);
+
+    // Stopping race mode resets the laps
+    harness.tap(Action::ToggleRaceMode);
+    assert!(!harness.app.world.resource::<Race>().active);
+    assert_eq!(laps(&harness), 0);
+}
+
+#[test]
+fn progress_picks_up_where_the_car_comes_back_on_track() {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&circle(150.0, 64), UpMode::Level));
+    let car = harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, 0.0));
+    harness.step(30);
+    harness.tap(Action::ToggleRaceMode);
+
+    let move_car = |harness: &mut Harness, translation: Vec3| {
+        *harness.app.world.get_mut::<Transform>(car).unwrap() = Transform::from_translation(translation);
+        harness.step(2);
+    };
+    // Off track, then back on across the circle
+    move_car(&mut harness, Vec3::new(150.0, 5.5, 400.0));
+    move_car(&mut harness, Vec3::new(300.0, 5.5, 0.0));
+
+    let (distance, _) = harness.game().road_network.distance_along(harness.transform(car).translation).unwrap();
+    let progress = harness.app.world.resource::<Race>().progress;
+    assert!((progress - distance).abs() < 5.0, "progress {} at {}", progress, distance);
+}
@@ -0,0 +1,62 @@
+use bevy::prelude::*;
+use osd::{
+    drive_input::DriveInput,
+    harness::Harness,
+    replay::{Replay, TickInput},
+    road_import::{road_network_from_points, UpMode},
+};
+
+fn setup() -> (Harness, Entity, Entity) {
+    let points: Vec<Vec3> = (0..=20).map(|i| Vec3::new((i as f32 * 0.3).sin() * 20.0, 0.0, -20.0 * i as f32)).collect();
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&points, UpMode::Level));
+    let car = harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, -10.0));
+    let trailer = harness.spawn_trailer("road_trailer_0001", Transform::from_xyz(0.0, 5.5, -2.0));
+    return (harness, car, trailer);
+}
+
+#[test]
+fn replay_reproduces_the_session() {
+    // Scripted session: hitch the trailer, accelerate, steer, brake
+    let (mut recorded, car, trailer) = setup();
+    recorded.step(10);
+    recorded.hitch();
+    recorded.set_drive_input(DriveInput { throttle: 0.8, ..Default::default() });
+    recorded.step(120);
+    recorded.set_drive_input(DriveInput { throttle: 0.5, steer: 0.6, roll: -0.2, ..Default::default() });
+    recorded.step(90);
+    recorded.set_drive_input(DriveInput { throttle: -1.0, ..Default::default() });
+    recorded.step(60);
+
+    assert!(recorded.transform(car).translation.z < -30.0, "car didn't move");
+
+    let recording = recorded.app.world.resource::<Replay>().recording.clone();
+    assert_eq!(recording.ticks.len() as u64, recorded.tick());
+
+    // Serialized like a saved replay
+    let recording = serde_json::from_str(&serde_json::to_string(&recording).unwrap()).unwrap();
+
+    let (mut replayed, replayed_car, replayed_trailer) = setup();
+    replayed.play(recording);
+    replayed.step(recorded.tick() as usize);
+
+    assert_eq!(replayed.road_train(), vec![replayed_trailer]);
+    assert_eq!(recorded.transform(car), replayed.transform(replayed_car));
+    assert_eq!(recorded.transform(trailer), replayed.transform(replayed_trailer));
+    assert_eq!(recorded.velocity(car), replayed.velocity(replayed_car));
+}
+
+#[test]
+fn live_sessions_are_only_recorded_on_request() {
+    let mut replay = Replay::default();
+    let input = TickInput {
+        drive: DriveInput { throttle: 1.0, ..Default::default() },
+        ..Default::default()
+    };
+    assert_eq!(replay.next_tick(input.clone()), input);
+    assert!(replay.recording.ticks.is_empty());
+
+    let mut replay = Replay::recording();
+    replay.next_tick(input.clone());
+    assert_eq!(replay.recording.ticks, vec![input]);
+}
@@ -0,0 +1,210 @@
+use bevy::prelude::*;
+use osd::{
+    editor::EditorConfig,
+    harness::Harness,
+    input_map::Action,
+    replay::Replay,
+    road_gizmo::{closest_on_axis, pick_handle, GizmoHandle, GIZMO_RING_RADIUS, GIZMO_SIZE},
+    road_network_builder::{RoadEdit, RoadNetwork},
+    road_profile::{bank_angle, banked_up, ProfileSection, RoadProfile},
+    road_systems::RoadNetworkEvent,
+};
+
+fn add_node(x: f32, z: f32) -> RoadEdit {
+    return RoadEdit::AddNode { position: Vec3::new(x, 0.0, z), up: Vec3::Y };
+}
+
+#[test]
+fn added_nodes_continue_the_road() {
+    let mut harness = Harness::new();
+    harness.load_road_network(RoadNetwork::default());
+    for edit in [add_node(0.0, 0.0), add_node(0.0, -50.0), add_node(20.0, -100.0)] {
+        harness.edit_road(edit);
+    }
+    harness.step(1);
+
+    // Same segments as driving the trailer to each node and inserting segments
+    let mut expected = RoadNetwork::default();
+    expected.add_node(Vec3::new(0.0, 0.0, 0.0), Vec3::Y);
+    expected.add_node(Vec3::new(0.0, 0.0, -50.0), Vec3::Y);
+    expected.add_node(Vec3::new(20.0, 0.0, -100.0), Vec3::Y);
+
+    let road_network = &harness.game().road_network;
+    assert_eq!(road_network.road_segments.len(), 2);
+    for (segment, expected_segment) in road_network.road_segments.iter().zip(&expected.road_segments) {
+        assert_eq!((segment.a, segment.b, segment.up), (expected_segment.a, expected_segment.b, expected_segment.up));
+    }
+    assert_eq!(road_network.last_position, Some(Vec3::new(20.0, 0.0, -100.0)));
+}
+
+#[test]
+fn moving_a_node_moves_both_segments() {
+    let mut harness = Harness::new();
+    harness.load_road_network(RoadNetwork::default());
+    for edit in [add_node(0.0, 0.0), add_node(0.0, -50.0), add_node(0.0, -100.0)] {
+        harness.edit_road(edit);
+    }
+    harness.step(1);
+
+    let from = harness.game().road_network.closest_node(Vec3::new(1.0, 0.0, -49.0), 4.0).unwrap();
+    assert_eq!(from, Vec3::new(0.0, 0.0, -50.0));
+    let to = Vec3::new(10.0, 3.0, -50.0);
+    harness.edit_road(RoadEdit::MoveNode { from, to });
+    harness.step(1);
+
+    let segments = &harness.game().road_network.road_segments;
+    assert_eq!(segments[0].b, to);
+    assert_eq!(segments[1].a, to);
+    assert_eq!(harness.game().road_network.nodes().len(), 3);
+}
+
+#[test]
+fn replays_reproduce_road_edits() {
+    let mut recorded = Harness::new();
+    recorded.load_road_network(RoadNetwork::default());
+    recorded.step(5);
+    recorded.edit_road(add_node(0.0, 0.0));
+    recorded.step(3);
+    recorded.edit_road(add_node(0.0, -50.0));
+    recorded.edit_road(RoadEdit::MoveNode { from: Vec3::ZERO, to: Vec3::new(5.0, 0.0, 0.0) });
+    recorded.step(2);
+
+    let recording = recorded.app.world.resource::<Replay>().recording.clone();
+    let recording = serde_json::from_str(&serde_json::to_string(&recording).unwrap()).unwrap();
+
+    let mut replayed = Harness::new();
+    replayed.load_road_network(RoadNetwork::default());
+    replayed.play(recording);
+    replayed.step(recorded.tick() as usize);
+
+    let recorded_json = serde_json::to_string(&recorded.game().road_network).unwrap();
+    let replayed_json = serde_json::to_string(&replayed.game().road_network).unwrap();
+    assert_eq!(recorded_json, replayed_json);
+    assert_eq!(replayed.game().road_network.road_segments[0].a, Vec3::new(5.0, 0.0, 0.0));
+}
+
+#[test]
+fn rotating_a_node_banks_and_turns_the_road() {
+    let mut harness = Harness::new();
+    harness.load_road_network(RoadNetwork::default());
+    for edit in [add_node(0.0, 0.0), add_node(0.0, -50.0), add_node(0.0, -100.0)] {
+        harness.edit_road(edit);
+    }
+    harness.step(1);
+
+    let node = Vec3::new(0.0, 0.0, -50.0);
+    let rotation = harness.game().road_network.node_rotation(node).unwrap();
+    assert!((rotation * Vec3::NEG_Z).distance(Vec3::NEG_Z) < 1e-4);
+    assert!((rotation * Vec3::Y).distance(Vec3::Y) < 1e-4);
+
+    // Bank by 20 degrees around the road, then turn left by 90 degrees
+    let bank = Quat::from_axis_angle(Vec3::NEG_Z, 20f32.to_radians());
+    harness.edit_road(RoadEdit::RotateNode { node, rotation: bank });
+    let heading = Quat::from_axis_angle(bank * Vec3::Y, 90f32.to_radians());
+    harness.edit_road(RoadEdit::RotateNode { node, rotation: heading });
+    harness.step(1);
+
+    let road_network = &harness.game().road_network;
+    let segments = &road_network.road_segments;
+    // The incoming segment keeps its bank, the outgoing one swings around the node
+    assert_eq!(segments[0].up, Vec3::Y);
+    assert!((segments[1].up.angle_between(Vec3::Y) - 20f32.to_radians()).abs() < 1e-4);
+    assert_eq!(segments[1].a, node);
+    assert!(((segments[1].b - node).length() - 50.0).abs() < 1e-3);
+    assert!((segments[1].b - node).normalize().dot(Vec3::NEG_X) > 0.9);
+    assert_eq!(road_network.last_position, Some(segments[1].b));
+
+    let rotation = road_network.node_rotation(node).unwrap();
+    assert!((rotation * Vec3::Y).distance(segments[1].up) < 1e-4);
+}
+
+#[test]
+fn gizmo_handles_are_picked_along_the_cursor_ray() {
+    let node = Vec3::new(10.0, 0.0, 10.0);
+    let down = Vec3::NEG_Y;
+    let above = |point: Vec3| point + Vec3::Y * 100.0;
+
+    // From above, the vertical arrow points at the camera and can't be picked
+    let x_arrow = node + Vec3::X * GIZMO_SIZE * 0.8;
+    assert_eq!(pick_handle(node, Some(Quat::IDENTITY), above(x_arrow), down), Some(GizmoHandle::TranslateX));
+    assert_eq!(pick_handle(node, Some(Quat::IDENTITY), above(node), down), None);
+
+    // The heading ring is seen as a circle, the bank ring as a line across the road
+    let ring = node + Vec3::new(-1.0, 0.0, -1.0).normalize() * GIZMO_RING_RADIUS;
+    assert_eq!(pick_handle(node, Some(Quat::IDENTITY), above(ring), down), Some(GizmoHandle::Heading));
+    assert_eq!(pick_handle(node, None, above(ring), down), None);
+
+    // Arrows follow the cursor along their axis
+    let along = closest_on_axis(node, Vec3::X, above(node + Vec3::new(3.0, 0.0, 2.0)), down).unwrap();
+    assert!((along - 3.0).abs() < 1e-4);
+    assert_eq!(closest_on_axis(node, Vec3::Y, above(node), down), None);
+}
+
+#[test]
+fn bank_angles_roll_like_a_vehicle() {
+    // Rolling right tilts the road up vector to the right
+    let up = banked_up(Vec3::NEG_Z, 30.0);
+    assert!(up.x > 0.4 && up.dot(Vec3::NEG_Z).abs() < 1e-5);
+    assert!((bank_angle(Vec3::NEG_Z, up) - 30.0).abs() < 1e-3);
+    assert!(bank_angle(Vec3::new(1.0, 0.3, 0.0), banked_up(Vec3::new(1.0, 0.3, 0.0), 0.0)).abs() < 1e-3);
+}
+
+#[test]
+fn profiles_bank_and_raise_the_road() {
+    let mut harness = Harness::new();
+    harness.load_road_network(RoadNetwork::default());
+    for index in 0..=20 {
+        harness.edit_road(add_node(0.0, index as f32 * -10.0));
+    }
+    harness.step(1);
+
+    let section = |from: f32, to: f32, value: f32| ProfileSection { from, to, value, ease: 20.0 };
+    let profile = RoadProfile {
+        bank: vec![section(50.0, 150.0, 30.0)],
+        elevation: vec![section(100.0, 200.0, 8.0)],
+    };
+    let json = serde_json::to_string(&profile).unwrap();
+    assert_eq!(RoadProfile::from_json(&json).unwrap(), profile);
+    harness.edit_road(RoadEdit::ApplyProfile { profile });
+    harness.step(1);
+
+    let road_network = &harness.game().road_network;
+    let segments = &road_network.road_segments;
+    let bank = |index: usize| bank_angle(segments[index].b - segments[index].a, segments[index].up);
+
+    // Untouched before the sections, eased in, full in the middle
+    assert_eq!(segments[2].up, Vec3::Y);
+    assert!(bank(5) == 0.0 && bank(6) > 0.0 && bank(6) < 30.0);
+    assert!((bank(8) - 30.0).abs() < 1e-3);
+    assert!((bank(10) - 30.0).abs() < 1e-3);
+    assert_eq!(segments[16].up, Vec3::Y);
+
+    assert_eq!(segments[9].a.y, 0.0);
+    assert!(segments[10].b.y > 0.0 && segments[10].b.y < 8.0);
+    assert!((segments[14].a.y - 8.0).abs() < 1e-4);
+    assert_eq!(segments[19].b.y, 0.0);
+
+    // Segments stay connected, and the road still continues from its end
+    for pair in segments.windows(2) {
+        assert_eq!(pair[0].b, pair[1].a);
+    }
+    assert_eq!(road_network.nodes().len(), 21);
+    assert_eq!(road_network.last_position, Some(segments[19].b));
+}
+
+#[test]
+fn applying_a_profile_reports_the_outcome() {
+    let apply = |path: &str| -> Vec<RoadNetworkEvent> {
+        let mut harness = Harness::new();
+        harness.app.world.resource_mut::<EditorConfig>().road_profile_path = Some(String::from(path));
+        harness.tap(Action::ApplyRoadProfile);
+        let events = harness.app.world.resource::<Events<RoadNetworkEvent>>();
+        return events.get_reader().iter(events).copied().collect();
+    };
+
+    assert_eq!(apply("no_such_profile.json"), vec![RoadNetworkEvent::ProfileFailed]);
+
+    let path = std::env::temp_dir().join("osd_road_profile.json");
+    std::fs::write(&path, serde_json::to_string(&RoadProfile::default()).unwrap()).unwrap();
+    assert_eq!(apply(path.to_str().unwrap()), vec![RoadNetworkEvent::ProfileApplied]);
+}
@@ -0,0 +1,207 @@
+use bevy::prelude::*;
+use osd::{
+    drive_input::DriveInput,
+    harness::{circle, Harness},
+    input_map::Action,
+    road_import::{road_network_from_points, UpMode},
+    trailer::{HitchKind, Hitches, TrailerStability, TRAILER_ATTACH_DISTANCE},
+};
+
+fn straight(length: f32) -> Vec<Vec3> {
+    return (0..=10).map(|i| Vec3::new(0.0, 0.0, -length * i as f32 / 10.0)).collect();
+}
+
+/// Straight, one full sine period, then straight again.
+fn s_curve(amplitude: f32, length: f32) -> Vec<Vec3> {
+    return (0..=60)
+        .map(|i| {
+            let z = -10.0 * i as f32;
+            let t = ((-z - 100.0) / length).clamp(0.0, 1.0);
+            Vec3::new(amplitude * (t * std::f32::consts::TAU).sin(), 0.0, z)
+        })
+        .collect();
+}
+
+#[test]
+fn car_hovers_at_rest_height() {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&straight(200.0), UpMode::Level));
+    let car = harness.spawn_car("car_0001", Transform::from_xyz(0.0, 3.0, -20.0));
+
+    harness.step(300);
+
+    let translation = harness.transform(car).translation;
+    assert!((translation.y - 5.5).abs() < 0.5, "car hovers at {}", translation.y);
+    assert!(harness.lateral_distance_to_road(translation) < 1.0);
+}
+
+#[test]
+fn car_stays_on_a_loop() {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&circle(150.0, 64), UpMode::Level));
+    let car = harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, 0.0));
+    harness.set_drive_input(DriveInput { throttle: 0.3, ..Default::default() });
+
+    // A lap takes a bit more than 40 seconds at this speed
+    let ticks = 60 * 50;
+    let on_road = harness.step_while(ticks, |harness| {
+        let translation = harness.transform(car).translation;
+        harness.lateral_distance_to_road(translation) < 5.0
+    });
+    assert_eq!(on_road, ticks, "car left the road after {} ticks", on_road);
+
+    // Went around instead of stalling
+    let speed = harness.velocity(car).linvel.length();
+    assert!(speed > 15.0, "car is going {}", speed);
+}
+
+#[test]
+fn trailer_hitches_and_follows() {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&straight(400.0), UpMode::Level));
+    let car = harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, -10.0));
+    let trailer = harness.spawn_trailer("road_trailer_0001", Transform::from_xyz(0.0, 5.5, -2.0));
+
+    harness.step(10);
+    harness.hitch();
+    assert_eq!(harness.road_train(), vec![trailer]);
+
+    harness.set_drive_input(DriveInput { throttle: 0.5, ..Default::default() });
+    harness.step(60 * 5);
+
+    let car_translation = harness.transform(car).translation;
+    let trailer_translation = harness.transform(trailer).translation;
+    assert!(car_translation.z < -50.0, "car only reached {}", car_translation);
+    assert!(car_translation.distance(trailer_translation) < 12.0);
+    assert!(harness.lateral_distance_to_road(trailer_translation) < 5.0);
+}
+
+#[test]
+fn road_train_hitches_and_unhitches() {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&straight(400.0), UpMode::Level));
+    let car = harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, -10.0));
+    let first = harness.spawn_trailer("road_trailer_0001", Transform::from_xyz(0.0, 5.5, -2.0));
+    let second = harness.spawn_trailer("road_trailer_0001", Transform::from_xyz(0.0, 5.5, 6.0));
+    harness.app.world.get_mut::<Hitches>(second).unwrap().kind = HitchKind::Ball;
+
+    harness.step(10);
+    harness.hitch();
+    harness.hitch();
+    assert_eq!(harness.road_train(), vec![first, second]);
+
+    harness.set_drive_input(DriveInput { throttle: 0.5, ..Default::default() });
+    harness.step(60 * 5);
+
+    let car_translation = harness.transform(car).translation;
+    let first_translation = harness.transform(first).translation;
+    let second_translation = harness.transform(second).translation;
+    assert!(car_translation.z < -50.0, "car only reached {}", car_translation);
+    assert!(first_translation.distance(second_translation) < 12.0);
+    assert!(harness.lateral_distance_to_road(second_translation) < 5.0);
+
+    assert_eq!(harness.road_train(), vec![first, second]);
+
+    // Unhitches the last trailer first
+    harness.tap(Action::ToggleTrailer);
+    assert_eq!(harness.road_train(), vec![first]);
+}
+
+#[test]
+fn unhitches_with_a_free_trailer_in_range() {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&straight(400.0), UpMode::Level));
+    harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, -10.0));
+    let first = harness.spawn_trailer("road_trailer_0001", Transform::from_xyz(0.0, 5.5, -2.0));
+    harness.step(10);
+    harness.hitch();
+    assert_eq!(harness.road_train(), vec![first]);
+
+    // A free trailer right behind the train, close enough to couple
+    let hitch = |harness: &Harness, trailer: Entity, rear: bool| {
+        let hitches = harness.app.world.get::<Hitches>(trailer).unwrap();
+        let local = if rear { hitches.rear } else { hitches.front };
+        return harness.transform(trailer).mul_vec3(local.unwrap());
+    };
+    let rear_hitch = hitch(&harness, first, true);
+    let free = harness.spawn_trailer("road_trailer_0001", Transform::from_translation(rear_hitch + Vec3::new(0.0, 0.0, 8.0)));
+    harness.step(10);
+    assert!(hitch(&harness, free, false).distance(hitch(&harness, first, true)) <= TRAILER_ATTACH_DISTANCE);
+
+    harness.tap(Action::UnhitchTrailer);
+    assert!(harness.road_train().is_empty());
+}
+
+#[test]
+fn trailer_couples_from_an_angle() {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&straight(400.0), UpMode::Level));
+    harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, -10.0));
+    let trailer = harness.spawn_trailer(
+        "road_trailer_0001",
+        Transform::from_xyz(3.0, 5.5, 0.0).with_rotation(Quat::from_rotation_y(0.5)),
+    );
+
+    harness.step(10);
+    let ticks = harness.hitch();
+    assert_eq!(harness.road_train(), vec![trailer]);
+    assert!(ticks < 120, "coupling took {} ticks", ticks);
+}
+
+#[test]
+fn hitch_breaks_when_pulled_too_hard() {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&straight(400.0), UpMode::Level));
+    let car = harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, -10.0));
+    let trailer = harness.spawn_trailer("road_trailer_0001", Transform::from_xyz(0.0, 5.5, -2.0));
+
+    harness.step(10);
+    harness.hitch();
+    assert_eq!(harness.road_train(), vec![trailer]);
+
+    // Holds while idling
+    harness.app.world.get_mut::<Hitches>(trailer).unwrap().break_impulse = Some(0.5);
+    harness.step(60);
+    assert_eq!(harness.road_train(), vec![trailer]);
+
+    harness.set_drive_input(DriveInput { throttle: 1.0, ..Default::default() });
+    harness.step(60 * 3);
+    assert!(harness.road_train().is_empty(), "hitch held");
+
+    let distance = harness.transform(car).translation.distance(harness.transform(trailer).translation);
+    assert!(distance > 20.0, "trailer is {} from the car", distance);
+}
+
+/// Drives a car and its trailer through an S-curve.
+/// Returns how much the trailer swung relative to the car, in radians.
+fn trailer_swing_on_s_curve(stability: bool) -> f32 {
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&s_curve(15.0, 300.0), UpMode::Level));
+    let car = harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, -10.0));
+    let trailer = harness.spawn_trailer("road_trailer_0001", Transform::from_xyz(0.0, 5.5, -2.0));
+    if !stability {
+        harness.app.world.entity_mut(trailer).remove::<TrailerStability>();
+    }
+
+    harness.step(10);
+    harness.hitch();
+    harness.set_drive_input(DriveInput { throttle: 0.6, ..Default::default() });
+
+    let mut swing = 0.0;
+    for _ in 0..60 * 25 {
+        harness.step(1);
+        let relative_yaw_rate = harness.velocity(trailer).angvel.y - harness.velocity(car).angvel.y;
+        swing += relative_yaw_rate.abs() / 60.0;
+    }
+
+    assert_eq!(harness.road_train(), vec![trailer]);
+    assert!(harness.transform(car).translation.z < -250.0, "car didn't go through the curve");
+    return swing;
+}
+
+#[test]
+fn trailer_stability_reduces_swing() {
+    let without = trailer_swing_on_s_curve(false);
+    let with = trailer_swing_on_s_curve(true);
+    assert!(with < without * 0.8, "swing went from {} to {}", without, with);
+}
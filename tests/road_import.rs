@@ -0,0 +1,79 @@
+use bevy::prelude::*;
+use osd::road_import::{
+    import_csv, import_gpx, import_json, import_svg_path, parse_csv_points, parse_gpx, parse_json_points, parse_svg_path,
+    HeightProfile, UpMode,
+};
+
+#[test]
+fn csv_points_skip_the_header_and_comments() {
+    let csv = "x,y,z\n# start\n0,0,0\n\n10; 1; -5\n20\t2\t-10\n";
+    let points = parse_csv_points(csv).unwrap();
+    assert_eq!(points, vec![Vec3::ZERO, Vec3::new(10.0, 1.0, -5.0), Vec3::new(20.0, 2.0, -10.0)]);
+    assert_eq!(import_csv(csv, UpMode::Level).unwrap().road_segments.len(), 2);
+
+    assert_eq!(parse_csv_points("0,0,0\n1,2\n").unwrap_err(), "line 2: expected 3 values, found 2");
+    assert!(parse_csv_points("0,0,0\n1,two,3\n").unwrap_err().starts_with("line 2:"));
+}
+
+#[test]
+fn json_points_are_arrays_or_objects() {
+    let json = r#"[[0, 0, 0], {"x": 10, "y": 1, "z": -5}, [20.5, 2, -10]]"#;
+    let points = parse_json_points(json).unwrap();
+    assert_eq!(points, vec![Vec3::ZERO, Vec3::new(10.0, 1.0, -5.0), Vec3::new(20.5, 2.0, -10.0)]);
+    assert_eq!(import_json(json, UpMode::Level).unwrap().road_segments.len(), 2);
+
+    assert!(parse_json_points(r#"{"x": 1}"#).is_err());
+    assert!(parse_json_points("[[0, 0, 0], [1, 2]]").unwrap_err().starts_with("point 1:"));
+    assert!(parse_json_points("[[0, 0,").is_err());
+}
+
+#[test]
+fn svg_paths_are_sampled_and_lifted() {
+    let points = parse_svg_path("M 0 0 L 100 0 l 0 50 H 0 Z", 4).unwrap();
+    assert_eq!(points.first(), Some(&Vec2::ZERO));
+    assert!(points.contains(&Vec2::new(100.0, 50.0)));
+    assert_eq!(points.last(), Some(&Vec2::ZERO));
+
+    // Curves are sampled, the end point lands exactly
+    let curve = parse_svg_path("M0,0 C 0,50 50,50 50,0", 8).unwrap();
+    assert_eq!(curve.len(), 9);
+    assert!(curve.last().unwrap().distance(Vec2::new(50.0, 0.0)) < 1e-4);
+
+    // SVG y is world z, heights follow the profile
+    let profile = HeightProfile { keys: vec![(0.0, 0.0), (10.0, 5.0)] };
+    let road_network = import_svg_path("M 0 0 L 0 20", 0.5, &profile, UpMode::Level).unwrap();
+    let last = road_network.road_segments.last().unwrap().b;
+    assert_eq!(last, Vec3::new(0.0, 5.0, 10.0));
+
+    assert!(parse_svg_path("0 0 L 1 1", 4).is_err());
+    assert!(parse_svg_path("M 0 0 L 10", 4).is_err());
+    assert!(parse_svg_path("M 0 0 X 5 5", 4).is_err());
+}
+
+#[test]
+fn gpx_tracks_are_projected_around_the_first_point() {
+    let gpx = r#"<?xml version="1.0"?>
+        <gpx><trk><trkseg>
+            <trkpt lat="45.0" lon="6.0"><ele>1000</ele></trkpt>
+            <trkpt lat="45.001" lon="6.0"><ele>1010</ele></trkpt>
+            <trkpt lat='45.001' lon='6.001'/>
+        </trkseg></trk></gpx>"#;
+    let points = parse_gpx(gpx).unwrap();
+    assert_eq!(points.len(), 3);
+    assert_eq!(points[0], Vec3::ZERO);
+    // North is -z, about 111 m per thousandth of a degree of latitude
+    assert!((points[1].z + 111.2).abs() < 0.5 && points[1].x.abs() < 1e-3);
+    assert_eq!(points[1].y, 10.0);
+    // East is +x, shorter away from the equator
+    assert!((points[2].x - 78.6).abs() < 0.5);
+    assert_eq!(points[2].y, -1000.0);
+
+    // Points closer than the spacing are dropped, except the last one
+    assert_eq!(import_gpx(gpx, 100.0, UpMode::Level).unwrap().road_segments.len(), 2);
+    assert_eq!(import_gpx(gpx, 500.0, UpMode::Level).unwrap().road_segments.len(), 1);
+
+    assert_eq!(parse_gpx("<gpx></gpx>").unwrap_err(), "no track points found");
+    assert!(parse_gpx(r#"<trkpt lon="6.0"/>"#).is_err());
+    assert!(parse_gpx(r#"<trkpt lat="45.0" lon="6.0"><ele>high</ele></trkpt>"#).is_err());
+    assert!(parse_gpx(r#"<trkpt lat="45.0" lon="6.0""#).is_err());
+}
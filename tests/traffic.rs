@@ -0,0 +1,80 @@
+use bevy::prelude::*;
+use osd::{
+    harness::Harness,
+    road_import::{road_network_from_points, UpMode},
+    road_network_builder::{Lane, RoadPath},
+    traffic::{idm_acceleration, Traffic, TrafficConfig, TrafficVehicle},
+};
+
+#[test]
+fn cars_follow_the_car_ahead() {
+    let config = TrafficConfig::default();
+
+    // Free road: speed up to the desired speed, and no further
+    assert!(idm_acceleration(&config, 0.0, 20.0, None) > 0.0);
+    assert!(idm_acceleration(&config, 20.0, 20.0, None).abs() < 1e-4);
+
+    // Closing in on a stopped car brakes hard, a far one barely matters
+    assert!(idm_acceleration(&config, 20.0, 20.0, Some((10.0, 0.0))) < -config.comfortable_deceleration);
+    let far = idm_acceleration(&config, 10.0, 20.0, Some((500.0, 10.0)));
+    assert!((far - idm_acceleration(&config, 10.0, 20.0, None)).abs() < 0.05);
+}
+
+#[test]
+fn traffic_drives_both_ways_and_reuses_cars() {
+    let mut harness = Harness::new();
+    let mut road_network = road_network_from_points(&[Vec3::ZERO, Vec3::new(0.0, 0.0, -300.0)], UpMode::Level);
+    road_network.lanes = vec![Lane { offset: 2.5, reverse: false }, Lane { offset: -2.5, reverse: true }];
+    harness.load_road_network(road_network);
+    harness.app.insert_resource(TrafficConfig {
+        max_vehicles: 12,
+        spawn_interval: 2.0,
+        ..default()
+    });
+    harness.step(60 * 40);
+
+    let config = harness.app.world.resource::<TrafficConfig>().clone();
+    let traffic = harness.app.world.resource::<Traffic>();
+    let cars = traffic.cars.clone();
+    let (pool, entered) = (traffic.pool.len(), traffic.entered);
+    assert!(cars.iter().any(|car| car.lane == 0) && cars.iter().any(|car| car.lane == 1));
+    assert!(cars.len() <= 12);
+
+    // Cars left at the far end and came back from the pool
+    let mut traffic_cars = harness.app.world.query_filtered::<Entity, With<TrafficVehicle>>();
+    let entities = traffic_cars.iter(&harness.app.world).count();
+    assert_eq!(entities, cars.len() + pool);
+    assert!((entered as usize) > entities);
+
+    for car in cars.iter() {
+        // Keeping their lane, facing the way it goes
+        let transform = harness.transform(car.entity);
+        let (offset, heading) = if car.lane == 0 { (2.5, -1.0) } else { (-2.5, 1.0) };
+        assert!((transform.translation.x - offset).abs() < 0.01);
+        assert!((transform.forward().z - heading).abs() < 0.01);
+
+        // And their distance to the car ahead
+        let ahead = cars
+            .iter()
+            .filter(|other| other.lane == car.lane && other.progress > car.progress)
+            .map(|other| other.progress - car.progress)
+            .fold(f32::MAX, f32::min);
+        assert!(ahead > config.min_gap, "{} to the car ahead", ahead);
+    }
+}
+
+#[test]
+fn road_path_lookups_match_the_road_network() {
+    let mut road_network = road_network_from_points(&[Vec3::ZERO, Vec3::new(0.0, 0.0, -100.0), Vec3::new(50.0, 0.0, -200.0)], UpMode::Level);
+    road_network.road_segments[1].target_speed = Some(12.0);
+    let path = RoadPath::new(&road_network);
+    assert!((path.length - road_network.length()).abs() < 1e-3);
+
+    for distance in [0.0, 30.0, 99.0, 101.0, 150.0, path.length] {
+        assert_eq!(path.target_speed_at(distance), road_network.target_speed_at(distance));
+        let (point, _, _) = path.point_at(distance).unwrap();
+        assert!(point.distance(road_network.point_at(distance).unwrap().0) < 1e-3);
+        let (along, off) = path.distance_along(point + Vec3::Y).unwrap();
+        assert!((along - distance).abs() < 1e-2 && (off - 1.0).abs() < 1e-3);
+    }
+}
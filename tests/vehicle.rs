@@ -0,0 +1,37 @@
+use bevy::prelude::*;
+use osd::{
+    harness::Harness,
+    input_map::Action,
+    road_import::{road_network_from_points, UpMode},
+    trailer::Coupling,
+    vehicle::{load_vehicle_manifests, VehicleRoster},
+};
+
+#[test]
+fn missing_manifests_are_skipped() {
+    let mut roster = VehicleRoster::default();
+    load_vehicle_manifests(&[String::from("car_0001"), String::from("no_such_car")], &mut roster);
+    assert!(roster.manifests.contains_key("car_0001"));
+    assert_eq!(roster.manifests.len(), 1);
+}
+
+#[test]
+fn switching_vehicles_keeps_coupling_the_trailer() {
+    let points: Vec<Vec3> = (0..=10).map(|i| Vec3::new(0.0, 0.0, -20.0 * i as f32)).collect();
+    let mut harness = Harness::new();
+    harness.load_road_network(road_network_from_points(&points, UpMode::Level));
+    let car = harness.spawn_car("car_0001", Transform::from_xyz(0.0, 5.5, -10.0));
+    let trailer = harness.spawn_trailer("road_trailer_0001", Transform::from_xyz(0.0, 5.5, 3.0));
+    harness.step(10);
+
+    harness.tap(Action::ToggleTrailer);
+    harness.tap(Action::SwitchVehicle);
+    let new_car = harness.game().player_car().unwrap();
+    assert_ne!(new_car, car);
+    let coupling = *harness.app.world.get::<Coupling>(trailer).expect("coupling was cancelled");
+    assert_eq!(coupling.tow, new_car);
+    assert_eq!(coupling.tow_rear_hitch, Vec3::new(0.0, 0.0, 3.8));
+
+    harness.step_while(600, |harness| harness.app.world.get::<Coupling>(trailer).is_some());
+    assert_eq!(harness.road_train(), vec![trailer]);
+}
@@ -0,0 +1,96 @@
+use std::{
+    io::Write,
+    net::{TcpListener, TcpStream},
+    thread::sleep,
+    time::Duration,
+};
+
+use osd::websocket::{accept_key, WebSocket, WebSocketState};
+
+const HANDSHAKE: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+
+/// A server side socket, and the raw TCP stream of its client.
+fn connection() -> (WebSocket, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    return (WebSocket::accept(stream).unwrap(), client);
+}
+
+/// Receives until the state changes from `state`, or gives up.
+fn receive_while(server: &mut WebSocket, state: WebSocketState) -> Vec<String> {
+    let mut messages = Vec::new();
+    for _ in 0..100 {
+        messages.extend(server.receive());
+        if server.state() != state {
+            break;
+        }
+        sleep(Duration::from_millis(5));
+    }
+    return messages;
+}
+
+/// A server side socket past the handshake.
+fn open_connection() -> (WebSocket, TcpStream) {
+    let (mut server, mut client) = connection();
+    client.write_all(HANDSHAKE).unwrap();
+    receive_while(&mut server, WebSocketState::Handshaking);
+    assert_eq!(server.state(), WebSocketState::Open);
+    return (server, client);
+}
+
+/// A masked client frame, with a zero mask.
+fn frame(first_byte: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![first_byte, 0x80 | payload.len() as u8, 0, 0, 0, 0];
+    frame.extend_from_slice(payload);
+    return frame;
+}
+
+#[test]
+fn websocket_accept_key_matches_rfc() {
+    // The example of RFC 6455
+    assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+}
+
+#[test]
+fn malformed_or_oversized_input_closes_the_socket() {
+    // Well-formed fragments make a message
+    let (mut server, mut client) = open_connection();
+    client.write_all(&frame(0x01, b"hel")).unwrap();
+    client.write_all(&frame(0x80, b"lo")).unwrap();
+    let mut messages = Vec::new();
+    for _ in 0..100 {
+        messages.extend(server.receive());
+        if !messages.is_empty() {
+            break;
+        }
+        sleep(Duration::from_millis(5));
+    }
+    assert_eq!(messages, vec![String::from("hello")]);
+
+    // A handshake that never ends
+    let (mut server, mut client) = connection();
+    client.write_all(&[b'a'; 16 * 1024]).unwrap();
+    receive_while(&mut server, WebSocketState::Handshaking);
+    assert_eq!(server.state(), WebSocketState::Closed);
+
+    // A fragment, then a frame whose length would overflow
+    let (mut server, mut client) = open_connection();
+    client.write_all(&frame(0x01, b"a")).unwrap();
+    client.write_all(&[0x80, 0x80 | 127, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]).unwrap();
+    receive_while(&mut server, WebSocketState::Open);
+    assert_eq!(server.state(), WebSocketState::Closed);
+
+    // A frame larger than a message may be
+    let (mut server, mut client) = open_connection();
+    client.write_all(&[0x81, 0x80 | 127, 0, 0, 0, 0, 0x10, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+    receive_while(&mut server, WebSocketState::Open);
+    assert_eq!(server.state(), WebSocketState::Closed);
+
+    // Clients must mask their frames
+    let (mut server, mut client) = open_connection();
+    client.write_all(&[0x81, 2, b'h', b'i']).unwrap();
+    receive_while(&mut server, WebSocketState::Open);
+    assert_eq!(server.state(), WebSocketState::Closed);
+}